@@ -0,0 +1,106 @@
+// Streams a synthetic stereo animation or an image to the network loader of VRScreenCap
+// usage: cargo run --example net_sender -- --address=127.0.0.1:9930 --codec=jpeg --stereo-mode=full-sbs
+use std::{
+    io::{BufWriter, Write},
+    net::TcpStream,
+};
+
+use clap::{Parser, ValueEnum};
+use image::{codecs::jpeg::JpegEncoder, ColorType, RgbaImage};
+use vr_screen_cap_core::{
+    protocol::net::{write_frame, NetCodec, NetFrameHeader, DEFAULT_NET_PORT},
+    StereoMode,
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Codec {
+    Raw,
+    Jpeg,
+}
+
+#[derive(Parser)]
+struct Args {
+    #[clap(long, default_value_t = format!("127.0.0.1:{DEFAULT_NET_PORT}"))]
+    address: String,
+    // Image to send instead of the animation
+    #[clap(long)]
+    image: Option<String>,
+    #[clap(long, default_value_t = 1920)]
+    width: u32,
+    #[clap(long, default_value_t = 1080)]
+    height: u32,
+    #[clap(long, value_enum, default_value_t = StereoMode::FullSbs)]
+    stereo_mode: StereoMode,
+    #[clap(long, value_enum, default_value_t = Codec::Jpeg)]
+    codec: Codec,
+    #[clap(long, default_value_t = 85)]
+    quality: u8,
+    #[clap(long, default_value_t = 30)]
+    fps: u64,
+}
+
+fn render_frame(args: &Args, frame_counter: u64) -> RgbaImage {
+    let eye_width = (args.width / 2).max(1);
+    let bar = (frame_counter * 8 % eye_width as u64) as u32;
+    // Left half tinted red and right half tinted blue, with a moving white bar
+    RgbaImage::from_fn(args.width, args.height, |x, y| {
+        let shade = ((x ^ y) & 0xFF) as u8;
+        if (bar..bar + 16).contains(&(x % eye_width)) {
+            image::Rgba([255, 255, 255, 255])
+        } else if x >= eye_width {
+            image::Rgba([shade / 4, shade / 4, shade, 255])
+        } else {
+            image::Rgba([shade, shade / 4, shade / 4, 255])
+        }
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let still_image = match &args.image {
+        Some(path) => Some(image::open(path)?.to_rgba8()),
+        None => None,
+    };
+
+    let stream = TcpStream::connect(&args.address)?;
+    stream.set_nodelay(true)?;
+    let mut writer = BufWriter::new(stream);
+    let frame_time = std::time::Duration::from_micros(1_000_000 / args.fps.max(1));
+    println!("Sending frames to {}, press Ctrl+C to stop", args.address);
+
+    let mut frame_counter = 0;
+    loop {
+        let frame = match &still_image {
+            Some(image) => image.clone(),
+            None => render_frame(&args, frame_counter),
+        };
+        let (codec, payload) = match args.codec {
+            Codec::Raw => (NetCodec::RawRgba, frame.as_raw().clone()),
+            Codec::Jpeg => {
+                // JPEG has no alpha channel
+                let rgb = image::DynamicImage::ImageRgba8(frame.clone()).to_rgb8();
+                let mut payload = vec![];
+                JpegEncoder::new_with_quality(&mut payload, args.quality).encode(
+                    rgb.as_raw(),
+                    rgb.width(),
+                    rgb.height(),
+                    ColorType::Rgb8,
+                )?;
+                (NetCodec::Jpeg, payload)
+            }
+        };
+
+        let header = NetFrameHeader {
+            width: frame.width(),
+            height: frame.height(),
+            stereo_mode: args.stereo_mode as u32,
+            codec,
+            payload_size: payload.len() as u32,
+        };
+        write_frame(&mut writer, &header, &payload)?;
+        writer.flush()?;
+
+        frame_counter += 1;
+        std::thread::sleep(frame_time);
+    }
+}
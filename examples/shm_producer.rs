@@ -0,0 +1,67 @@
+// Writes a synthetic stereo animation to the shared memory segment read by VRScreenCap on Linux
+// usage: cargo run --example shm_producer -- --width=1920 --height=1080 --stereo-mode=full-sbs
+#[cfg(target_os = "linux")]
+fn main() -> anyhow::Result<()> {
+    use clap::Parser;
+    use vr_screen_cap_core::{
+        protocol::shm::{
+            SharedMemory, ShmFrameHeader, ShmPixelFormat, DEFAULT_SHM_NAME, SHM_HEADER_SIZE,
+        },
+        StereoMode,
+    };
+
+    #[derive(Parser)]
+    struct Args {
+        #[clap(long, default_value = DEFAULT_SHM_NAME)]
+        name: String,
+        #[clap(long, default_value_t = 1920)]
+        width: u32,
+        #[clap(long, default_value_t = 1080)]
+        height: u32,
+        #[clap(long, value_enum, default_value_t = StereoMode::FullSbs)]
+        stereo_mode: StereoMode,
+        #[clap(long, default_value_t = 60)]
+        fps: u64,
+    }
+
+    let args = Args::parse();
+    let mut header = ShmFrameHeader::new(
+        args.width,
+        args.height,
+        ShmPixelFormat::Rgba8Unorm,
+        args.stereo_mode as u32,
+    );
+    let mut memory = SharedMemory::create(&args.name, SHM_HEADER_SIZE + header.frame_size())?;
+    let frame_time = std::time::Duration::from_micros(1_000_000 / args.fps.max(1));
+    println!("Writing frames to {}, press Ctrl+C to stop", args.name);
+
+    loop {
+        let eye_width = args.width / 2;
+        let bar = (header.frame_counter * 8 % eye_width.max(1) as u64) as u32;
+        let pixels = memory.pixels_mut(&header)?;
+        for (y, row) in pixels.chunks_exact_mut(header.stride as usize).enumerate() {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let (x, y) = (x as u32, y as u32);
+                // Left half tinted red and right half tinted blue, with a moving white bar
+                let shade = ((x ^ y) & 0xFF) as u8;
+                let color = if (bar..bar + 16).contains(&(x % eye_width.max(1))) {
+                    [255, 255, 255, 255]
+                } else if x >= eye_width {
+                    [shade / 4, shade / 4, shade, 255]
+                } else {
+                    [shade, shade / 4, shade / 4, 255]
+                };
+                pixel.copy_from_slice(&color);
+            }
+        }
+
+        header.frame_counter += 1;
+        memory.write_header(&header);
+        std::thread::sleep(frame_time);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("The shared memory producer is only available on Linux");
+}
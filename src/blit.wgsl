@@ -40,19 +40,40 @@ var t_history: texture_2d<f32>;
 var s_history: sampler;
 @group(2) @binding(0)
 var<uniform> blur_params: TemporalBlurParams;
+// Planar sources bind their planes in place of t_diffuse, sampled through s_diffuse
+@group(0) @binding(2)
+var t_luma: texture_2d<f32>;
+@group(0) @binding(3)
+var t_chroma: texture_2d<f32>;
+@group(0) @binding(4)
+var<uniform> yuv_params: YuvParams;
 
 struct TemporalOutput {
     @location(0) color: vec4<f32>,
     @location(1) history: vec4<f32>,
 }
-@fragment
-fn temporal_fs_main(in: VertexOutput) -> TemporalOutput {
+fn temporal_blend(current_color: vec4<f32>, uv: vec2<f32>) -> TemporalOutput {
     var out: TemporalOutput;
-    let current_color = textureSample(t_diffuse, s_diffuse, in.tex_coords + blur_params.jitter * blur_params.scale);
-    let history_color = textureSample(t_history, s_history, in.tex_coords);
+    let history_color = textureSample(t_history, s_history, uv);
     let mixed_color = mix(current_color, history_color, blur_params.history_decay).rgb;
     let brightness = smoothstep(0.05, 0.35, dot(mixed_color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722)));
     out.color = vec4<f32>(mixed_color * brightness, 1.0);
     out.history = vec4<f32>(mixed_color.rgb, 1.0);
     return out;
+}
+
+@fragment
+fn temporal_fs_main(in: VertexOutput) -> TemporalOutput {
+    let current_color = textureSample(t_diffuse, s_diffuse, in.tex_coords + blur_params.jitter * blur_params.scale);
+    return temporal_blend(current_color, in.tex_coords);
+}
+
+// Converted the same way as the planar screen so the ambient light keeps the source colors
+@fragment
+fn yuv_temporal_fs_main(in: VertexOutput) -> TemporalOutput {
+    let uv = in.tex_coords + blur_params.jitter * blur_params.scale;
+    let luma = textureSample(t_luma, s_diffuse, uv).r;
+    let chroma = textureSample(t_chroma, s_diffuse, uv).rg;
+    let rgb = srgb_to_linear(yuv_to_rgb(vec3<f32>(luma, chroma), yuv_params));
+    return temporal_blend(vec4<f32>(rgb, 1.0), in.tex_coords);
 }
\ No newline at end of file
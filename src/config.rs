@@ -4,6 +4,8 @@ use clap::Parser;
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use serde::{Deserialize, Serialize};
 
+use crate::loaders::{test_pattern_loader::TestPattern, StereoMode};
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ScreenParamsUniform {
@@ -15,8 +17,52 @@ pub struct ScreenParamsUniform {
     aspect_ratio: f32,
     screen_width: u32,
     ambient_width: u32,
+    eye_uv_rects: [[f32; 4]; 2],
+    stereo_mode: u32,
+    anaglyph_mode: u32,
+    _padding: [u32; 2],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum AnaglyphMode {
+    Off,
+    RedCyan,
+    Dubois,
+}
+
+impl AnaglyphMode {
+    pub fn next(&self) -> AnaglyphMode {
+        match self {
+            AnaglyphMode::Off => AnaglyphMode::RedCyan,
+            AnaglyphMode::RedCyan => AnaglyphMode::Dubois,
+            AnaglyphMode::Dubois => AnaglyphMode::Off,
+        }
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum MirrorMode {
+    Left,
+    Right,
+    // Both eyes blended together
+    Both,
+    SideBySide,
+}
+
+impl MirrorMode {
+    pub fn next(&self) -> MirrorMode {
+        match self {
+            MirrorMode::Left => MirrorMode::Right,
+            MirrorMode::Right => MirrorMode::Both,
+            MirrorMode::Both => MirrorMode::SideBySide,
+            MirrorMode::SideBySide => MirrorMode::Left,
+        }
+    }
+}
+
+// Android has no local capture source, so it listens for network frames out of the box
+const ANDROID_NET_LISTEN: &str = "0.0.0.0:9930";
+
 #[derive(Parser, Serialize, Deserialize, Debug, Clone)]
 #[serde(default)]
 pub struct AppConfig {
@@ -44,14 +90,82 @@ pub struct AppConfig {
     // Wether ambient light should be used, default: false, usage: --ambient=true
     #[clap(short, long, value_parser, default_value_t = false)]
     pub ambient: bool,
+    // Combines both eyes into an anaglyph image for previews, default: off, usage: --anaglyph=dubois
+    #[clap(long, value_enum, default_value_t = AnaglyphMode::Off)]
+    pub anaglyph: AnaglyphMode,
+    // Treats odd frames of frame sequential sources as the left eye, default: false, usage: --invert-frame-phase=true
+    #[clap(long, value_parser, default_value_t = false)]
+    pub invert_frame_phase: bool,
     // Configuration file to watch for live changes, usage: --config-file=config.json
     #[clap(short, long, value_parser)]
     pub config_file: Option<String>,
+    // Image file to show when no other source is available, usage: --image-path=screenshot.png
+    #[clap(long, value_parser)]
+    pub image_path: Option<String>,
+    // Stereo layout of the image file, default: full-sbs, usage: --image-stereo-mode=full-sbs
+    #[clap(long, value_enum, default_value_t = StereoMode::FullSbs)]
+    pub image_stereo_mode: StereoMode,
+    // X11 window to capture by id or title, or root for the whole screen (Linux only, requires the x11 feature), usage: --x11-window=0x3a00007
+    #[clap(long, value_parser)]
+    pub x11_window: Option<String>,
+    // Stereo layout of the captured X11 window, default: full-sbs, usage: --x11-stereo-mode=full-sbs
+    #[clap(long, value_enum, default_value_t = StereoMode::FullSbs)]
+    pub x11_stereo_mode: StereoMode,
+    // V4L2 capture device to show, like a webcam or an HDMI grabber (Linux only, requires the v4l feature), usage: --v4l-device=/dev/video0
+    #[clap(long, value_parser)]
+    pub v4l_device: Option<String>,
+    // Stereo layout of the V4L2 capture device, default: full-sbs, usage: --v4l-stereo-mode=full-sbs
+    #[clap(long, value_enum, default_value_t = StereoMode::FullSbs)]
+    pub v4l_stereo_mode: StereoMode,
+    // Address to receive frames over TCP on, default on Android: 0.0.0.0:9930, usage: --net-listen=0.0.0.0:9930
+    #[clap(long, value_parser)]
+    #[cfg_attr(target_os = "android", clap(default_value = ANDROID_NET_LISTEN))]
+    pub net_listen: Option<String>,
+    // Video file to play when no other source is available (requires the video feature), usage: --video-path=movie.mkv
+    #[clap(long, value_parser)]
+    pub video_path: Option<String>,
+    // Stereo layout of the video file, default: full-sbs, usage: --video-stereo-mode=full-sbs
+    #[clap(long, value_enum, default_value_t = StereoMode::FullSbs)]
+    pub video_stereo_mode: StereoMode,
+    // Name of the shared memory segment to read frames from (Linux only), usage: --shm-name=/vr-screen-cap
+    #[clap(long, value_parser, default_value = "/vr-screen-cap")]
+    pub shm_name: String,
+    // Unix socket receiving DMA-BUF file descriptors (Linux only), usage: --dmabuf-socket=/tmp/vr-screen-cap.sock
+    #[clap(long, value_parser)]
+    pub dmabuf_socket: Option<String>,
+    // PipeWire video node to capture, e.g. a screencast stream (Linux only, requires the pipewire feature), usage: --pipewire-node=42
+    #[clap(long, value_parser)]
+    pub pipewire_node: Option<u32>,
+    // Stereo layout of the PipeWire stream, default: full-sbs, usage: --pipewire-stereo-mode=full-sbs
+    #[clap(long, value_enum, default_value_t = StereoMode::FullSbs)]
+    pub pipewire_stereo_mode: StereoMode,
+    // Calibration pattern to show when no other source is available, usage: --test-pattern=eye-markers
+    #[clap(long, value_enum)]
+    pub test_pattern: Option<TestPattern>,
+    // Stereo layout of the test pattern, default: full-sbs, usage: --test-pattern-stereo-mode=full-sbs
+    #[clap(long, value_enum, default_value_t = StereoMode::FullSbs)]
+    pub test_pattern_stereo_mode: StereoMode,
+    // Comma separated loaders to try first (katanga, shm, dmabuf, pipewire, x11, v4l, network, video, image, test-pattern), usage: --loader-priority=pipewire,image
+    #[clap(long, value_parser, value_delimiter = ',')]
+    pub loader_priority: Vec<String>,
+    // Seconds between slideshow images when image-path is a folder (0 = manual), usage: --slideshow-interval=10
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub slideshow_interval: u64,
+    // Renders without an OpenXR runtime (e.g. on lavapipe) and saves both eyes to a PNG file, usage: --headless-output=eyes.png
+    #[clap(long, value_parser)]
+    pub headless_output: Option<String>,
+    // Opens a desktop window mirroring the headset, M cycles the modes (not on Android), usage: --mirror=side-by-side
+    #[clap(long, value_enum)]
+    pub mirror: Option<MirrorMode>,
+    // Headset frames between two mirror window updates, default: 2, usage: --mirror-interval=2
+    #[clap(long, value_parser, default_value_t = 2)]
+    pub mirror_interval: u32,
 }
 
 impl AppConfig {
     pub fn uniform(
         &self,
+        stereo_mode: StereoMode,
         aspect_ratio: f32,
         screen_width: u32,
         ambient_width: u32,
@@ -74,6 +188,10 @@ impl AppConfig {
             aspect_ratio,
             screen_width,
             ambient_width,
+            eye_uv_rects: [stereo_mode.eye_uv_rect(0), stereo_mode.eye_uv_rect(1)],
+            stereo_mode: stereo_mode as u32,
+            anaglyph_mode: self.anaglyph as u32,
+            _padding: [0; 2],
         }
     }
 }
@@ -90,6 +208,28 @@ impl Default for AppConfig {
             scale: 40.0,
             config_file: None,
             ambient: false,
+            anaglyph: AnaglyphMode::Off,
+            invert_frame_phase: false,
+            image_path: None,
+            image_stereo_mode: StereoMode::FullSbs,
+            slideshow_interval: 0,
+            test_pattern: None,
+            test_pattern_stereo_mode: StereoMode::FullSbs,
+            loader_priority: vec![],
+            x11_window: None,
+            x11_stereo_mode: StereoMode::FullSbs,
+            v4l_device: None,
+            v4l_stereo_mode: StereoMode::FullSbs,
+            net_listen: cfg!(target_os = "android").then(|| ANDROID_NET_LISTEN.to_owned()),
+            video_path: None,
+            video_stereo_mode: StereoMode::FullSbs,
+            shm_name: "/vr-screen-cap".to_owned(),
+            dmabuf_socket: None,
+            pipewire_node: None,
+            pipewire_stereo_mode: StereoMode::FullSbs,
+            headless_output: None,
+            mirror: None,
+            mirror_interval: 2,
         }
     }
 }
@@ -1,4 +1,8 @@
+#[cfg(target_os = "windows")]
+use anyhow::Context;
 use ash::vk;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+use pipewire::spa::param::video::VideoFormat;
 use wgpu::{Device, TextureDescriptor, TextureFormat};
 use wgpu_hal::api::Vulkan;
 #[cfg(target_os = "windows")]
@@ -9,83 +13,197 @@ pub fn vulkan_image_to_texture(
     image: vk::Image,
     tex_desc: TextureDescriptor,
     hal_tex_desc: wgpu_hal::TextureDescriptor,
+    // Without a guard wgpu destroys the image with the texture, a guard takes care of it instead
+    drop_guard: Option<wgpu_hal::DropGuard>,
 ) -> wgpu::Texture {
     let texture = unsafe {
         <wgpu_hal::api::Vulkan as wgpu_hal::Api>::Device::texture_from_raw(
             image,
             &hal_tex_desc,
-            None,
+            drop_guard,
         )
     };
 
     unsafe { device.create_texture_from_hal::<Vulkan>(texture, &tex_desc) }
 }
 
+// Concrete DXGI formats and their wgpu counterpart, typeless formats are resolved beforehand
+#[cfg(target_os = "windows")]
+fn dxgi_formats() -> &'static [(DXGI_FORMAT, TextureFormat)] {
+    use wgpu::TextureFormat as Tf;
+    &[
+        (DXGI_FORMAT_R8_UNORM, Tf::R8Unorm),
+        (DXGI_FORMAT_R8_SNORM, Tf::R8Snorm),
+        (DXGI_FORMAT_R8_UINT, Tf::R8Uint),
+        (DXGI_FORMAT_R8_SINT, Tf::R8Sint),
+        (DXGI_FORMAT_R16_UINT, Tf::R16Uint),
+        (DXGI_FORMAT_R16_SINT, Tf::R16Sint),
+        (DXGI_FORMAT_R16_UNORM, Tf::R16Unorm),
+        (DXGI_FORMAT_R16_SNORM, Tf::R16Snorm),
+        (DXGI_FORMAT_R16_FLOAT, Tf::R16Float),
+        (DXGI_FORMAT_R8G8_UNORM, Tf::Rg8Unorm),
+        (DXGI_FORMAT_R8G8_SNORM, Tf::Rg8Snorm),
+        (DXGI_FORMAT_R8G8_UINT, Tf::Rg8Uint),
+        (DXGI_FORMAT_R8G8_SINT, Tf::Rg8Sint),
+        (DXGI_FORMAT_R16G16_UNORM, Tf::Rg16Unorm),
+        (DXGI_FORMAT_R16G16_SNORM, Tf::Rg16Snorm),
+        (DXGI_FORMAT_R32_UINT, Tf::R32Uint),
+        (DXGI_FORMAT_R32_SINT, Tf::R32Sint),
+        (DXGI_FORMAT_R32_FLOAT, Tf::R32Float),
+        (DXGI_FORMAT_R16G16_UINT, Tf::Rg16Uint),
+        (DXGI_FORMAT_R16G16_SINT, Tf::Rg16Sint),
+        (DXGI_FORMAT_R16G16_FLOAT, Tf::Rg16Float),
+        (DXGI_FORMAT_R8G8B8A8_UNORM, Tf::Rgba8Unorm),
+        (DXGI_FORMAT_R8G8B8A8_UNORM_SRGB, Tf::Rgba8UnormSrgb),
+        (DXGI_FORMAT_B8G8R8A8_UNORM_SRGB, Tf::Bgra8UnormSrgb),
+        (DXGI_FORMAT_R8G8B8A8_SNORM, Tf::Rgba8Snorm),
+        (DXGI_FORMAT_B8G8R8A8_UNORM, Tf::Bgra8Unorm),
+        (DXGI_FORMAT_R8G8B8A8_UINT, Tf::Rgba8Uint),
+        (DXGI_FORMAT_R8G8B8A8_SINT, Tf::Rgba8Sint),
+        (DXGI_FORMAT_R10G10B10A2_UNORM, Tf::Rgb10a2Unorm),
+        (DXGI_FORMAT_R11G11B10_FLOAT, Tf::Rg11b10Float),
+        (DXGI_FORMAT_R32G32_UINT, Tf::Rg32Uint),
+        (DXGI_FORMAT_R32G32_SINT, Tf::Rg32Sint),
+        (DXGI_FORMAT_R32G32_FLOAT, Tf::Rg32Float),
+        (DXGI_FORMAT_R16G16B16A16_UINT, Tf::Rgba16Uint),
+        (DXGI_FORMAT_R16G16B16A16_SINT, Tf::Rgba16Sint),
+        (DXGI_FORMAT_R16G16B16A16_UNORM, Tf::Rgba16Unorm),
+        (DXGI_FORMAT_R16G16B16A16_SNORM, Tf::Rgba16Snorm),
+        (DXGI_FORMAT_R16G16B16A16_FLOAT, Tf::Rgba16Float),
+        (DXGI_FORMAT_R32G32B32A32_UINT, Tf::Rgba32Uint),
+        (DXGI_FORMAT_R32G32B32A32_SINT, Tf::Rgba32Sint),
+        (DXGI_FORMAT_R32G32B32A32_FLOAT, Tf::Rgba32Float),
+        (DXGI_FORMAT_D32_FLOAT, Tf::Depth32Float),
+        (DXGI_FORMAT_D32_FLOAT_S8X24_UINT, Tf::Depth32FloatStencil8),
+        (DXGI_FORMAT_D24_UNORM_S8_UINT, Tf::Depth24PlusStencil8),
+        (DXGI_FORMAT_R9G9B9E5_SHAREDEXP, Tf::Rgb9e5Ufloat),
+        (DXGI_FORMAT_BC1_UNORM, Tf::Bc1RgbaUnorm),
+        (DXGI_FORMAT_BC1_UNORM_SRGB, Tf::Bc1RgbaUnormSrgb),
+        (DXGI_FORMAT_BC2_UNORM, Tf::Bc2RgbaUnorm),
+        (DXGI_FORMAT_BC2_UNORM_SRGB, Tf::Bc2RgbaUnormSrgb),
+        (DXGI_FORMAT_BC3_UNORM, Tf::Bc3RgbaUnorm),
+        (DXGI_FORMAT_BC3_UNORM_SRGB, Tf::Bc3RgbaUnormSrgb),
+        (DXGI_FORMAT_BC4_UNORM, Tf::Bc4RUnorm),
+        (DXGI_FORMAT_BC4_SNORM, Tf::Bc4RSnorm),
+        (DXGI_FORMAT_BC5_UNORM, Tf::Bc5RgUnorm),
+        (DXGI_FORMAT_BC5_SNORM, Tf::Bc5RgSnorm),
+        (DXGI_FORMAT_BC6H_UF16, Tf::Bc6hRgbUfloat),
+        (DXGI_FORMAT_BC6H_SF16, Tf::Bc6hRgbFloat),
+        (DXGI_FORMAT_BC7_UNORM, Tf::Bc7RgbaUnorm),
+        (DXGI_FORMAT_BC7_UNORM_SRGB, Tf::Bc7RgbaUnormSrgb),
+        (DXGI_FORMAT_D16_UNORM, Tf::Depth16Unorm),
+    ]
+}
+
+// Typeless resources get the view format picked by the game, 8 and 10 bit channels resolve to
+// UNORM (SDR swapchains) and wider channels to FLOAT (scRGB swapchains)
 #[cfg(target_os = "windows")]
-pub fn unmap_texture_format(format: DXGI_FORMAT) -> TextureFormat {
+pub fn resolve_typeless_format(format: DXGI_FORMAT) -> DXGI_FORMAT {
     match format {
-        DXGI_FORMAT_R8_UNORM => TextureFormat::R8Unorm,
-        DXGI_FORMAT_R8_SNORM => TextureFormat::R8Snorm,
-        DXGI_FORMAT_R8_UINT => TextureFormat::R8Uint,
-        DXGI_FORMAT_R8_SINT => TextureFormat::R8Sint,
-        DXGI_FORMAT_R16_UINT => TextureFormat::R16Uint,
-        DXGI_FORMAT_R16_SINT => TextureFormat::R16Sint,
-        DXGI_FORMAT_R16_UNORM => TextureFormat::R16Unorm,
-        DXGI_FORMAT_R16_SNORM => TextureFormat::R16Snorm,
-        DXGI_FORMAT_R16_FLOAT => TextureFormat::R16Float,
-        DXGI_FORMAT_R8G8_UNORM => TextureFormat::Rg8Unorm,
-        DXGI_FORMAT_R8G8_SNORM => TextureFormat::Rg8Snorm,
-        DXGI_FORMAT_R8G8_UINT => TextureFormat::Rg8Uint,
-        DXGI_FORMAT_R8G8_SINT => TextureFormat::Rg8Sint,
-        DXGI_FORMAT_R16G16_UNORM => TextureFormat::Rg16Unorm,
-        DXGI_FORMAT_R16G16_SNORM => TextureFormat::Rg16Snorm,
-        DXGI_FORMAT_R32_UINT => TextureFormat::R32Uint,
-        DXGI_FORMAT_R32_SINT => TextureFormat::R32Sint,
-        DXGI_FORMAT_R32_FLOAT => TextureFormat::R32Float,
-        DXGI_FORMAT_R16G16_UINT => TextureFormat::Rg16Uint,
-        DXGI_FORMAT_R16G16_SINT => TextureFormat::Rg16Sint,
-        DXGI_FORMAT_R16G16_FLOAT => TextureFormat::Rg16Float,
-        DXGI_FORMAT_R8G8B8A8_TYPELESS => TextureFormat::Rgba8Unorm,
-        DXGI_FORMAT_R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
-        DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => TextureFormat::Rgba8UnormSrgb,
-        DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => TextureFormat::Bgra8UnormSrgb,
-        DXGI_FORMAT_R8G8B8A8_SNORM => TextureFormat::Rgba8Snorm,
-        DXGI_FORMAT_B8G8R8A8_UNORM => TextureFormat::Bgra8Unorm,
-        DXGI_FORMAT_R8G8B8A8_UINT => TextureFormat::Rgba8Uint,
-        DXGI_FORMAT_R8G8B8A8_SINT => TextureFormat::Rgba8Sint,
-        DXGI_FORMAT_R10G10B10A2_UNORM => TextureFormat::Rgb10a2Unorm,
-        DXGI_FORMAT_R11G11B10_FLOAT => TextureFormat::Rg11b10Float,
-        DXGI_FORMAT_R32G32_UINT => TextureFormat::Rg32Uint,
-        DXGI_FORMAT_R32G32_SINT => TextureFormat::Rg32Sint,
-        DXGI_FORMAT_R32G32_FLOAT => TextureFormat::Rg32Float,
-        DXGI_FORMAT_R16G16B16A16_UINT => TextureFormat::Rgba16Uint,
-        DXGI_FORMAT_R16G16B16A16_SINT => TextureFormat::Rgba16Sint,
-        DXGI_FORMAT_R16G16B16A16_UNORM => TextureFormat::Rgba16Unorm,
-        DXGI_FORMAT_R16G16B16A16_SNORM => TextureFormat::Rgba16Snorm,
-        DXGI_FORMAT_R16G16B16A16_FLOAT => TextureFormat::Rgba16Float,
-        DXGI_FORMAT_R32G32B32A32_UINT => TextureFormat::Rgba32Uint,
-        DXGI_FORMAT_R32G32B32A32_SINT => TextureFormat::Rgba32Sint,
-        DXGI_FORMAT_R32G32B32A32_FLOAT => TextureFormat::Rgba32Float,
-        DXGI_FORMAT_D32_FLOAT => TextureFormat::Depth32Float,
-        DXGI_FORMAT_D32_FLOAT_S8X24_UINT => TextureFormat::Depth32FloatStencil8,
-        DXGI_FORMAT_D24_UNORM_S8_UINT => TextureFormat::Depth24PlusStencil8,
-        DXGI_FORMAT_R9G9B9E5_SHAREDEXP => TextureFormat::Rgb9e5Ufloat,
-        DXGI_FORMAT_BC1_UNORM => TextureFormat::Bc1RgbaUnorm,
-        DXGI_FORMAT_BC1_UNORM_SRGB => TextureFormat::Bc1RgbaUnormSrgb,
-        DXGI_FORMAT_BC2_UNORM => TextureFormat::Bc2RgbaUnorm,
-        DXGI_FORMAT_BC2_UNORM_SRGB => TextureFormat::Bc2RgbaUnormSrgb,
-        DXGI_FORMAT_BC3_UNORM => TextureFormat::Bc3RgbaUnorm,
-        DXGI_FORMAT_BC3_UNORM_SRGB => TextureFormat::Bc3RgbaUnormSrgb,
-        DXGI_FORMAT_BC4_UNORM => TextureFormat::Bc4RUnorm,
-        DXGI_FORMAT_BC4_SNORM => TextureFormat::Bc4RSnorm,
-        DXGI_FORMAT_BC5_UNORM => TextureFormat::Bc5RgUnorm,
-        DXGI_FORMAT_BC5_SNORM => TextureFormat::Bc5RgSnorm,
-        DXGI_FORMAT_BC6H_UF16 => TextureFormat::Bc6hRgbUfloat,
-        DXGI_FORMAT_BC6H_SF16 => TextureFormat::Bc6hRgbFloat,
-        DXGI_FORMAT_BC7_UNORM => TextureFormat::Bc7RgbaUnorm,
-        DXGI_FORMAT_BC7_UNORM_SRGB => TextureFormat::Bc7RgbaUnormSrgb,
-        DXGI_FORMAT_D16_UNORM => TextureFormat::Depth16Unorm,
-        _ => panic!("Unsupported texture format: {:?}", format),
+        DXGI_FORMAT_R8_TYPELESS => DXGI_FORMAT_R8_UNORM,
+        DXGI_FORMAT_R8G8_TYPELESS => DXGI_FORMAT_R8G8_UNORM,
+        DXGI_FORMAT_R8G8B8A8_TYPELESS => DXGI_FORMAT_R8G8B8A8_UNORM,
+        DXGI_FORMAT_B8G8R8A8_TYPELESS => DXGI_FORMAT_B8G8R8A8_UNORM,
+        DXGI_FORMAT_R10G10B10A2_TYPELESS => DXGI_FORMAT_R10G10B10A2_UNORM,
+        // Single channel 16 bit resources are almost always D16 depth buffers
+        DXGI_FORMAT_R16_TYPELESS => DXGI_FORMAT_R16_UNORM,
+        DXGI_FORMAT_R16G16_TYPELESS => DXGI_FORMAT_R16G16_FLOAT,
+        DXGI_FORMAT_R16G16B16A16_TYPELESS => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        DXGI_FORMAT_R32_TYPELESS => DXGI_FORMAT_R32_FLOAT,
+        DXGI_FORMAT_R32G32_TYPELESS => DXGI_FORMAT_R32G32_FLOAT,
+        DXGI_FORMAT_R32G32B32A32_TYPELESS => DXGI_FORMAT_R32G32B32A32_FLOAT,
+        DXGI_FORMAT_R24G8_TYPELESS => DXGI_FORMAT_D24_UNORM_S8_UINT,
+        DXGI_FORMAT_R32G8X24_TYPELESS => DXGI_FORMAT_D32_FLOAT_S8X24_UINT,
+        DXGI_FORMAT_BC1_TYPELESS => DXGI_FORMAT_BC1_UNORM,
+        DXGI_FORMAT_BC2_TYPELESS => DXGI_FORMAT_BC2_UNORM,
+        DXGI_FORMAT_BC3_TYPELESS => DXGI_FORMAT_BC3_UNORM,
+        DXGI_FORMAT_BC4_TYPELESS => DXGI_FORMAT_BC4_UNORM,
+        DXGI_FORMAT_BC5_TYPELESS => DXGI_FORMAT_BC5_UNORM,
+        DXGI_FORMAT_BC6H_TYPELESS => DXGI_FORMAT_BC6H_UF16,
+        DXGI_FORMAT_BC7_TYPELESS => DXGI_FORMAT_BC7_UNORM,
+        _ => format,
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub fn is_typeless_format(format: DXGI_FORMAT) -> bool {
+    resolve_typeless_format(format) != format
+}
+
+#[cfg(target_os = "windows")]
+pub fn unmap_texture_format(format: DXGI_FORMAT) -> anyhow::Result<TextureFormat> {
+    let concrete_format = resolve_typeless_format(format);
+    dxgi_formats()
+        .iter()
+        .find(|(dxgi_format, _)| *dxgi_format == concrete_format)
+        .map(|(_, texture_format)| *texture_format)
+        .with_context(|| format!("Unsupported texture format: {:?}", format))
+}
+
+#[cfg(target_os = "windows")]
+pub fn map_dxgi_format(format: TextureFormat) -> Option<DXGI_FORMAT> {
+    dxgi_formats()
+        .iter()
+        .find(|(_, texture_format)| *texture_format == format)
+        .map(|(dxgi_format, _)| *dxgi_format)
+}
+
+// Shared textures keep the stored values untouched by sampling through their own format, the
+// other encoding is only offered as a view format of resources created typeless
+pub fn srgb_view_formats(format: TextureFormat) -> Vec<TextureFormat> {
+    let alternate_format = if format.is_srgb() {
+        format.remove_srgb_suffix()
+    } else {
+        format.add_srgb_suffix()
+    };
+    if alternate_format == format {
+        vec![]
+    } else {
+        vec![alternate_format]
+    }
+}
+
+pub const fn drm_fourcc(code: &[u8; 4]) -> u32 {
+    (code[0] as u32) | (code[1] as u32) << 8 | (code[2] as u32) << 16 | (code[3] as u32) << 24
+}
+
+pub const DRM_FORMAT_R8: u32 = drm_fourcc(b"R8  ");
+pub const DRM_FORMAT_GR88: u32 = drm_fourcc(b"GR88");
+pub const DRM_FORMAT_R16: u32 = drm_fourcc(b"R16 ");
+pub const DRM_FORMAT_GR1616: u32 = drm_fourcc(b"GR32");
+pub const DRM_FORMAT_XRGB8888: u32 = drm_fourcc(b"XR24");
+pub const DRM_FORMAT_ARGB8888: u32 = drm_fourcc(b"AR24");
+pub const DRM_FORMAT_XBGR8888: u32 = drm_fourcc(b"XB24");
+pub const DRM_FORMAT_ABGR8888: u32 = drm_fourcc(b"AB24");
+pub const DRM_FORMAT_XBGR2101010: u32 = drm_fourcc(b"XB30");
+pub const DRM_FORMAT_ABGR2101010: u32 = drm_fourcc(b"AB30");
+pub const DRM_FORMAT_XBGR16161616F: u32 = drm_fourcc(b"XB4H");
+pub const DRM_FORMAT_ABGR16161616F: u32 = drm_fourcc(b"AB4H");
+
+// DRM formats are named after the packed little endian value, so ARGB8888 is laid out as B, G, R, A
+pub fn unmap_drm_format(fourcc: u32) -> Option<TextureFormat> {
+    match fourcc {
+        DRM_FORMAT_R8 => Some(TextureFormat::R8Unorm),
+        DRM_FORMAT_GR88 => Some(TextureFormat::Rg8Unorm),
+        DRM_FORMAT_R16 => Some(TextureFormat::R16Unorm),
+        DRM_FORMAT_GR1616 => Some(TextureFormat::Rg16Unorm),
+        DRM_FORMAT_XRGB8888 | DRM_FORMAT_ARGB8888 => Some(TextureFormat::Bgra8Unorm),
+        DRM_FORMAT_XBGR8888 | DRM_FORMAT_ABGR8888 => Some(TextureFormat::Rgba8Unorm),
+        DRM_FORMAT_XBGR2101010 | DRM_FORMAT_ABGR2101010 => Some(TextureFormat::Rgb10a2Unorm),
+        DRM_FORMAT_XBGR16161616F | DRM_FORMAT_ABGR16161616F => Some(TextureFormat::Rgba16Float),
+        _ => None,
+    }
+}
+
+// Only packed formats wgpu can sample directly are negotiated, 24 bit RGB has no texture format
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub fn unmap_spa_video_format(format: VideoFormat) -> Option<TextureFormat> {
+    match format {
+        VideoFormat::RGBA | VideoFormat::RGBx => Some(TextureFormat::Rgba8Unorm),
+        VideoFormat::BGRA | VideoFormat::BGRx => Some(TextureFormat::Bgra8Unorm),
+        VideoFormat::ABGR_210LE | VideoFormat::xBGR_210LE => Some(TextureFormat::Rgb10a2Unorm),
+        VideoFormat::GRAY8 => Some(TextureFormat::R8Unorm),
+        _ => None,
     }
 }
 
@@ -218,3 +336,544 @@ pub fn map_texture_format(format: wgpu::TextureFormat) -> vk::Format {
         Tf::Stencil8 => F::S8_UINT,
     }
 }
+
+// Inverse of map_texture_format, used to describe images created outside of wgpu
+pub fn unmap_vk_format(format: vk::Format) -> Option<wgpu::TextureFormat> {
+    use ash::vk::Format as F;
+    use wgpu::TextureFormat as Tf;
+    use wgpu::{AstcBlock, AstcChannel};
+    Some(match format {
+        F::R8_UNORM => Tf::R8Unorm,
+        F::R8_SNORM => Tf::R8Snorm,
+        F::R8_UINT => Tf::R8Uint,
+        F::R8_SINT => Tf::R8Sint,
+        F::R16_UINT => Tf::R16Uint,
+        F::R16_SINT => Tf::R16Sint,
+        F::R16_UNORM => Tf::R16Unorm,
+        F::R16_SNORM => Tf::R16Snorm,
+        F::R16_SFLOAT => Tf::R16Float,
+        F::R8G8_UNORM => Tf::Rg8Unorm,
+        F::R8G8_SNORM => Tf::Rg8Snorm,
+        F::R8G8_UINT => Tf::Rg8Uint,
+        F::R8G8_SINT => Tf::Rg8Sint,
+        F::R16G16_UNORM => Tf::Rg16Unorm,
+        F::R16G16_SNORM => Tf::Rg16Snorm,
+        F::R32_UINT => Tf::R32Uint,
+        F::R32_SINT => Tf::R32Sint,
+        F::R32_SFLOAT => Tf::R32Float,
+        F::R16G16_UINT => Tf::Rg16Uint,
+        F::R16G16_SINT => Tf::Rg16Sint,
+        F::R16G16_SFLOAT => Tf::Rg16Float,
+        F::R8G8B8A8_UNORM => Tf::Rgba8Unorm,
+        F::R8G8B8A8_SRGB => Tf::Rgba8UnormSrgb,
+        F::B8G8R8A8_SRGB => Tf::Bgra8UnormSrgb,
+        F::R8G8B8A8_SNORM => Tf::Rgba8Snorm,
+        F::B8G8R8A8_UNORM => Tf::Bgra8Unorm,
+        F::R8G8B8A8_UINT => Tf::Rgba8Uint,
+        F::R8G8B8A8_SINT => Tf::Rgba8Sint,
+        F::A2B10G10R10_UNORM_PACK32 => Tf::Rgb10a2Unorm,
+        F::B10G11R11_UFLOAT_PACK32 => Tf::Rg11b10Float,
+        F::R32G32_UINT => Tf::Rg32Uint,
+        F::R32G32_SINT => Tf::Rg32Sint,
+        F::R32G32_SFLOAT => Tf::Rg32Float,
+        F::R16G16B16A16_UINT => Tf::Rgba16Uint,
+        F::R16G16B16A16_SINT => Tf::Rgba16Sint,
+        F::R16G16B16A16_UNORM => Tf::Rgba16Unorm,
+        F::R16G16B16A16_SNORM => Tf::Rgba16Snorm,
+        F::R16G16B16A16_SFLOAT => Tf::Rgba16Float,
+        F::R32G32B32A32_UINT => Tf::Rgba32Uint,
+        F::R32G32B32A32_SINT => Tf::Rgba32Sint,
+        F::R32G32B32A32_SFLOAT => Tf::Rgba32Float,
+        F::D32_SFLOAT => Tf::Depth32Float,
+        F::D32_SFLOAT_S8_UINT => Tf::Depth32FloatStencil8,
+        F::D24_UNORM_S8_UINT => Tf::Depth24PlusStencil8,
+        F::D16_UNORM => Tf::Depth16Unorm,
+        F::E5B9G9R9_UFLOAT_PACK32 => Tf::Rgb9e5Ufloat,
+        F::BC1_RGBA_UNORM_BLOCK => Tf::Bc1RgbaUnorm,
+        F::BC1_RGBA_SRGB_BLOCK => Tf::Bc1RgbaUnormSrgb,
+        F::BC2_UNORM_BLOCK => Tf::Bc2RgbaUnorm,
+        F::BC2_SRGB_BLOCK => Tf::Bc2RgbaUnormSrgb,
+        F::BC3_UNORM_BLOCK => Tf::Bc3RgbaUnorm,
+        F::BC3_SRGB_BLOCK => Tf::Bc3RgbaUnormSrgb,
+        F::BC4_UNORM_BLOCK => Tf::Bc4RUnorm,
+        F::BC4_SNORM_BLOCK => Tf::Bc4RSnorm,
+        F::BC5_UNORM_BLOCK => Tf::Bc5RgUnorm,
+        F::BC5_SNORM_BLOCK => Tf::Bc5RgSnorm,
+        F::BC6H_UFLOAT_BLOCK => Tf::Bc6hRgbUfloat,
+        F::BC6H_SFLOAT_BLOCK => Tf::Bc6hRgbFloat,
+        F::BC7_UNORM_BLOCK => Tf::Bc7RgbaUnorm,
+        F::BC7_SRGB_BLOCK => Tf::Bc7RgbaUnormSrgb,
+        F::ETC2_R8G8B8_UNORM_BLOCK => Tf::Etc2Rgb8Unorm,
+        F::ETC2_R8G8B8_SRGB_BLOCK => Tf::Etc2Rgb8UnormSrgb,
+        F::ETC2_R8G8B8A1_UNORM_BLOCK => Tf::Etc2Rgb8A1Unorm,
+        F::ETC2_R8G8B8A1_SRGB_BLOCK => Tf::Etc2Rgb8A1UnormSrgb,
+        F::ETC2_R8G8B8A8_UNORM_BLOCK => Tf::Etc2Rgba8Unorm,
+        F::ETC2_R8G8B8A8_SRGB_BLOCK => Tf::Etc2Rgba8UnormSrgb,
+        F::EAC_R11_UNORM_BLOCK => Tf::EacR11Unorm,
+        F::EAC_R11_SNORM_BLOCK => Tf::EacR11Snorm,
+        F::EAC_R11G11_UNORM_BLOCK => Tf::EacRg11Unorm,
+        F::EAC_R11G11_SNORM_BLOCK => Tf::EacRg11Snorm,
+        F::S8_UINT => Tf::Stencil8,
+        F::ASTC_4X4_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B4x4,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_5X4_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B5x4,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_5X5_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B5x5,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_6X5_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B6x5,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_6X6_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B6x6,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_8X5_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B8x5,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_8X6_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B8x6,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_8X8_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B8x8,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_10X5_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B10x5,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_10X6_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B10x6,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_10X8_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B10x8,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_10X10_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B10x10,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_12X10_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B12x10,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_12X12_UNORM_BLOCK => Tf::Astc {
+            block: AstcBlock::B12x12,
+            channel: AstcChannel::Unorm,
+        },
+        F::ASTC_4X4_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B4x4,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_5X4_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B5x4,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_5X5_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B5x5,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_6X5_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B6x5,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_6X6_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B6x6,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_8X5_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B8x5,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_8X6_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B8x6,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_8X8_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B8x8,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_10X5_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B10x5,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_10X6_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B10x6,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_10X8_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B10x8,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_10X10_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B10x10,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_12X10_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B12x10,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_12X12_SRGB_BLOCK => Tf::Astc {
+            block: AstcBlock::B12x12,
+            channel: AstcChannel::UnormSrgb,
+        },
+        F::ASTC_4X4_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B4x4,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_5X4_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B5x4,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_5X5_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B5x5,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_6X5_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B6x5,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_6X6_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B6x6,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_8X5_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B8x5,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_8X6_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B8x6,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_8X8_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B8x8,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_10X5_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B10x5,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_10X6_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B10x6,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_10X8_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B10x8,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_10X10_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B10x10,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_12X10_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B12x10,
+            channel: AstcChannel::Hdr,
+        },
+        F::ASTC_12X12_SFLOAT_BLOCK_EXT => Tf::Astc {
+            block: AstcBlock::B12x12,
+            channel: AstcChannel::Hdr,
+        },
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FormatCapabilities {
+    pub sampled: bool,
+    pub filterable: bool,
+    // Whether images of the format can be imported from the requested external handle type
+    pub importable: bool,
+    pub max_extent: vk::Extent3D,
+}
+
+// Features of the format when laid out with the modifier, empty if the device doesn't offer it
+unsafe fn drm_modifier_features(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+    modifier: u64,
+) -> vk::FormatFeatureFlags {
+    let mut modifier_list = vk::DrmFormatModifierPropertiesListEXT::default();
+    {
+        let mut properties = vk::FormatProperties2::builder().push_next(&mut modifier_list);
+        instance.get_physical_device_format_properties2(physical_device, format, &mut properties);
+    }
+    let mut modifiers = vec![
+        vk::DrmFormatModifierPropertiesEXT::default();
+        modifier_list.drm_format_modifier_count as usize
+    ];
+    modifier_list.p_drm_format_modifier_properties = modifiers.as_mut_ptr();
+    {
+        let mut properties = vk::FormatProperties2::builder().push_next(&mut modifier_list);
+        instance.get_physical_device_format_properties2(physical_device, format, &mut properties);
+    }
+
+    modifiers
+        .iter()
+        .take(modifier_list.drm_format_modifier_count as usize)
+        .find(|properties| properties.drm_format_modifier == modifier)
+        .map(|properties| properties.drm_format_modifier_tiling_features)
+        .unwrap_or_default()
+}
+
+// Checks a format against the physical device before creating images of it with the given flags,
+// modifiers only apply to DRM_FORMAT_MODIFIER_EXT tiling
+pub unsafe fn query_format_capabilities(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    flags: vk::ImageCreateFlags,
+    modifier: Option<u64>,
+    handle_type: Option<vk::ExternalMemoryHandleTypeFlags>,
+) -> FormatCapabilities {
+    let format_properties = instance.get_physical_device_format_properties(physical_device, format);
+    let features = match tiling {
+        vk::ImageTiling::LINEAR => format_properties.linear_tiling_features,
+        vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT => drm_modifier_features(
+            instance,
+            physical_device,
+            format,
+            modifier.unwrap_or_default(),
+        ),
+        _ => format_properties.optimal_tiling_features,
+    };
+
+    let mut modifier_info = vk::PhysicalDeviceImageDrmFormatModifierInfoEXT::builder()
+        .drm_format_modifier(modifier.unwrap_or_default())
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let mut external_info = vk::PhysicalDeviceExternalImageFormatInfo::builder()
+        .handle_type(handle_type.unwrap_or_default());
+    let mut format_info = vk::PhysicalDeviceImageFormatInfo2::builder()
+        .format(format)
+        .ty(vk::ImageType::TYPE_2D)
+        .tiling(tiling)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC)
+        .flags(flags);
+    if modifier.is_some() {
+        format_info = format_info.push_next(&mut modifier_info);
+    }
+    if handle_type.is_some() {
+        format_info = format_info.push_next(&mut external_info);
+    }
+
+    let mut external_properties = vk::ExternalImageFormatProperties::default();
+    let mut properties = vk::ImageFormatProperties2::builder().push_next(&mut external_properties);
+    // Unsupported combinations are reported through FORMAT_NOT_SUPPORTED
+    let supported = instance
+        .get_physical_device_image_format_properties2(
+            physical_device,
+            &format_info,
+            &mut properties,
+        )
+        .is_ok();
+    let max_extent = properties.image_format_properties.max_extent;
+
+    FormatCapabilities {
+        sampled: supported && features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE),
+        filterable: supported
+            && features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR),
+        importable: supported
+            && handle_type.is_some()
+            && external_properties
+                .external_memory_properties
+                .external_memory_features
+                .contains(vk::ExternalMemoryFeatureFlags::IMPORTABLE),
+        max_extent,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vk_formats_round_trip() {
+        // Core formats plus the ASTC HDR extension range
+        for raw in (0..=184).chain(1_000_066_000..1_000_066_014) {
+            let format = vk::Format::from_raw(raw);
+            if let Some(texture_format) = unmap_vk_format(format) {
+                assert_eq!(map_texture_format(texture_format), format);
+            }
+        }
+    }
+
+    #[test]
+    fn vk_formats_unmap_to_wgpu_formats() {
+        assert_eq!(
+            unmap_vk_format(vk::Format::B8G8R8A8_SRGB),
+            Some(wgpu::TextureFormat::Bgra8UnormSrgb)
+        );
+        assert_eq!(
+            unmap_vk_format(vk::Format::D32_SFLOAT),
+            Some(wgpu::TextureFormat::Depth32Float)
+        );
+        assert_eq!(
+            unmap_vk_format(vk::Format::ASTC_4X4_SFLOAT_BLOCK_EXT),
+            Some(wgpu::TextureFormat::Astc {
+                block: wgpu::AstcBlock::B4x4,
+                channel: wgpu::AstcChannel::Hdr,
+            })
+        );
+        assert_eq!(unmap_vk_format(vk::Format::G8_B8R8_2PLANE_420_UNORM), None);
+    }
+
+    #[test]
+    fn drm_fourcc_matches_drm_fourcc_h() {
+        assert_eq!(DRM_FORMAT_XRGB8888, 0x3432_5258);
+        assert_eq!(DRM_FORMAT_ARGB8888, 0x3432_5241);
+        assert_eq!(DRM_FORMAT_ABGR8888, 0x3432_4241);
+        assert_eq!(DRM_FORMAT_R8, 0x2020_3852);
+    }
+
+    #[test]
+    fn drm_formats_map_to_matching_channel_order() {
+        assert_eq!(
+            unmap_drm_format(DRM_FORMAT_XRGB8888),
+            Some(TextureFormat::Bgra8Unorm)
+        );
+        assert_eq!(
+            unmap_drm_format(DRM_FORMAT_ARGB8888),
+            Some(TextureFormat::Bgra8Unorm)
+        );
+        assert_eq!(
+            unmap_drm_format(DRM_FORMAT_XBGR8888),
+            Some(TextureFormat::Rgba8Unorm)
+        );
+        assert_eq!(
+            unmap_drm_format(DRM_FORMAT_ABGR8888),
+            Some(TextureFormat::Rgba8Unorm)
+        );
+        assert_eq!(
+            unmap_drm_format(DRM_FORMAT_ABGR2101010),
+            Some(TextureFormat::Rgb10a2Unorm)
+        );
+        assert_eq!(
+            unmap_drm_format(DRM_FORMAT_ABGR16161616F),
+            Some(TextureFormat::Rgba16Float)
+        );
+        assert_eq!(
+            unmap_drm_format(DRM_FORMAT_GR88),
+            Some(TextureFormat::Rg8Unorm)
+        );
+    }
+
+    #[test]
+    fn unknown_drm_formats_are_rejected() {
+        assert_eq!(unmap_drm_format(0), None);
+        assert_eq!(unmap_drm_format(drm_fourcc(b"NV12")), None);
+        assert_eq!(unmap_drm_format(drm_fourcc(b"YUYV")), None);
+    }
+
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    #[test]
+    fn spa_formats_map_to_matching_channel_order() {
+        assert_eq!(
+            unmap_spa_video_format(VideoFormat::BGRx),
+            Some(TextureFormat::Bgra8Unorm)
+        );
+        assert_eq!(
+            unmap_spa_video_format(VideoFormat::RGBA),
+            Some(TextureFormat::Rgba8Unorm)
+        );
+        assert_eq!(
+            unmap_spa_video_format(VideoFormat::xBGR_210LE),
+            Some(TextureFormat::Rgb10a2Unorm)
+        );
+        assert_eq!(unmap_spa_video_format(VideoFormat::RGB), None);
+        assert_eq!(unmap_spa_video_format(VideoFormat::NV12), None);
+    }
+
+    #[test]
+    fn drm_formats_have_a_vulkan_format() {
+        for fourcc in [
+            DRM_FORMAT_R8,
+            DRM_FORMAT_GR88,
+            DRM_FORMAT_R16,
+            DRM_FORMAT_GR1616,
+            DRM_FORMAT_XRGB8888,
+            DRM_FORMAT_ARGB8888,
+            DRM_FORMAT_XBGR8888,
+            DRM_FORMAT_ABGR8888,
+            DRM_FORMAT_XBGR2101010,
+            DRM_FORMAT_ABGR2101010,
+            DRM_FORMAT_XBGR16161616F,
+            DRM_FORMAT_ABGR16161616F,
+        ] {
+            let format = unmap_drm_format(fourcc).unwrap();
+            assert_ne!(map_texture_format(format), vk::Format::UNDEFINED);
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn dxgi_formats_round_trip() {
+        for (dxgi_format, texture_format) in dxgi_formats() {
+            assert_eq!(unmap_texture_format(*dxgi_format).unwrap(), *texture_format);
+            assert_eq!(map_dxgi_format(*texture_format), Some(*dxgi_format));
+            assert_ne!(map_texture_format(*texture_format), vk::Format::UNDEFINED);
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn typeless_dxgi_formats_resolve_to_concrete_formats() {
+        assert!(is_typeless_format(DXGI_FORMAT_R8G8B8A8_TYPELESS));
+        assert!(!is_typeless_format(DXGI_FORMAT_R8G8B8A8_UNORM));
+        assert_eq!(
+            unmap_texture_format(DXGI_FORMAT_R8G8B8A8_TYPELESS).unwrap(),
+            TextureFormat::Rgba8Unorm
+        );
+        assert_eq!(
+            unmap_texture_format(DXGI_FORMAT_B8G8R8A8_TYPELESS).unwrap(),
+            TextureFormat::Bgra8Unorm
+        );
+        assert_eq!(
+            unmap_texture_format(DXGI_FORMAT_R10G10B10A2_TYPELESS).unwrap(),
+            TextureFormat::Rgb10a2Unorm
+        );
+        assert_eq!(
+            unmap_texture_format(DXGI_FORMAT_R16G16B16A16_TYPELESS).unwrap(),
+            TextureFormat::Rgba16Float
+        );
+        assert_eq!(
+            unmap_texture_format(DXGI_FORMAT_BC7_TYPELESS).unwrap(),
+            TextureFormat::Bc7RgbaUnorm
+        );
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn unknown_dxgi_formats_are_rejected() {
+        assert!(unmap_texture_format(DXGI_FORMAT_UNKNOWN).is_err());
+        assert!(unmap_texture_format(DXGI_FORMAT_NV12).is_err());
+        assert!(unmap_texture_format(DXGI_FORMAT_B5G6R5_UNORM).is_err());
+    }
+
+    #[test]
+    fn srgb_view_formats_offer_the_other_encoding() {
+        assert_eq!(
+            srgb_view_formats(TextureFormat::Rgba8Unorm),
+            vec![TextureFormat::Rgba8UnormSrgb]
+        );
+        assert_eq!(
+            srgb_view_formats(TextureFormat::Bgra8UnormSrgb),
+            vec![TextureFormat::Bgra8Unorm]
+        );
+        assert_eq!(
+            srgb_view_formats(TextureFormat::Bc7RgbaUnorm),
+            vec![TextureFormat::Bc7RgbaUnormSrgb]
+        );
+        assert!(srgb_view_formats(TextureFormat::Rgba16Float).is_empty());
+        assert!(srgb_view_formats(TextureFormat::Rgb10a2Unorm).is_empty());
+    }
+}
@@ -1,13 +1,30 @@
-use ash::vk;
+use std::ffi::CStr;
+
+use ash::vk::{self, Handle};
+
+use crate::conversions::{map_texture_format, query_format_capabilities, FormatCapabilities};
 
 pub mod camera;
+#[cfg(not(target_os = "android"))]
+pub mod desktop;
 pub mod entity;
 pub mod geometry;
+pub mod headless;
 pub mod input;
 pub mod jitter;
+#[cfg(not(target_os = "android"))]
+pub mod mirror;
+pub mod pipelines;
 pub mod screen;
+pub mod session;
 pub mod texture;
 pub mod vr;
+// Only the video and V4L2 loaders hand over YUV frames
+#[cfg_attr(
+    not(any(feature = "video", all(target_os = "linux", feature = "v4l"))),
+    allow(dead_code)
+)]
+pub mod yuv;
 
 pub const TARGET_VULKAN_VERSION: u32 = vk::make_api_version(0, 1, 1, 0);
 
@@ -25,10 +42,76 @@ pub struct WgpuContext {
     pub queue: wgpu::Queue,
 }
 
+impl WgpuContext {
+    // Capabilities of the format on the physical device, for images created with flags and
+    // imported from handle_type
+    pub fn format_capabilities(
+        &self,
+        format: wgpu::TextureFormat,
+        tiling: vk::ImageTiling,
+        flags: vk::ImageCreateFlags,
+        modifier: Option<u64>,
+        handle_type: Option<vk::ExternalMemoryHandleTypeFlags>,
+    ) -> FormatCapabilities {
+        unsafe {
+            let instance = ash::Instance::load(
+                self.vk_entry.static_fn(),
+                vk::Instance::from_raw(self.vk_instance_ptr),
+            );
+            query_format_capabilities(
+                &instance,
+                vk::PhysicalDevice::from_raw(self.vk_phys_device_ptr),
+                map_texture_format(format),
+                tiling,
+                flags,
+                modifier,
+                handle_type,
+            )
+        }
+    }
+}
+
 pub trait WgpuLoader {
     fn load_wgpu(&mut self) -> anyhow::Result<WgpuContext>;
 }
 
-pub trait WgpuRunner {
-    fn run(&mut self, wgpu_context: &WgpuContext);
+// Device extensions the loaders import external memory through, on top of what wgpu requires
+pub fn external_memory_extensions(
+    vk_instance: &ash::Instance,
+    vk_physical_device: vk::PhysicalDevice,
+) -> anyhow::Result<Vec<&'static CStr>> {
+    #[cfg(target_os = "windows")]
+    {
+        let _ = (vk_instance, vk_physical_device);
+        Ok(vec![ash::extensions::khr::ExternalMemoryWin32::name()])
+    }
+
+    // DMA-BUF imports are optional on Linux, only request them when the driver has them all
+    #[cfg(target_os = "linux")]
+    {
+        let available_extensions =
+            unsafe { vk_instance.enumerate_device_extension_properties(vk_physical_device)? };
+        let dma_buf_extensions = vec![
+            ash::extensions::khr::ExternalMemoryFd::name(),
+            vk::ExtExternalMemoryDmaBufFn::name(),
+            vk::ExtImageDrmFormatModifierFn::name(),
+            vk::KhrImageFormatListFn::name(),
+        ];
+        if dma_buf_extensions.iter().all(|extension| {
+            available_extensions.iter().any(|available| unsafe {
+                CStr::from_ptr(available.extension_name.as_ptr()) == *extension
+            })
+        }) {
+            Ok(dma_buf_extensions)
+        } else {
+            log::warn!("DMA-BUF import extensions not available");
+            Ok(vec![])
+        }
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    {
+        let _ = (vk_instance, vk_physical_device);
+        Ok(vec![])
+    }
 }
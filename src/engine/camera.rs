@@ -51,7 +51,6 @@ impl Camera {
         );
     }
 
-    #[allow(unused)]
     pub fn update_projection(&mut self, fov: Rad<f32>, aspect_ratio: f32) {
         self.projection = cgmath::perspective(fov, aspect_ratio, self.near, self.far)
     }
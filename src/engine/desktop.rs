@@ -0,0 +1,217 @@
+use anyhow::Context;
+use ash::vk::{self, Handle};
+use cgmath::{Deg, Quaternion, Rad, Rotation, Rotation3, Vector3};
+use wgpu_hal::{api::Vulkan, OpenDevice};
+
+use super::{
+    camera::Camera, external_memory_extensions, headless::HEADLESS_IPD, mirror::WindowThread,
+    WgpuContext, WgpuLoader,
+};
+
+// Vertical field of view of the desktop preview
+pub const DESKTOP_FOV: Deg<f32> = Deg(60.0);
+// Radians turned for every pixel the mouse is dragged by
+const MOUSE_LOOK_SENSITIVITY: f32 = 0.004;
+// Keeps the camera from flipping over when looking straight up or down
+const MAX_PITCH: f32 = 1.5;
+
+// Head orientation of the desktop preview, driven by dragging the mouse
+#[derive(Default)]
+pub struct MouseLook {
+    yaw: f32,
+    pitch: f32,
+}
+
+impl MouseLook {
+    pub fn rotate(&mut self, (delta_x, delta_y): (f32, f32)) {
+        self.yaw -= delta_x * MOUSE_LOOK_SENSITIVITY;
+        self.pitch = (self.pitch - delta_y * MOUSE_LOOK_SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    // Looks straight at the screen again
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    // Places one camera per eye at the origin, like a headset right after recentering
+    pub fn update_cameras(&self, cameras: &mut [Camera], aspect_ratio: f32) {
+        let rotation =
+            Quaternion::from_angle_y(Rad(self.yaw)) * Quaternion::from_angle_x(Rad(self.pitch));
+        for (eye, camera) in cameras.iter_mut().enumerate() {
+            let side = if eye == 0 { -0.5 } else { 0.5 };
+            camera.entity.position =
+                rotation.rotate_vector(Vector3::new(side * HEADLESS_IPD, 0.0, 0.0));
+            camera.entity.rotation = rotation;
+            camera.entity.update_matrices(&[]);
+            camera.update_projection(DESKTOP_FOV.into(), aspect_ratio);
+        }
+    }
+}
+
+// Creates the wgpu device of the desktop preview around its window, so the adapter is one that can
+// present to it
+pub struct DesktopContext {
+    // Declared first so it is dropped before its window
+    surface: Option<wgpu::Surface>,
+    window: Option<WindowThread>,
+}
+
+impl DesktopContext {
+    pub fn new(window: WindowThread) -> Self {
+        Self {
+            surface: None,
+            window: Some(window),
+        }
+    }
+
+    // Hands the window and its surface over to the preview once the device is loaded
+    pub fn take_window(&mut self) -> anyhow::Result<(WindowThread, wgpu::Surface)> {
+        let surface = self
+            .surface
+            .take()
+            .context("The desktop preview device is not loaded")?;
+        let window = self
+            .window
+            .take()
+            .context("The desktop preview window was already taken")?;
+        Ok((window, surface))
+    }
+}
+
+impl WgpuLoader for DesktopContext {
+    fn load_wgpu(&mut self) -> anyhow::Result<WgpuContext> {
+        let window = self
+            .window
+            .as_ref()
+            .context("The desktop preview window was already taken")?;
+        let wgpu_features = wgpu::Features::MULTIVIEW;
+        let wgpu_instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::VULKAN,
+            ..Default::default()
+        });
+        let surface = unsafe { wgpu_instance.create_surface(window.window.as_ref()) }?;
+        let wgpu_adapter =
+            pollster::block_on(wgpu_instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: Some(&surface),
+            }))
+            .context("No Vulkan adapter can present to the desktop preview window")?;
+        log::info!("Using desktop adapter {:?}", wgpu_adapter.get_info());
+
+        // wgpu can't enable extra extensions, open the device through wgpu-hal like the OpenXR
+        // loader so the loaders can import external memory
+        let hal_device = unsafe {
+            wgpu_adapter.as_hal::<Vulkan, _, _>(|adapter| {
+                adapter.map(|adapter| open_device(adapter, wgpu_features))
+            })
+        }
+        .context("The desktop adapter is not using Vulkan")??;
+        let (wgpu_device, wgpu_queue) = unsafe {
+            wgpu_adapter.create_device_from_hal(
+                hal_device,
+                &wgpu::DeviceDescriptor {
+                    label: Some("Desktop Device"),
+                    features: wgpu_features,
+                    // Full SBS sources are twice as wide as one eye, take what the adapter offers
+                    limits: wgpu_adapter.limits(),
+                },
+                None,
+            )
+        }?;
+
+        let (vk_entry, vk_instance_ptr) = unsafe { wgpu_instance.as_hal::<Vulkan>() }
+            .map(|instance| {
+                let shared_instance = instance.shared_instance();
+                (
+                    shared_instance.entry().clone(),
+                    shared_instance.raw_instance().handle().as_raw(),
+                )
+            })
+            .context("The desktop instance is not using Vulkan")?;
+        let vk_phys_device_ptr = unsafe {
+            wgpu_adapter.as_hal::<Vulkan, _, _>(|adapter| {
+                adapter.map(|adapter| adapter.raw_physical_device().as_raw())
+            })
+        }
+        .context("The desktop adapter is not using Vulkan")?;
+        let (vk_device_ptr, queue_index) = unsafe {
+            wgpu_device.as_hal::<Vulkan, _, _>(|device| {
+                device.map(|device| {
+                    (
+                        device.raw_device().handle().as_raw(),
+                        device.queue_family_index(),
+                    )
+                })
+            })
+        }
+        .context("The desktop device is not using Vulkan")?;
+
+        self.surface = Some(surface);
+        Ok(WgpuContext {
+            vk_entry,
+            vk_instance_ptr,
+            vk_phys_device_ptr,
+            vk_device_ptr,
+            queue_index,
+            instance: wgpu_instance,
+            device: wgpu_device,
+            physical_device: wgpu_adapter,
+            queue: wgpu_queue,
+        })
+    }
+}
+
+// Same device setup as the OpenXR loader, minus the runtime specific bits
+unsafe fn open_device(
+    adapter: &wgpu_hal::vulkan::Adapter,
+    wgpu_features: wgpu::Features,
+) -> anyhow::Result<OpenDevice<Vulkan>> {
+    let vk_instance = adapter.shared_instance().raw_instance();
+    let vk_physical_device = adapter.raw_physical_device();
+
+    // wgpu-hal checks presenting from the first family, the graphics one on desktop GPUs
+    let queue_family_index = vk_instance
+        .get_physical_device_queue_family_properties(vk_physical_device)
+        .into_iter()
+        .position(|info| info.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+        .context("Vulkan device has no graphics queue")? as u32;
+
+    let mut device_extensions = adapter.required_device_extensions(wgpu_features);
+    for extension in external_memory_extensions(vk_instance, vk_physical_device)? {
+        if !device_extensions.contains(&extension) {
+            device_extensions.push(extension);
+        }
+    }
+    log::info!("Requested device extensions: {:?}", device_extensions);
+
+    let queue_priorities = [1.0];
+    let family_info = vk::DeviceQueueCreateInfo::builder()
+        .queue_family_index(queue_family_index)
+        .queue_priorities(&queue_priorities)
+        .build();
+    let device_extensions_ptrs = device_extensions
+        .iter()
+        .map(|x| x.as_ptr())
+        .collect::<Vec<_>>();
+    // Also chains the multiview features requested through wgpu_features
+    let mut enabled_features = adapter.physical_device_features(&device_extensions, wgpu_features);
+    let family_infos = [family_info];
+    let device_create_info = enabled_features
+        .add_to_device_create_builder(
+            vk::DeviceCreateInfo::builder()
+                .enabled_extension_names(&device_extensions_ptrs)
+                .queue_create_infos(&family_infos),
+        )
+        .build();
+    let vk_device = vk_instance.create_device(vk_physical_device, &device_create_info, None)?;
+
+    Ok(adapter.device_from_raw(
+        vk_device,
+        true,
+        &device_extensions,
+        wgpu_features,
+        queue_family_index,
+        0,
+    )?)
+}
@@ -0,0 +1,206 @@
+use anyhow::Context;
+use ash::vk::Handle;
+use wgpu_hal::api::Vulkan;
+
+use super::{
+    texture::{Texture2D, Unbound},
+    vr::{SWAPCHAIN_COLOR_FORMAT, VIEW_COUNT},
+    WgpuContext, WgpuLoader,
+};
+
+// Distance between the eyes of the fixed headless views in meters
+pub const HEADLESS_IPD: f32 = 0.064;
+// Half of the horizontal and vertical field of view of the fixed headless views
+pub const HEADLESS_HALF_FOV: f32 = std::f32::consts::FRAC_PI_4;
+
+// Creates a plain wgpu Vulkan device without an OpenXR runtime, e.g. on lavapipe for CI
+#[derive(Default)]
+pub struct HeadlessContext {
+    // Only accept software adapters like lavapipe
+    pub force_fallback_adapter: bool,
+}
+
+impl WgpuLoader for HeadlessContext {
+    fn load_wgpu(&mut self) -> anyhow::Result<WgpuContext> {
+        let wgpu_instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::VULKAN,
+            ..Default::default()
+        });
+        let wgpu_adapter =
+            pollster::block_on(wgpu_instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: self.force_fallback_adapter,
+                compatible_surface: None,
+            }))
+            .context("No Vulkan adapter available")?;
+        log::info!("Using headless adapter {:?}", wgpu_adapter.get_info());
+
+        let (wgpu_device, wgpu_queue) = pollster::block_on(wgpu_adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("Headless Device"),
+                features: wgpu::Features::MULTIVIEW,
+                // Full SBS sources are twice as wide as a single eye, take what the adapter offers
+                limits: wgpu_adapter.limits(),
+            },
+            None,
+        ))?;
+
+        // The raw handles are only needed by loaders importing external memory
+        let (vk_entry, vk_instance_ptr) = unsafe { wgpu_instance.as_hal::<Vulkan>() }
+            .map(|instance| {
+                let shared_instance = instance.shared_instance();
+                (
+                    shared_instance.entry().clone(),
+                    shared_instance.raw_instance().handle().as_raw(),
+                )
+            })
+            .context("The headless instance is not using Vulkan")?;
+        let vk_phys_device_ptr = unsafe {
+            wgpu_adapter.as_hal::<Vulkan, _, _>(|adapter| {
+                adapter.map(|adapter| adapter.raw_physical_device().as_raw())
+            })
+        }
+        .context("The headless adapter is not using Vulkan")?;
+        let (vk_device_ptr, queue_index) = unsafe {
+            wgpu_device.as_hal::<Vulkan, _, _>(|device| {
+                device.map(|device| {
+                    (
+                        device.raw_device().handle().as_raw(),
+                        device.queue_family_index(),
+                    )
+                })
+            })
+        }
+        .context("The headless device is not using Vulkan")?;
+
+        Ok(WgpuContext {
+            vk_entry,
+            vk_instance_ptr,
+            vk_phys_device_ptr,
+            vk_device_ptr,
+            queue_index,
+            instance: wgpu_instance,
+            device: wgpu_device,
+            physical_device: wgpu_adapter,
+            queue: wgpu_queue,
+        })
+    }
+}
+
+// Fixed views looking down -Z from the origin, like a headset right after recentering
+pub fn headless_views() -> [openxr::View; 2] {
+    let fov = openxr::Fovf {
+        angle_left: -HEADLESS_HALF_FOV,
+        angle_right: HEADLESS_HALF_FOV,
+        angle_up: HEADLESS_HALF_FOV,
+        angle_down: -HEADLESS_HALF_FOV,
+    };
+    [-0.5, 0.5].map(|side| openxr::View {
+        pose: openxr::Posef {
+            orientation: openxr::Posef::IDENTITY.orientation,
+            position: openxr::Vector3f {
+                x: side * HEADLESS_IPD,
+                y: 0.0,
+                z: 0.0,
+            },
+        },
+        fov,
+    })
+}
+
+// Two layer render target standing in for the OpenXR swapchain
+pub struct OffscreenTarget {
+    pub texture: Texture2D<Unbound>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl OffscreenTarget {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: VIEW_COUNT,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SWAPCHAIN_COLOR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        Self {
+            texture: Texture2D::<Unbound>::from_wgpu(device, texture),
+            width,
+            height,
+        }
+    }
+
+    // Copies every layer back to the CPU, pixels are sRGB encoded like the swapchain images
+    pub fn read_layers(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Vec<image::RgbaImage>> {
+        let unpadded_bytes_per_row = self.width * 4;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let layer_size = (padded_bytes_per_row * self.height) as usize;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Readback Buffer"),
+            size: (layer_size * VIEW_COUNT as usize) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Offscreen Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            self.texture.texture.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let data = slice.get_mapped_range();
+        let layers = data
+            .chunks_exact(layer_size)
+            .map(|layer| {
+                let mut pixels =
+                    Vec::with_capacity((unpadded_bytes_per_row * self.height) as usize);
+                for row in layer.chunks_exact(padded_bytes_per_row as usize) {
+                    // Swapchain images are BGRA
+                    for pixel in row[..unpadded_bytes_per_row as usize].chunks_exact(4) {
+                        pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                    }
+                }
+                image::RgbaImage::from_raw(self.width, self.height, pixels)
+                    .context("Readback size mismatch")
+            })
+            .collect();
+        drop(data);
+        buffer.unmap();
+        layers
+    }
+}
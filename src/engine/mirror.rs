@@ -0,0 +1,509 @@
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, TryRecvError},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+use anyhow::Context;
+use wgpu::util::DeviceExt;
+#[cfg(target_os = "windows")]
+use winit::platform::windows::EventLoopBuilderExtWindows;
+#[cfg(target_os = "linux")]
+use winit::platform::x11::EventLoopBuilderExtX11;
+use winit::{
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
+    event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy},
+    platform::run_return::EventLoopExtRunReturn,
+    window::{Window, WindowBuilder},
+};
+
+use crate::config::MirrorMode;
+
+use super::WgpuContext;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MirrorParamsUniform {
+    mode: u32,
+    _padding: [u32; 3],
+}
+
+impl MirrorParamsUniform {
+    fn new(mode: MirrorMode) -> Self {
+        Self {
+            mode: match mode {
+                MirrorMode::Left => 0,
+                MirrorMode::Right => 1,
+                MirrorMode::Both => 2,
+                MirrorMode::SideBySide => 3,
+            },
+            _padding: [0; 3],
+        }
+    }
+}
+
+// Window events the render loop cares about
+enum WindowInput {
+    Resized(PhysicalSize<u32>),
+    Closed,
+    CycleMode,
+    Look(f32, f32),
+}
+
+// Window running its event loop on a dedicated thread: moving or resizing a window enters a modal
+// loop on Windows, which would otherwise stall the frame loop polling it
+pub struct WindowThread {
+    pub window: Arc<Window>,
+    inputs: Receiver<WindowInput>,
+    proxy: EventLoopProxy<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WindowThread {
+    pub fn spawn(title: String, size: LogicalSize<u32>) -> anyhow::Result<Self> {
+        let (setup_sender, setup_receiver) = channel();
+        let (input_sender, inputs) = channel();
+        let thread = std::thread::Builder::new()
+            .name("Window Events".to_owned())
+            .spawn(move || {
+                let mut builder = EventLoopBuilder::new();
+                #[cfg(any(target_os = "windows", target_os = "linux"))]
+                builder.with_any_thread(true);
+                let mut event_loop = builder.build();
+                let window = match WindowBuilder::new()
+                    .with_title(title)
+                    .with_inner_size(size)
+                    .build(&event_loop)
+                {
+                    Ok(window) => Arc::new(window),
+                    Err(err) => {
+                        let _ = setup_sender.send(Err(err.to_string()));
+                        return;
+                    }
+                };
+                let _ = setup_sender.send(Ok((window.clone(), event_loop.create_proxy())));
+
+                let mut cursor_position: Option<PhysicalPosition<f64>> = None;
+                let mut dragging = false;
+                event_loop.run_return(|event, _, control_flow| {
+                    *control_flow = ControlFlow::Wait;
+                    let input = match event {
+                        // Sent when the owner goes away
+                        Event::UserEvent(()) => {
+                            *control_flow = ControlFlow::Exit;
+                            None
+                        }
+                        Event::WindowEvent { event, .. } => match event {
+                            WindowEvent::Resized(size) => Some(WindowInput::Resized(size)),
+                            WindowEvent::CloseRequested => Some(WindowInput::Closed),
+                            WindowEvent::KeyboardInput {
+                                input:
+                                    KeyboardInput {
+                                        state: ElementState::Pressed,
+                                        virtual_keycode: Some(VirtualKeyCode::M),
+                                        ..
+                                    },
+                                ..
+                            } => Some(WindowInput::CycleMode),
+                            WindowEvent::MouseInput {
+                                state,
+                                button: MouseButton::Left,
+                                ..
+                            } => {
+                                dragging = state == ElementState::Pressed;
+                                None
+                            }
+                            WindowEvent::CursorMoved { position, .. } => {
+                                let last_position = cursor_position.replace(position);
+                                match (dragging, last_position) {
+                                    (true, Some(last_position)) => Some(WindowInput::Look(
+                                        (position.x - last_position.x) as f32,
+                                        (position.y - last_position.y) as f32,
+                                    )),
+                                    _ => None,
+                                }
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if let Some(input) = input {
+                        let _ = input_sender.send(input);
+                    }
+                });
+            })?;
+
+        let (window, proxy) = setup_receiver
+            .recv()
+            .context("The window thread exited early")?
+            .map_err(|err| anyhow::anyhow!("Cannot create the window: {}", err))?;
+        Ok(Self {
+            window,
+            inputs,
+            proxy,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for WindowThread {
+    fn drop(&mut self) {
+        let _ = self.proxy.send_event(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Desktop window showing the swapchain layers, presented without vsync and fed by the window
+// thread so it never holds back the XR frame loop
+pub struct MirrorWindow {
+    // The surface must be dropped before its window
+    surface: wgpu::Surface,
+    surface_config: wgpu::SurfaceConfiguration,
+    window: WindowThread,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    params_buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+    title: &'static str,
+    mode: MirrorMode,
+    interval: u32,
+    frame: u32,
+    look_delta: (f32, f32),
+}
+
+const MIRROR_TITLE: &str = "VRScreenCap Mirror";
+const PREVIEW_TITLE: &str = "VRScreenCap Preview";
+
+fn window_title(title: &str, mode: MirrorMode) -> String {
+    format!("{} ({:?})", title, mode)
+}
+
+fn window_size(mode: MirrorMode) -> LogicalSize<u32> {
+    match mode {
+        MirrorMode::SideBySide => LogicalSize::new(1600, 800),
+        _ => LogicalSize::new(800, 800),
+    }
+}
+
+impl MirrorWindow {
+    pub fn new(
+        wgpu_context: &WgpuContext,
+        mode: MirrorMode,
+        interval: u32,
+    ) -> anyhow::Result<Self> {
+        let window = WindowThread::spawn(window_title(MIRROR_TITLE, mode), window_size(mode))?;
+        let surface = unsafe { wgpu_context.instance.create_surface(window.window.as_ref()) }?;
+        Self::create(
+            wgpu_context,
+            window,
+            surface,
+            MIRROR_TITLE,
+            mode,
+            interval,
+            wgpu::PresentMode::AutoNoVsync,
+        )
+    }
+
+    // Opened before the device of the desktop preview, so its adapter can present to it
+    pub fn preview_window(mode: MirrorMode) -> anyhow::Result<WindowThread> {
+        WindowThread::spawn(window_title(PREVIEW_TITLE, mode), window_size(mode))
+    }
+
+    // Window the desktop preview renders into, synchronized with the display since nothing else
+    // paces the frames
+    pub fn preview(
+        wgpu_context: &WgpuContext,
+        window: WindowThread,
+        surface: wgpu::Surface,
+        mode: MirrorMode,
+    ) -> anyhow::Result<Self> {
+        Self::create(
+            wgpu_context,
+            window,
+            surface,
+            PREVIEW_TITLE,
+            mode,
+            1,
+            wgpu::PresentMode::AutoVsync,
+        )
+    }
+
+    fn create(
+        wgpu_context: &WgpuContext,
+        window: WindowThread,
+        surface: wgpu::Surface,
+        title: &'static str,
+        mode: MirrorMode,
+        interval: u32,
+        present_mode: wgpu::PresentMode,
+    ) -> anyhow::Result<Self> {
+        let device = &wgpu_context.device;
+        let capabilities = surface.get_capabilities(&wgpu_context.physical_device);
+        // Swapchain images hold sRGB encoded values in a linear format, copy them as they are
+        let format = capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(|format| !format.is_srgb())
+            .or_else(|| capabilities.formats.first().copied())
+            .context("The mirror window surface has no supported format")?;
+        let size = window.window.inner_size();
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode,
+            alpha_mode: capabilities
+                .alpha_modes
+                .first()
+                .copied()
+                .unwrap_or(wgpu::CompositeAlphaMode::Auto),
+            view_formats: vec![],
+        };
+        surface.configure(device, &surface_config);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Mirror Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mirror Params Buffer"),
+            contents: bytemuck::cast_slice(&[MirrorParamsUniform::new(mode)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../mirror.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mirror Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mirror Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            surface,
+            surface_config,
+            window,
+            bind_group_layout,
+            sampler,
+            params_buffer,
+            pipeline,
+            title,
+            mode,
+            interval: interval.max(1),
+            frame: 0,
+            look_delta: (0.0, 0.0),
+        })
+    }
+
+    // Handles the events forwarded by the window thread without waiting for new ones, returns
+    // false once the window was closed
+    pub fn poll_events(&mut self, wgpu_context: &WgpuContext) -> bool {
+        let mut resized = None;
+        let mut cycle_mode = false;
+        let mut closed = false;
+        loop {
+            match self.window.inputs.try_recv() {
+                Ok(WindowInput::Resized(size)) => resized = Some(size),
+                Ok(WindowInput::Closed) | Err(TryRecvError::Disconnected) => {
+                    closed = true;
+                    break;
+                }
+                Ok(WindowInput::CycleMode) => cycle_mode = true,
+                Ok(WindowInput::Look(x, y)) => {
+                    self.look_delta.0 += x;
+                    self.look_delta.1 += y;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        if let Some(size) = resized {
+            // Minimized windows report a zero size, keep the last configuration until restored
+            if size.width > 0 && size.height > 0 {
+                self.surface_config.width = size.width;
+                self.surface_config.height = size.height;
+                self.surface
+                    .configure(&wgpu_context.device, &self.surface_config);
+            }
+        }
+        if cycle_mode {
+            self.set_mode(wgpu_context, self.mode.next());
+        }
+        !closed
+    }
+
+    pub fn set_mode(&mut self, wgpu_context: &WgpuContext, mode: MirrorMode) {
+        self.mode = mode;
+        self.window
+            .window
+            .set_title(&window_title(self.title, mode));
+        wgpu_context.queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[MirrorParamsUniform::new(mode)]),
+        );
+    }
+
+    // Pixels the mouse was dragged by since the last call
+    pub fn take_look_delta(&mut self) -> (f32, f32) {
+        std::mem::take(&mut self.look_delta)
+    }
+
+    // Aspect ratio each eye is shown with
+    pub fn eye_aspect_ratio(&self) -> f32 {
+        let width = match self.mode {
+            MirrorMode::SideBySide => self.surface_config.width / 2,
+            _ => self.surface_config.width,
+        };
+        width.max(1) as f32 / self.surface_config.height as f32
+    }
+
+    // Copies the layers of a rendered swapchain image to the window every interval frames, must
+    // be called after the frame was submitted and before the image is released
+    pub fn present(&mut self, wgpu_context: &WgpuContext, layers: &wgpu::TextureView) {
+        self.frame = self.frame.wrapping_add(1);
+        if self.frame % self.interval != 0 {
+            return;
+        }
+
+        let surface_texture = match self.surface.get_current_texture() {
+            Ok(surface_texture) => surface_texture,
+            Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+                self.surface
+                    .configure(&wgpu_context.device, &self.surface_config);
+                return;
+            }
+            Err(err) => {
+                log::warn!("Skipping mirror frame: {}", err);
+                return;
+            }
+        };
+        let surface_view = surface_texture
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group = wgpu_context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Mirror Bind Group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(layers),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.params_buffer.as_entire_binding(),
+                    },
+                ],
+            });
+
+        let mut encoder =
+            wgpu_context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Mirror Encoder"),
+                });
+        {
+            let mut mirror_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mirror Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &surface_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            mirror_pass.set_pipeline(&self.pipeline);
+            mirror_pass.set_bind_group(0, &bind_group, &[]);
+            mirror_pass.draw(0..3, 0..1);
+        }
+        // The runtime expects released images in the color attachment layout, not the sampled one
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mirror Layout Restore Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: layers,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        wgpu_context.queue.submit(Some(encoder.finish()));
+        surface_texture.present();
+    }
+}
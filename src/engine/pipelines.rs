@@ -0,0 +1,444 @@
+use std::num::NonZeroU32;
+
+use wgpu::util::DeviceExt;
+
+use crate::config::{ScreenParamsUniform, TemporalBlurParamsUniform};
+
+use super::{
+    camera::CameraUniform,
+    entity::ModelUniform,
+    geometry::{ModelVertex, Vertex},
+    screen::Screen,
+    texture::{Bound, RoundRobinTextureBuffer, Texture2D},
+    vr::{SWAPCHAIN_COLOR_FORMAT, VIEW_COUNT},
+    yuv::{planar_bind_group_layout, YuvPlanes},
+};
+
+// Shaders, layouts and pipelines used to draw the screen and the ambient dome, shared by every
+// frontend rendering into multiview SWAPCHAIN_COLOR_FORMAT targets
+pub struct ScreenPipelines {
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    pub planar_texture_bind_group_layout: wgpu::BindGroupLayout,
+    global_uniform_bind_group_layout: wgpu::BindGroupLayout,
+    global_temporal_blur_uniform_layout: wgpu::BindGroupLayout,
+    screen_render_pipeline: wgpu::RenderPipeline,
+    planar_screen_render_pipeline: wgpu::RenderPipeline,
+    ambient_dome_pipeline: wgpu::RenderPipeline,
+    temporal_blur_pipeline: wgpu::RenderPipeline,
+    planar_temporal_blur_pipeline: wgpu::RenderPipeline,
+    fullscreen_triangle_index_buffer: wgpu::Buffer,
+}
+
+// Uniform buffers of a scene and the bind groups exposing them to the pipelines
+pub struct SceneUniforms {
+    pub camera_buffer: wgpu::Buffer,
+    pub screen_params_buffer: wgpu::Buffer,
+    pub screen_model_matrix_buffer: wgpu::Buffer,
+    pub temporal_blur_params_buffer: wgpu::Buffer,
+    global_uniform_bind_group: wgpu::BindGroup,
+    global_temporal_blur_uniform_bind_group: wgpu::BindGroup,
+}
+
+// Textures sampled by the screen for the current frame
+pub struct ScreenSources<'a> {
+    pub left_eye: &'a Texture2D<Bound>,
+    pub right_eye: &'a Texture2D<Bound>,
+    pub planes: Option<&'a YuvPlanes<Bound>>,
+}
+
+fn uniform_layout_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn color_target() -> Option<wgpu::ColorTargetState> {
+    Some(wgpu::ColorTargetState {
+        format: SWAPCHAIN_COLOR_FORMAT,
+        blend: Some(wgpu::BlendState::REPLACE),
+        write_mask: wgpu::ColorWrites::ALL,
+    })
+}
+
+impl ScreenPipelines {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let screen_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shader.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("../yuv.wgsl"), include_str!("../shader.wgsl")).into(),
+            ),
+        });
+        let blit_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("blit.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("../yuv.wgsl"), include_str!("../blit.wgsl")).into(),
+            ),
+        });
+
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        // This should match the filterable field of the
+                        // corresponding Texture entry above.
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("texture_bind_group_layout"),
+            });
+        let planar_texture_bind_group_layout = planar_bind_group_layout(device);
+
+        let global_temporal_blur_uniform_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[uniform_layout_entry(0, wgpu::ShaderStages::FRAGMENT)],
+                label: Some("global_temporal_blur_bind_group_layout"),
+            });
+
+        let global_uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_layout_entry(0, wgpu::ShaderStages::VERTEX),
+                    uniform_layout_entry(1, wgpu::ShaderStages::VERTEX_FRAGMENT),
+                    uniform_layout_entry(2, wgpu::ShaderStages::VERTEX),
+                ],
+                label: Some("global_uniform_bind_group_layout"),
+            });
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &global_uniform_bind_group_layout,
+                    &texture_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let planar_render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Planar Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    &planar_texture_bind_group_layout,
+                    &global_uniform_bind_group_layout,
+                    &texture_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let blit_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &texture_bind_group_layout,
+                &global_temporal_blur_uniform_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let planar_blit_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Planar Blit Pipeline Layout"),
+                bind_group_layouts: &[
+                    &planar_texture_bind_group_layout,
+                    &texture_bind_group_layout,
+                    &global_temporal_blur_uniform_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let scene_pipeline = |label: &str,
+                              layout: &wgpu::PipelineLayout,
+                              vertex_entry_point: &str,
+                              fragment_entry_point: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &screen_shader,
+                    entry_point: vertex_entry_point,
+                    buffers: &[ModelVertex::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &screen_shader,
+                    entry_point: fragment_entry_point,
+                    targets: &[color_target()],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: NonZeroU32::new(VIEW_COUNT),
+            })
+        };
+
+        // The blur writes both the current ambient texture and the history it is blended with
+        let blit_pipeline =
+            |label: &str, layout: &wgpu::PipelineLayout, fragment_entry_point: &str| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(label),
+                    layout: Some(layout),
+                    vertex: wgpu::VertexState {
+                        module: &blit_shader,
+                        entry_point: "vs_main",
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &blit_shader,
+                        entry_point: fragment_entry_point,
+                        targets: &[color_target(), color_target()],
+                    }),
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                })
+            };
+
+        let screen_render_pipeline = scene_pipeline(
+            "Render Pipeline",
+            &render_pipeline_layout,
+            "vs_main",
+            "fs_main",
+        );
+        let planar_screen_render_pipeline = scene_pipeline(
+            "Planar Render Pipeline",
+            &planar_render_pipeline_layout,
+            "vs_main",
+            "yuv_fs_main",
+        );
+        let ambient_dome_pipeline = scene_pipeline(
+            "Ambient Dome Pipeline",
+            &render_pipeline_layout,
+            "mv_vs_main",
+            "vignette_fs_main",
+        );
+        let temporal_blur_pipeline =
+            blit_pipeline("Blit Pipeline", &blit_pipeline_layout, "temporal_fs_main");
+        let planar_temporal_blur_pipeline = blit_pipeline(
+            "Planar Blit Pipeline",
+            &planar_blit_pipeline_layout,
+            "yuv_temporal_fs_main",
+        );
+
+        let fullscreen_triangle_index_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Fullscreen Tri Index Buffer"),
+                contents: bytemuck::cast_slice(&[0, 1, 2]),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+
+        Self {
+            texture_bind_group_layout,
+            planar_texture_bind_group_layout,
+            global_uniform_bind_group_layout,
+            global_temporal_blur_uniform_layout,
+            screen_render_pipeline,
+            planar_screen_render_pipeline,
+            ambient_dome_pipeline,
+            temporal_blur_pipeline,
+            planar_temporal_blur_pipeline,
+            fullscreen_triangle_index_buffer,
+        }
+    }
+
+    pub fn create_uniforms(
+        &self,
+        device: &wgpu::Device,
+        cameras: &[CameraUniform],
+        screen_params: ScreenParamsUniform,
+        screen_model: ModelUniform,
+        temporal_blur_params: TemporalBlurParamsUniform,
+    ) -> SceneUniforms {
+        let uniform_buffer = |label: &str, contents: &[u8]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            })
+        };
+        let camera_buffer = uniform_buffer("Camera Buffer", bytemuck::cast_slice(cameras));
+        let screen_params_buffer = uniform_buffer(
+            "Screen Params Buffer",
+            bytemuck::cast_slice(&[screen_params]),
+        );
+        let screen_model_matrix_buffer = uniform_buffer(
+            "Screen Model Matrix Buffer",
+            bytemuck::cast_slice(&[screen_model]),
+        );
+        let temporal_blur_params_buffer = uniform_buffer(
+            "Temporal Blur Params Buffer",
+            bytemuck::cast_slice(&[temporal_blur_params]),
+        );
+
+        let global_uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.global_uniform_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: screen_params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: screen_model_matrix_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("global_uniform_bind_group"),
+        });
+
+        let global_temporal_blur_uniform_bind_group =
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.global_temporal_blur_uniform_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: temporal_blur_params_buffer.as_entire_binding(),
+                }],
+                label: Some("global_temporal_blur_uniform_bind_group"),
+            });
+
+        SceneUniforms {
+            camera_buffer,
+            screen_params_buffer,
+            screen_model_matrix_buffer,
+            temporal_blur_params_buffer,
+            global_uniform_bind_group,
+            global_temporal_blur_uniform_bind_group,
+        }
+    }
+
+    // Blurs the screen into the current ambient texture, the caller advances the buffer first
+    pub fn encode_ambient_blur(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        screen_texture: &Texture2D<Bound>,
+        planes: Option<&YuvPlanes<Bound>>,
+        ambient_texture: &RoundRobinTextureBuffer<Texture2D<Bound>, 3>,
+        uniforms: &SceneUniforms,
+    ) {
+        let mut blit_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &ambient_texture.current().view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &ambient_texture.previous(1).view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }),
+            ],
+            depth_stencil_attachment: None,
+        });
+
+        match planes {
+            Some(planes) => {
+                blit_pass.set_pipeline(&self.planar_temporal_blur_pipeline);
+                blit_pass.set_bind_group(0, planes.bind_group(), &[]);
+            }
+            None => {
+                blit_pass.set_pipeline(&self.temporal_blur_pipeline);
+                blit_pass.set_bind_group(0, screen_texture.bind_group(), &[]);
+            }
+        }
+        blit_pass.set_bind_group(1, ambient_texture.previous(2).bind_group(), &[]);
+        blit_pass.set_bind_group(2, &uniforms.global_temporal_blur_uniform_bind_group, &[]);
+        blit_pass.set_index_buffer(
+            self.fullscreen_triangle_index_buffer.slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+        blit_pass.draw_indexed(0..3, 0, 0..1);
+    }
+
+    // Draws the ambient dome (when given) and the screen into both layers of target
+    pub fn encode_scene(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        screen: &Screen,
+        sources: &ScreenSources,
+        ambient_texture: Option<&Texture2D<Bound>>,
+        uniforms: &SceneUniforms,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        // Render the ambient dome
+        if let Some(ambient_texture) = ambient_texture {
+            let ambient_mesh = &screen.ambient_mesh;
+            rpass.set_pipeline(&self.ambient_dome_pipeline);
+            rpass.set_bind_group(0, ambient_texture.bind_group(), &[]);
+            rpass.set_bind_group(1, &uniforms.global_uniform_bind_group, &[]);
+            rpass.set_bind_group(2, ambient_texture.bind_group(), &[]);
+            rpass.set_vertex_buffer(0, ambient_mesh.vertex_buffer().slice(..));
+            rpass.set_index_buffer(
+                ambient_mesh.index_buffer().slice(..),
+                wgpu::IndexFormat::Uint32,
+            );
+            rpass.draw_indexed(0..ambient_mesh.indices(), 0, 0..1);
+        }
+
+        // Render the screen
+        match sources.planes {
+            Some(planes) => {
+                rpass.set_pipeline(&self.planar_screen_render_pipeline);
+                rpass.set_bind_group(0, planes.bind_group(), &[]);
+            }
+            None => {
+                rpass.set_pipeline(&self.screen_render_pipeline);
+                rpass.set_bind_group(0, sources.left_eye.bind_group(), &[]);
+            }
+        }
+        rpass.set_bind_group(1, &uniforms.global_uniform_bind_group, &[]);
+        rpass.set_bind_group(2, sources.right_eye.bind_group(), &[]);
+        rpass.set_vertex_buffer(0, screen.mesh.vertex_buffer().slice(..));
+        rpass.set_index_buffer(
+            screen.mesh.index_buffer().slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+        rpass.draw_indexed(0..screen.mesh.indices(), 0, 0..1);
+    }
+}
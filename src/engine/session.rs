@@ -0,0 +1,767 @@
+use anyhow::Context;
+use cgmath::Rotation3;
+use openxr::ReferenceSpaceType;
+
+use super::{
+    input::InputContext,
+    texture::{Texture2D, Unbound},
+    vr::{OpenXRContext, VIEW_TYPE},
+    WgpuContext,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecenterRequest {
+    pub delay: i64,
+    pub horizon_locked: bool,
+}
+
+// OpenXR events the frame loop reacts to, anything else is treated like no event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionEvent {
+    StateChanged(openxr::SessionState),
+    InstanceLossPending,
+    EventsLost(u32),
+    ReferenceSpaceChangePending,
+}
+
+// What the frame loop needs from an OpenXR session, so it can be driven by a scripted session
+// in tests
+pub trait XrSession {
+    // What frames are rendered into
+    type Target;
+
+    fn poll_event(&mut self) -> anyhow::Result<Option<SessionEvent>>;
+    fn begin(&mut self) -> anyhow::Result<()>;
+    fn end(&mut self) -> anyhow::Result<()>;
+    // Blocks until the runtime is ready for another frame and begins it
+    fn begin_frame(&mut self) -> anyhow::Result<openxr::FrameState>;
+    // Ends the current frame without any layer
+    fn skip_frame(&mut self, frame_state: &openxr::FrameState) -> anyhow::Result<()>;
+    fn locate_views(
+        &mut self,
+        frame_state: &openxr::FrameState,
+    ) -> anyhow::Result<Vec<openxr::View>>;
+    // Waits for the next swapchain image, the swapchain is created on first use
+    fn acquire_image(&mut self) -> anyhow::Result<&Self::Target>;
+    // Releases the swapchain image and submits it for the given views
+    fn end_frame(
+        &mut self,
+        frame_state: &openxr::FrameState,
+        views: &[openxr::View],
+    ) -> anyhow::Result<()>;
+    // Follows the runtime LOCAL space again
+    fn reset_space(&mut self) -> anyhow::Result<()>;
+    fn recenter(
+        &mut self,
+        frame_state: &openxr::FrameState,
+        request: RecenterRequest,
+    ) -> anyhow::Result<()>;
+    // Looks for the hands near head gesture asking for a recenter
+    fn poll_gesture(&mut self, frame_state: &openxr::FrameState) -> Option<RecenterRequest>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopControl {
+    Continue,
+    Exit,
+}
+
+// Session state handling shared by every XrSession
+#[derive(Default)]
+pub struct FrameLoop {
+    session_running: bool,
+    recenter_request: Option<RecenterRequest>,
+}
+
+impl FrameLoop {
+    // Applied after the next rendered frame
+    pub fn request_recenter(&mut self, horizon_locked: bool) {
+        self.recenter_request = Some(RecenterRequest {
+            horizon_locked,
+            delay: 0,
+        });
+    }
+
+    pub fn is_session_running(&self) -> bool {
+        self.session_running
+    }
+
+    // Handles one pending event, or renders a frame when there is none and the session is running
+    pub fn step<S: XrSession>(
+        &mut self,
+        session: &mut S,
+        mut render: impl FnMut(&[openxr::View], &S::Target, std::time::Duration) -> anyhow::Result<()>,
+    ) -> anyhow::Result<LoopControl> {
+        match session.poll_event()? {
+            Some(SessionEvent::StateChanged(state)) => {
+                // Session state change is where we can begin and end sessions, as well as
+                // find quit messages!
+                log::info!("Entered state {:?}", state);
+                match state {
+                    openxr::SessionState::READY => {
+                        session.begin()?;
+                        self.session_running = true;
+                    }
+                    openxr::SessionState::STOPPING => {
+                        session.end()?;
+                        self.session_running = false;
+                    }
+                    openxr::SessionState::EXITING => {
+                        return Ok(LoopControl::Exit);
+                    }
+                    _ => {}
+                }
+            }
+            Some(SessionEvent::InstanceLossPending) => {}
+            Some(SessionEvent::EventsLost(count)) => {
+                log::error!("Lost {} OpenXR events", count);
+            }
+            Some(SessionEvent::ReferenceSpaceChangePending) => {
+                //Reset XR space to follow runtime
+                session.reset_space()?;
+            }
+            None => {
+                // Render to HMD only if we have an active session
+                if self.session_running {
+                    self.render_frame(session, &mut render)?;
+                }
+
+                #[cfg(feature = "profiling")]
+                profiling::finish_frame!();
+            }
+        }
+        Ok(LoopControl::Continue)
+    }
+
+    fn render_frame<S: XrSession>(
+        &mut self,
+        session: &mut S,
+        render: &mut impl FnMut(&[openxr::View], &S::Target, std::time::Duration) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let frame_state = session.begin_frame()?;
+
+        // Only render if we should
+        if !frame_state.should_render {
+            return session.skip_frame(&frame_state);
+        }
+
+        #[cfg(feature = "profiling")]
+        profiling::scope!("Locate Views");
+        log::trace!("Locate views");
+        // Fetch the view transforms as late as possible, right before recording the scene, so
+        // the uniforms written by the renderer use the freshest prediction.
+        let views = session.locate_views(&frame_state)?;
+
+        let target = session.acquire_image()?;
+        render(
+            &views,
+            target,
+            std::time::Duration::from_nanos(frame_state.predicted_display_time.as_nanos() as u64),
+        )?;
+        session.end_frame(&frame_state, &views)?;
+
+        if self.recenter_request.is_none() {
+            self.recenter_request = session.poll_gesture(&frame_state);
+        }
+
+        if let Some(recenter_request) = self.recenter_request.take() {
+            if let Err(err) = session.recenter(&frame_state, recenter_request) {
+                log::error!("Failed to recenter scene: {}", err);
+            }
+        }
+        Ok(())
+    }
+}
+
+type Swapchain = (
+    openxr::Swapchain<openxr::Vulkan>,
+    ash::vk::Extent2D,
+    Vec<Texture2D<Unbound>>,
+);
+
+// XrSession backed by the OpenXR runtime
+pub struct OpenXRSession<'a> {
+    xr_context: &'a OpenXRContext,
+    wgpu_context: &'a WgpuContext,
+    session: openxr::Session<openxr::Vulkan>,
+    frame_wait: openxr::FrameWaiter,
+    frame_stream: openxr::FrameStream<openxr::Vulkan>,
+    reference_space: openxr::Space,
+    view_space: openxr::Space,
+    space: openxr::Space,
+    swapchain: Option<Swapchain>,
+    input_context: Option<InputContext>,
+    event_storage: openxr::EventDataBuffer,
+}
+
+impl<'a> OpenXRSession<'a> {
+    pub fn new(
+        xr_context: &'a OpenXRContext,
+        wgpu_context: &'a WgpuContext,
+    ) -> anyhow::Result<Self> {
+        // Start the OpenXR session
+        let (session, frame_wait, frame_stream) = unsafe {
+            xr_context.instance.create_session::<openxr::Vulkan>(
+                xr_context.system,
+                &openxr::vulkan::SessionCreateInfo {
+                    instance: wgpu_context.vk_instance_ptr as _,
+                    physical_device: wgpu_context.vk_phys_device_ptr as _,
+                    device: wgpu_context.vk_device_ptr as _,
+                    queue_family_index: wgpu_context.queue_index,
+                    queue_index: 0,
+                },
+            )?
+        };
+
+        // Create a room-scale reference space
+        let reference_space =
+            session.create_reference_space(ReferenceSpaceType::LOCAL, openxr::Posef::IDENTITY)?;
+        let view_space =
+            session.create_reference_space(ReferenceSpaceType::VIEW, openxr::Posef::IDENTITY)?;
+        let space =
+            session.create_reference_space(ReferenceSpaceType::LOCAL, openxr::Posef::IDENTITY)?;
+
+        let input_context =
+            InputContext::init(&xr_context.instance)
+                .ok()
+                .and_then(|mut input_context| {
+                    input_context
+                        .attach_to_session(&session)
+                        .ok()
+                        .map(|_| input_context)
+                });
+
+        Ok(Self {
+            xr_context,
+            wgpu_context,
+            session,
+            frame_wait,
+            frame_stream,
+            reference_space,
+            view_space,
+            space,
+            swapchain: None,
+            input_context,
+            event_storage: openxr::EventDataBuffer::new(),
+        })
+    }
+}
+
+impl<'a> XrSession for OpenXRSession<'a> {
+    type Target = wgpu::TextureView;
+
+    fn poll_event(&mut self) -> anyhow::Result<Option<SessionEvent>> {
+        let event = self
+            .xr_context
+            .instance
+            .poll_event(&mut self.event_storage)?;
+        Ok(match event {
+            Some(openxr::Event::SessionStateChanged(e)) => {
+                Some(SessionEvent::StateChanged(e.state()))
+            }
+            Some(openxr::Event::InstanceLossPending(_)) => Some(SessionEvent::InstanceLossPending),
+            Some(openxr::Event::EventsLost(e)) => {
+                Some(SessionEvent::EventsLost(e.lost_event_count()))
+            }
+            Some(openxr::Event::ReferenceSpaceChangePending(_)) => {
+                Some(SessionEvent::ReferenceSpaceChangePending)
+            }
+            _ => None,
+        })
+    }
+
+    fn begin(&mut self) -> anyhow::Result<()> {
+        Ok(self.session.begin(VIEW_TYPE)?)
+    }
+
+    fn end(&mut self) -> anyhow::Result<()> {
+        Ok(self.session.end()?)
+    }
+
+    fn begin_frame(&mut self) -> anyhow::Result<openxr::FrameState> {
+        // Block until the previous frame is finished displaying, and is ready for another one.
+        // Also returns a prediction of when the next frame will be displayed, for use with
+        // predicting locations of controllers, viewpoints, etc.
+        #[cfg(feature = "profiling")]
+        profiling::scope!("Wait for frame");
+        let frame_state = self.frame_wait.wait()?;
+
+        // Must be called before any rendering is done!
+        self.frame_stream.begin()?;
+        Ok(frame_state)
+    }
+
+    fn skip_frame(&mut self, frame_state: &openxr::FrameState) -> anyhow::Result<()> {
+        if let Err(err) = self.frame_stream.end(
+            frame_state.predicted_display_time,
+            self.xr_context.blend_mode,
+            &[],
+        ) {
+            log::error!(
+                "Failed to end frame stream when should_render is FALSE : {:?}",
+                err
+            );
+        };
+        Ok(())
+    }
+
+    fn locate_views(
+        &mut self,
+        frame_state: &openxr::FrameState,
+    ) -> anyhow::Result<Vec<openxr::View>> {
+        let (_, views) = self.session.locate_views(
+            VIEW_TYPE,
+            frame_state.predicted_display_time,
+            &self.space,
+        )?;
+        Ok(views)
+    }
+
+    fn acquire_image(&mut self) -> anyhow::Result<&wgpu::TextureView> {
+        #[cfg(feature = "profiling")]
+        profiling::scope!("Swapchain Setup");
+
+        // If we do not have a swapchain yet, create it
+        let (xr_swapchain, _, swapchain_textures) = match self.swapchain {
+            Some(ref mut swapchain) => swapchain,
+            None => {
+                let new_swapchain = self
+                    .xr_context
+                    .create_swapchain(&self.session, &self.wgpu_context.device)?;
+                self.swapchain.get_or_insert(new_swapchain)
+            }
+        };
+        // Check which image we need to render to and wait until the compositor is
+        // done with this image
+        let image_index = xr_swapchain.acquire_image()?;
+        xr_swapchain.wait_image(openxr::Duration::INFINITE)?;
+
+        Ok(&swapchain_textures
+            .get(image_index as usize)
+            .context("Invalid swapchain image index")?
+            .view)
+    }
+
+    fn end_frame(
+        &mut self,
+        frame_state: &openxr::FrameState,
+        views: &[openxr::View],
+    ) -> anyhow::Result<()> {
+        let (xr_swapchain, resolution, _) = self
+            .swapchain
+            .as_mut()
+            .context("No swapchain image was acquired")?;
+
+        #[cfg(feature = "profiling")]
+        profiling::scope!("Release Swapchain");
+        log::trace!("Release swapchain image");
+        xr_swapchain.release_image()?;
+
+        // End rendering and submit the images
+        let rect = openxr::Rect2Di {
+            offset: openxr::Offset2Di { x: 0, y: 0 },
+            extent: openxr::Extent2Di {
+                width: resolution.width as _,
+                height: resolution.height as _,
+            },
+        };
+
+        log::trace!("End frame stream");
+
+        #[cfg(feature = "profiling")]
+        {
+            let predicted_display_time_nanos = frame_state.predicted_display_time.as_nanos();
+            profiling::scope!(
+                "Show Time Calculation",
+                format!("{predicted_display_time_nanos}").as_str()
+            );
+        }
+        let projection_views = views
+            .iter()
+            .enumerate()
+            .map(|(eye, view)| {
+                openxr::CompositionLayerProjectionView::new()
+                    .pose(view.pose)
+                    .fov(view.fov)
+                    .sub_image(
+                        openxr::SwapchainSubImage::new()
+                            .swapchain(xr_swapchain)
+                            .image_array_index(eye as u32)
+                            .image_rect(rect),
+                    )
+            })
+            .collect::<Vec<_>>();
+        if let Err(err) = self.frame_stream.end(
+            frame_state.predicted_display_time,
+            self.xr_context.blend_mode,
+            &[&openxr::CompositionLayerProjection::new()
+                .space(&self.space)
+                .views(&projection_views)],
+        ) {
+            log::error!("Failed to end frame stream: {}", err);
+        };
+        Ok(())
+    }
+
+    fn reset_space(&mut self) -> anyhow::Result<()> {
+        self.space = self
+            .session
+            .create_reference_space(ReferenceSpaceType::LOCAL, openxr::Posef::IDENTITY)?;
+        Ok(())
+    }
+
+    fn recenter(
+        &mut self,
+        frame_state: &openxr::FrameState,
+        request: RecenterRequest,
+    ) -> anyhow::Result<()> {
+        self.space = recenter_scene(
+            &self.session,
+            &self.reference_space,
+            &self.view_space,
+            frame_state.predicted_display_time,
+            request.horizon_locked,
+            request.delay,
+        )?;
+        Ok(())
+    }
+
+    fn poll_gesture(&mut self, frame_state: &openxr::FrameState) -> Option<RecenterRequest> {
+        //XR Input processing
+        #[cfg(feature = "profiling")]
+        profiling::scope!("Process Inputs");
+
+        let input_context = self.input_context.as_mut()?;
+        input_context
+            .process_inputs(
+                &self.session,
+                frame_state,
+                &self.reference_space,
+                &self.view_space,
+            )
+            .ok()?;
+
+        let new_state = input_context.input_state.as_ref()?;
+        if new_state.hands_near_head > 0 && new_state.near_start.elapsed().as_secs() > 3 {
+            let should_unlock_horizon = new_state.hands_near_head > 1
+                || (new_state.hands_near_head == 1
+                    && new_state.count_change.elapsed().as_secs() < 1);
+
+            return Some(RecenterRequest {
+                horizon_locked: !should_unlock_horizon,
+                delay: 0,
+            });
+        }
+        None
+    }
+}
+
+// Returns a LOCAL space facing where the user is looking, optionally kept level with the horizon
+fn recenter_scene(
+    xr_session: &openxr::Session<openxr::Vulkan>,
+    xr_reference_space: &openxr::Space,
+    xr_view_space: &openxr::Space,
+    last_predicted_frame_time: openxr::Time,
+    horizon_locked: bool,
+    delay: i64,
+) -> anyhow::Result<openxr::Space> {
+    let mut view_location_pose = xr_view_space
+        .locate(
+            xr_reference_space,
+            openxr::Time::from_nanos(last_predicted_frame_time.as_nanos() - delay),
+        )?
+        .pose;
+    let quaternion =
+        cgmath::Quaternion::from(mint::Quaternion::from(view_location_pose.orientation));
+    let forward = cgmath::Vector3::new(0.0, 0.0, 1.0);
+    let look_dir = quaternion * forward;
+    let yaw = cgmath::Rad(look_dir.x.atan2(look_dir.z));
+    let clean_orientation = if horizon_locked {
+        cgmath::Quaternion::from_angle_y(yaw)
+    } else {
+        let padj = (look_dir.x * look_dir.x + look_dir.z * look_dir.z).sqrt();
+        let pitch = -cgmath::Rad(look_dir.y.atan2(padj));
+        cgmath::Quaternion::from_angle_y(yaw) * cgmath::Quaternion::from_angle_x(pitch)
+    };
+    view_location_pose.orientation = openxr::Quaternionf {
+        x: clean_orientation.v.x,
+        y: clean_orientation.v.y,
+        z: clean_orientation.v.z,
+        w: clean_orientation.s,
+    };
+    Ok(xr_session.create_reference_space(ReferenceSpaceType::LOCAL, view_location_pose)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Begin,
+        End,
+        SkipFrame,
+        EndFrame(usize),
+        ResetSpace,
+        Recenter(RecenterRequest),
+    }
+
+    // Replays scripted events, should_render flags and gestures, recording what the loop asks for
+    #[derive(Default)]
+    struct ScriptedSession {
+        events: VecDeque<SessionEvent>,
+        should_render: VecDeque<bool>,
+        gestures: VecDeque<RecenterRequest>,
+        frame: i64,
+        calls: Vec<Call>,
+    }
+
+    impl ScriptedSession {
+        fn new(events: &[SessionEvent]) -> Self {
+            Self {
+                events: events.iter().copied().collect(),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl XrSession for ScriptedSession {
+        type Target = ();
+
+        fn poll_event(&mut self) -> anyhow::Result<Option<SessionEvent>> {
+            Ok(self.events.pop_front())
+        }
+
+        fn begin(&mut self) -> anyhow::Result<()> {
+            self.calls.push(Call::Begin);
+            Ok(())
+        }
+
+        fn end(&mut self) -> anyhow::Result<()> {
+            self.calls.push(Call::End);
+            Ok(())
+        }
+
+        fn begin_frame(&mut self) -> anyhow::Result<openxr::FrameState> {
+            self.frame += 1;
+            Ok(openxr::FrameState {
+                predicted_display_time: openxr::Time::from_nanos(self.frame * 11_111_111),
+                predicted_display_period: openxr::Duration::from_nanos(11_111_111),
+                should_render: self.should_render.pop_front().unwrap_or(true),
+            })
+        }
+
+        fn skip_frame(&mut self, _frame_state: &openxr::FrameState) -> anyhow::Result<()> {
+            self.calls.push(Call::SkipFrame);
+            Ok(())
+        }
+
+        fn locate_views(
+            &mut self,
+            _frame_state: &openxr::FrameState,
+        ) -> anyhow::Result<Vec<openxr::View>> {
+            Ok(crate::engine::headless::headless_views().to_vec())
+        }
+
+        fn acquire_image(&mut self) -> anyhow::Result<&()> {
+            Ok(&())
+        }
+
+        fn end_frame(
+            &mut self,
+            _frame_state: &openxr::FrameState,
+            views: &[openxr::View],
+        ) -> anyhow::Result<()> {
+            self.calls.push(Call::EndFrame(views.len()));
+            Ok(())
+        }
+
+        fn reset_space(&mut self) -> anyhow::Result<()> {
+            self.calls.push(Call::ResetSpace);
+            Ok(())
+        }
+
+        fn recenter(
+            &mut self,
+            _frame_state: &openxr::FrameState,
+            request: RecenterRequest,
+        ) -> anyhow::Result<()> {
+            self.calls.push(Call::Recenter(request));
+            Ok(())
+        }
+
+        fn poll_gesture(&mut self, _frame_state: &openxr::FrameState) -> Option<RecenterRequest> {
+            self.gestures.pop_front()
+        }
+    }
+
+    fn state(state: openxr::SessionState) -> SessionEvent {
+        SessionEvent::StateChanged(state)
+    }
+
+    // Steps the loop, returning how many frames were rendered
+    fn run_steps(
+        frame_loop: &mut FrameLoop,
+        session: &mut ScriptedSession,
+        steps: usize,
+    ) -> (usize, LoopControl) {
+        let mut rendered = 0;
+        let mut control = LoopControl::Continue;
+        for _ in 0..steps {
+            control = frame_loop
+                .step(session, |views, _, _| {
+                    assert_eq!(views.len(), 2);
+                    rendered += 1;
+                    Ok(())
+                })
+                .unwrap();
+            if control == LoopControl::Exit {
+                break;
+            }
+        }
+        (rendered, control)
+    }
+
+    #[test]
+    fn frames_wait_for_ready() {
+        let mut session = ScriptedSession::new(&[]);
+        let mut frame_loop = FrameLoop::default();
+        assert_eq!(run_steps(&mut frame_loop, &mut session, 3).0, 0);
+        assert_eq!(session.frame, 0);
+
+        session.events.push_back(state(openxr::SessionState::READY));
+        assert_eq!(run_steps(&mut frame_loop, &mut session, 3).0, 2);
+        assert!(frame_loop.is_session_running());
+        assert_eq!(
+            session.calls,
+            [Call::Begin, Call::EndFrame(2), Call::EndFrame(2)]
+        );
+    }
+
+    #[test]
+    fn session_restarts_after_stopping() {
+        let mut session = ScriptedSession::new(&[
+            state(openxr::SessionState::READY),
+            state(openxr::SessionState::SYNCHRONIZED),
+            state(openxr::SessionState::STOPPING),
+            state(openxr::SessionState::IDLE),
+            state(openxr::SessionState::READY),
+        ]);
+        let mut frame_loop = FrameLoop::default();
+        assert_eq!(run_steps(&mut frame_loop, &mut session, 5).0, 0);
+        assert_eq!(session.calls, [Call::Begin, Call::End, Call::Begin]);
+        assert!(frame_loop.is_session_running());
+
+        assert_eq!(run_steps(&mut frame_loop, &mut session, 1).0, 1);
+    }
+
+    #[test]
+    fn stopped_session_does_not_render() {
+        let mut session = ScriptedSession::new(&[
+            state(openxr::SessionState::READY),
+            state(openxr::SessionState::STOPPING),
+        ]);
+        let mut frame_loop = FrameLoop::default();
+        assert_eq!(run_steps(&mut frame_loop, &mut session, 4).0, 0);
+        assert!(!frame_loop.is_session_running());
+        assert_eq!(session.frame, 0);
+    }
+
+    #[test]
+    fn exiting_stops_the_loop() {
+        let mut session = ScriptedSession::new(&[
+            state(openxr::SessionState::READY),
+            state(openxr::SessionState::EXITING),
+            state(openxr::SessionState::READY),
+        ]);
+        let mut frame_loop = FrameLoop::default();
+        let (rendered, control) = run_steps(&mut frame_loop, &mut session, 3);
+        assert_eq!(control, LoopControl::Exit);
+        assert_eq!(rendered, 0);
+        assert_eq!(session.events.len(), 1);
+    }
+
+    #[test]
+    fn frames_are_skipped_when_not_rendering() {
+        let mut session = ScriptedSession::new(&[state(openxr::SessionState::READY)]);
+        session.should_render = [false, false, true].into();
+        let mut frame_loop = FrameLoop::default();
+        frame_loop.request_recenter(true);
+
+        assert_eq!(run_steps(&mut frame_loop, &mut session, 3).0, 0);
+        assert_eq!(
+            session.calls,
+            [Call::Begin, Call::SkipFrame, Call::SkipFrame]
+        );
+
+        // Pending recenters wait for a rendered frame
+        assert_eq!(run_steps(&mut frame_loop, &mut session, 1).0, 1);
+        assert_eq!(
+            session.calls[3..],
+            [
+                Call::EndFrame(2),
+                Call::Recenter(RecenterRequest {
+                    delay: 0,
+                    horizon_locked: true
+                })
+            ]
+        );
+    }
+
+    #[test]
+    fn reference_space_change_resets_space() {
+        let mut session = ScriptedSession::new(&[
+            state(openxr::SessionState::READY),
+            SessionEvent::ReferenceSpaceChangePending,
+            SessionEvent::EventsLost(3),
+        ]);
+        let mut frame_loop = FrameLoop::default();
+        assert_eq!(run_steps(&mut frame_loop, &mut session, 3).0, 0);
+        assert_eq!(session.calls, [Call::Begin, Call::ResetSpace]);
+    }
+
+    #[test]
+    fn recenter_requests_are_applied_once() {
+        let mut session = ScriptedSession::new(&[state(openxr::SessionState::READY)]);
+        let mut frame_loop = FrameLoop::default();
+        frame_loop.request_recenter(false);
+        assert_eq!(run_steps(&mut frame_loop, &mut session, 3).0, 2);
+        assert_eq!(
+            session.calls,
+            [
+                Call::Begin,
+                Call::EndFrame(2),
+                Call::Recenter(RecenterRequest {
+                    delay: 0,
+                    horizon_locked: false
+                }),
+                Call::EndFrame(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn requested_recenter_wins_over_gesture() {
+        let gesture = RecenterRequest {
+            delay: 0,
+            horizon_locked: false,
+        };
+        let mut session = ScriptedSession::new(&[state(openxr::SessionState::READY)]);
+        session.gestures = [gesture, gesture].into();
+        let mut frame_loop = FrameLoop::default();
+        frame_loop.request_recenter(true);
+        run_steps(&mut frame_loop, &mut session, 3);
+
+        let recenters = session
+            .calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::Recenter(request) => Some(request.horizon_locked),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(recenters, [true, false]);
+    }
+}
@@ -47,7 +47,9 @@ impl<State> Texture2D<State> {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
 
@@ -205,3 +207,64 @@ impl<TextureType: Sized, const SIZE: usize> RoundRobinTextureBuffer<TextureType,
         &mut self.textures[index]
     }
 }
+
+// Keeps the latest left and right frames of a source that alternates eyes between frames
+pub struct FrameSequentialBuffer {
+    frames: RoundRobinTextureBuffer<Texture2D<Bound>, 2>,
+    frame_counter: u64,
+}
+
+impl FrameSequentialBuffer {
+    pub fn new(
+        source: &Texture2D<Bound>,
+        wgpu_context: &WgpuContext,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let frames = (0..2)
+            .map(|idx| {
+                let texture = wgpu_context.device.create_texture(&TextureDescriptor {
+                    label: Some(format!("Frame Sequential Texture {idx}").as_str()),
+                    size: source.texture.size(),
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: source.texture.format(),
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+                Texture2D::<Unbound>::from_wgpu(&wgpu_context.device, texture)
+                    .bind_to_context(wgpu_context, bind_group_layout)
+            })
+            .collect::<Vec<_>>()
+            .try_into()
+            .ok()
+            .context("Cannot create frame sequential buffer")?;
+
+        Ok(Self {
+            frames: RoundRobinTextureBuffer::new(frames),
+            frame_counter: 0,
+        })
+    }
+
+    pub fn push(&mut self, encoder: &mut wgpu::CommandEncoder, source: &Texture2D<Bound>) {
+        self.frames.next();
+        let target = self.frames.current();
+        encoder.copy_texture_to_texture(
+            source.texture.as_image_copy(),
+            target.texture.as_image_copy(),
+            target.texture.size(),
+        );
+        self.frame_counter += 1;
+    }
+
+    // Even frames belong to the left eye unless the phase is inverted
+    pub fn eye(&self, eye: usize, invert_phase: bool) -> &Texture2D<Bound> {
+        let current_frame = self.frame_counter.saturating_sub(1);
+        let current_eye = (current_frame % 2) as usize ^ invert_phase as usize;
+        if current_eye == eye {
+            self.frames.current()
+        } else {
+            self.frames.previous(1)
+        }
+    }
+}
@@ -8,8 +8,9 @@ use wgpu_hal as hal;
 use crate::conversions::vulkan_image_to_texture;
 
 use super::{
+    external_memory_extensions,
     texture::{Texture2D, Unbound},
-    WgpuLoader, WgpuRunner, TARGET_VULKAN_VERSION,
+    WgpuLoader, TARGET_VULKAN_VERSION,
 };
 
 pub struct OpenXRContext {
@@ -46,7 +47,9 @@ pub fn enable_xr_runtime() -> anyhow::Result<OpenXRContext> {
 
     let available_extensions = entry.enumerate_extensions()?;
     log::info!("Available extensions: {:?}", available_extensions);
-    assert!(available_extensions.khr_vulkan_enable2);
+    if !available_extensions.khr_vulkan_enable2 {
+        bail!("The OpenXR runtime doesn't support XR_KHR_vulkan_enable2");
+    }
 
     let mut enabled_extensions = openxr::ExtensionSet::default();
     enabled_extensions.khr_vulkan_enable2 = true;
@@ -234,8 +237,11 @@ impl WgpuLoader for OpenXRContext {
             .adapter
             .required_device_extensions(wgpu_features);
 
-        #[cfg(target_os = "windows")]
-        device_extensions.push(ash::extensions::khr::ExternalMemoryWin32::name());
+        for extension in external_memory_extensions(&vk_instance, vk_physical_device)? {
+            if !device_extensions.contains(&extension) {
+                device_extensions.push(extension);
+            }
+        }
 
         log::info!("Requested device extensions: {:?}", device_extensions);
 
@@ -340,12 +346,6 @@ impl WgpuLoader for OpenXRContext {
     }
 }
 
-impl WgpuRunner for OpenXRContext {
-    fn run(&mut self, _wgpu_context: &super::WgpuContext) {
-        todo!()
-    }
-}
-
 impl OpenXRContext {
     pub fn create_swapchain(
         &self,
@@ -413,7 +413,10 @@ impl OpenXRContext {
                     dimension: wgpu_tex_desc.dimension,
                     format: wgpu_tex_desc.format,
                     view_formats: vec![],
-                    usage: wgpu_hal::TextureUses::COLOR_TARGET | wgpu_hal::TextureUses::COPY_DST,
+                    // Sampled by the mirror window
+                    usage: wgpu_hal::TextureUses::COLOR_TARGET
+                        | wgpu_hal::TextureUses::RESOURCE
+                        | wgpu_hal::TextureUses::COPY_DST,
                     memory_flags: MemoryFlags::empty(),
                 };
 
@@ -424,6 +427,7 @@ impl OpenXRContext {
                     vk::Image::from_raw(image),
                     wgpu_tex_desc,
                     wgpu_hal_tex_desc,
+                    None,
                 );
 
                 Texture2D::<Unbound>::from_wgpu(device, wgpu_texture)
@@ -0,0 +1,639 @@
+use std::marker::PhantomData;
+
+use wgpu::util::DeviceExt;
+
+use super::{
+    texture::{Bound, Texture2D, Unbound},
+    WgpuContext,
+};
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum YuvFormat {
+    // Packed 4:2:2, Y0 U Y1 V
+    #[cfg(all(target_os = "linux", feature = "v4l"))]
+    Yuyv = 1,
+    // 4:2:0 with a full resolution Y plane followed by an interleaved UV plane
+    Nv12 = 2,
+}
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum YuvColorSpace {
+    Bt601 = 0,
+    Bt709 = 1,
+}
+
+impl YuvColorSpace {
+    // Sources rarely tag their frames, HD resolutions are assumed to be BT.709
+    pub fn from_height(height: u32) -> Self {
+        if height >= 720 {
+            YuvColorSpace::Bt709
+        } else {
+            YuvColorSpace::Bt601
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct YuvParamsUniform {
+    format: u32,
+    color_space: u32,
+    full_range: u32,
+    _padding: u32,
+}
+
+impl YuvParamsUniform {
+    pub fn new(format: YuvFormat, color_space: YuvColorSpace, full_range: bool) -> Self {
+        Self {
+            format: format as u32,
+            color_space: color_space as u32,
+            full_range: full_range as u32,
+            _padding: 0,
+        }
+    }
+}
+
+// Texture format and size of every plane, the second plane of packed formats is a 1x1 placeholder
+#[cfg(all(target_os = "linux", feature = "v4l"))]
+pub fn yuv_planes(
+    format: YuvFormat,
+    width: u32,
+    height: u32,
+) -> [(wgpu::TextureFormat, u32, u32); 2] {
+    match format {
+        YuvFormat::Yuyv => [
+            (wgpu::TextureFormat::Rgba8Unorm, (width + 1) / 2, height),
+            (wgpu::TextureFormat::R8Unorm, 1, 1),
+        ],
+        YuvFormat::Nv12 => [
+            (wgpu::TextureFormat::R8Unorm, width, height),
+            (
+                wgpu::TextureFormat::Rg8Unorm,
+                (width + 1) / 2,
+                (height + 1) / 2,
+            ),
+        ],
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "v4l"))]
+pub fn yuv_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let plane_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("YUV Bind Group Layout"),
+        entries: &[
+            plane_entry(0),
+            plane_entry(1),
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+// Bindings of the planar path of shader.wgsl, the sampler slot is shared with the RGBA layout
+pub fn planar_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let plane_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("planar_texture_bind_group_layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            plane_entry(2),
+            plane_entry(3),
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+// Chroma plane of a source handing over planar frames, the luma plane is the source texture itself
+pub struct YuvPlanes<State> {
+    pub chroma: wgpu::Texture,
+    pub params: YuvParamsUniform,
+    pub bind_group: Option<wgpu::BindGroup>,
+    // Kept alive alongside the bind group
+    _params_buffer: Option<wgpu::Buffer>,
+    state: PhantomData<State>,
+}
+
+#[cfg_attr(not(feature = "video"), allow(dead_code))]
+impl YuvPlanes<Unbound> {
+    // Creates the R8 luma and RG8 chroma planes of a 4:2:0 frame
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        params: YuvParamsUniform,
+        label: &str,
+    ) -> (wgpu::Texture, Self) {
+        let create_plane = |label: String, format, width, height| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label.as_str()),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        };
+        let luma = create_plane(
+            format!("{label} Luma"),
+            wgpu::TextureFormat::R8Unorm,
+            width,
+            height,
+        );
+        let chroma = create_plane(
+            format!("{label} Chroma"),
+            wgpu::TextureFormat::Rg8Unorm,
+            (width + 1) / 2,
+            (height + 1) / 2,
+        );
+        (
+            luma,
+            Self {
+                chroma,
+                params,
+                bind_group: None,
+                _params_buffer: None,
+                state: PhantomData,
+            },
+        )
+    }
+
+    pub fn bind_to_context(
+        self,
+        wgpu_context: &WgpuContext,
+        luma: &Texture2D<Bound>,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> YuvPlanes<Bound> {
+        let params_buffer =
+            wgpu_context
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("YUV Params Buffer"),
+                    contents: bytemuck::cast_slice(&[self.params]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+        let chroma_view = self
+            .chroma
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = wgpu_context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&luma.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&luma.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&chroma_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("Planar Texture Bind Group"),
+            });
+        YuvPlanes::<Bound> {
+            chroma: self.chroma,
+            params: self.params,
+            bind_group: Some(bind_group),
+            _params_buffer: Some(params_buffer),
+            state: PhantomData,
+        }
+    }
+}
+
+impl YuvPlanes<Bound> {
+    #[inline]
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        self.bind_group.as_ref().unwrap()
+    }
+}
+
+// Holds the planes of a YUV frame and converts them into an RGBA render target
+#[cfg(all(target_os = "linux", feature = "v4l"))]
+pub struct YuvConverter {
+    format: YuvFormat,
+    planes: [wgpu::Texture; 2],
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+#[cfg(all(target_os = "linux", feature = "v4l"))]
+impl YuvConverter {
+    pub fn new(
+        device: &wgpu::Device,
+        format: YuvFormat,
+        width: u32,
+        height: u32,
+        color_space: YuvColorSpace,
+        full_range: bool,
+        target_format: wgpu::TextureFormat,
+    ) -> Self {
+        let planes = yuv_planes(format, width, height).map(|(plane_format, width, height)| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("YUV Plane"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: plane_format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            })
+        });
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("YUV Params Buffer"),
+            contents: bytemuck::cast_slice(&[YuvParamsUniform::new(
+                format,
+                color_space,
+                full_range,
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group_layout = yuv_bind_group_layout(device);
+        let plane_views: Vec<_> = planes
+            .iter()
+            .map(|plane| plane.create_view(&wgpu::TextureViewDescriptor::default()))
+            .collect();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("YUV Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&plane_views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&plane_views[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("YUV Convert Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("../yuv.wgsl"),
+                    include_str!("../yuv_convert.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("YUV Convert Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("YUV Convert Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            format,
+            planes,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Uploads a frame laid out as V4L2 does, the planes of NV12 share the same stride
+    pub fn upload(&self, queue: &wgpu::Queue, data: &[u8], stride: u32) {
+        let mut offset = 0;
+        let plane_count = match self.format {
+            YuvFormat::Yuyv => 1,
+            YuvFormat::Nv12 => 2,
+        };
+        for plane in self.planes.iter().take(plane_count) {
+            let size = plane.size();
+            queue.write_texture(
+                plane.as_image_copy(),
+                &data[offset.min(data.len())..],
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(stride),
+                    rows_per_image: Some(size.height),
+                },
+                size,
+            );
+            offset += (stride * size.height) as usize;
+        }
+    }
+
+    pub fn convert(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut convert_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("YUV Convert Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        convert_pass.set_pipeline(&self.pipeline);
+        convert_pass.set_bind_group(0, &self.bind_group, &[]);
+        convert_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu::util::DeviceExt;
+
+    use super::{YuvColorSpace, YuvFormat, YuvParamsUniform};
+    use crate::engine::{headless::HeadlessContext, WgpuLoader};
+
+    const CONVERT_SHADER: &str = r"
+@group(0) @binding(0) var<storage, read> inputs: array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read_write> outputs: array<vec4<f32>>;
+@group(0) @binding(2) var<uniform> params: YuvParams;
+
+@compute @workgroup_size(1)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    outputs[id.x] = vec4<f32>(yuv_to_rgb(inputs[id.x].xyz, params), 1.0);
+}
+";
+
+    // Black, white, red, green and blue
+    const RGB: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 1.0, 1.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 0.0, 1.0],
+    ];
+    // The reference values are rounded to 8 bits and the shader centers chroma on 0.5 instead of
+    // 128/255, neither moves a channel by more than a code value
+    const TOLERANCE: f32 = 3.0 / 255.0;
+
+    // Runs yuv_to_rgb of the shaders on a software adapter, None when there is none
+    fn convert(
+        color_space: YuvColorSpace,
+        full_range: bool,
+        yuv: &[[u8; 3]],
+    ) -> Option<Vec<[f32; 3]>> {
+        let mut headless_context = HeadlessContext {
+            force_fallback_adapter: true,
+        };
+        let Ok(wgpu_context) = headless_context.load_wgpu() else {
+            log::warn!("Skipping YUV conversion test, no software Vulkan adapter like lavapipe");
+            return None;
+        };
+        let device = &wgpu_context.device;
+
+        let inputs: Vec<[f32; 4]> = yuv
+            .iter()
+            .map(|&[y, cb, cr]| [y as f32 / 255.0, cb as f32 / 255.0, cr as f32 / 255.0, 0.0])
+            .collect();
+        let size = std::mem::size_of_val(inputs.as_slice()) as wgpu::BufferAddress;
+        let input_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("YUV Test Inputs"),
+            contents: bytemuck::cast_slice(&inputs),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("YUV Test Outputs"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let read_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("YUV Test Readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("YUV Test Params"),
+            contents: bytemuck::cast_slice(&[YuvParamsUniform::new(
+                YuvFormat::Nv12,
+                color_space,
+                full_range,
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("YUV Test Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                format!("{}{}", include_str!("../yuv.wgsl"), CONVERT_SHADER).into(),
+            ),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("YUV Test Pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: "cs_main",
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("YUV Test Bind Group"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: input_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: output_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("YUV Test Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("YUV Test Pass"),
+            });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(inputs.len() as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output_buffer, 0, &read_buffer, 0, size);
+        wgpu_context.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let slice = read_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv().unwrap().unwrap();
+        let data = slice.get_mapped_range();
+        Some(
+            bytemuck::cast_slice::<u8, [f32; 4]>(&data)
+                .iter()
+                .map(|&[r, g, b, _]| [r, g, b])
+                .collect(),
+        )
+    }
+
+    fn assert_converts(color_space: YuvColorSpace, full_range: bool, yuv: [[u8; 3]; 5]) {
+        let Some(converted) = convert(color_space, full_range, &yuv) else {
+            return;
+        };
+        for ((yuv, rgb), expected) in yuv.iter().zip(converted).zip(RGB) {
+            assert!(
+                rgb.iter()
+                    .zip(expected)
+                    .all(|(value, expected)| (value - expected).abs() < TOLERANCE),
+                "{yuv:?} converted to {rgb:?} instead of {expected:?} \
+                 ({color_space:?}, full range {full_range})"
+            );
+        }
+    }
+
+    #[test]
+    fn bt601_limited_range() {
+        assert_converts(
+            YuvColorSpace::Bt601,
+            false,
+            [
+                [16, 128, 128],
+                [235, 128, 128],
+                [81, 90, 240],
+                [145, 54, 34],
+                [41, 240, 110],
+            ],
+        );
+    }
+
+    #[test]
+    fn bt601_full_range() {
+        assert_converts(
+            YuvColorSpace::Bt601,
+            true,
+            [
+                [0, 128, 128],
+                [255, 128, 128],
+                [76, 85, 255],
+                [150, 44, 21],
+                [29, 255, 107],
+            ],
+        );
+    }
+
+    #[test]
+    fn bt709_limited_range() {
+        assert_converts(
+            YuvColorSpace::Bt709,
+            false,
+            [
+                [16, 128, 128],
+                [235, 128, 128],
+                [63, 102, 240],
+                [173, 42, 26],
+                [32, 240, 118],
+            ],
+        );
+    }
+
+    #[test]
+    fn bt709_full_range() {
+        assert_converts(
+            YuvColorSpace::Bt709,
+            true,
+            [
+                [0, 128, 128],
+                [255, 128, 128],
+                [54, 99, 255],
+                [182, 30, 12],
+                [18, 255, 116],
+            ],
+        );
+    }
+}
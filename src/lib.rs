@@ -3,19 +3,21 @@ use ::windows::Win32::System::Threading::{
     GetCurrentProcess, SetPriorityClass, HIGH_PRIORITY_CLASS,
 };
 use anyhow::Context;
-use cgmath::Rotation3;
 use clap::Parser;
-use config::{AppConfig, TemporalBlurParams};
+#[cfg(not(target_os = "android"))]
+use config::MirrorMode;
+#[cfg(not(target_os = "android"))]
 use engine::{
-    camera::{Camera, CameraUniform},
-    geometry::{ModelVertex, Vertex},
-    input::InputContext,
-    screen::Screen,
-    texture::{Bound, RoundRobinTextureBuffer, Texture2D, Unbound},
-    vr::{enable_xr_runtime, OpenXRContext, SWAPCHAIN_COLOR_FORMAT, VIEW_COUNT, VIEW_TYPE},
-    WgpuContext, WgpuLoader,
+    camera::Camera,
+    desktop::{DesktopContext, MouseLook},
+    mirror::MirrorWindow,
 };
-use loaders::{katanga_loader::KatangaLoaderContext, Loader, StereoMode};
+use engine::{
+    headless::headless_views,
+    session::{FrameLoop, LoopControl, OpenXRSession},
+    vr::{enable_xr_runtime, OpenXRContext},
+};
+use loaders::LoaderAction;
 use log::LevelFilter;
 use log4rs::{
     append::file::FileAppender,
@@ -23,16 +25,10 @@ use log4rs::{
     encode::pattern::PatternEncoder,
     Config,
 };
-use openxr::ReferenceSpaceType;
-use std::{
-    iter,
-    num::NonZeroU32,
-    sync::{Arc, Mutex},
-};
+use std::sync::{Arc, Mutex};
 use thread_priority::*;
 #[cfg(not(target_os = "android"))]
 use tray_item::TrayItem;
-use wgpu::{util::DeviceExt, BindGroupLayout};
 
 use crate::config::ConfigContext;
 
@@ -40,6 +36,18 @@ mod config;
 mod conversions;
 mod engine;
 mod loaders;
+pub mod protocol;
+mod renderer;
+
+pub use config::AppConfig;
+// Describes Vulkan images created outside of wgpu, e.g. by embedders sharing their own textures
+pub use conversions::{unmap_vk_format, FormatCapabilities};
+pub use engine::{
+    headless::{HeadlessContext, OffscreenTarget},
+    WgpuContext, WgpuLoader,
+};
+pub use loaders::StereoMode;
+pub use renderer::Renderer;
 
 #[derive(Clone)]
 enum TrayMessages {
@@ -47,6 +55,9 @@ enum TrayMessages {
     Reload,
     Recenter(bool),
     ToggleSettings(ToggleSetting),
+    LoaderAction(LoaderAction),
+    // None goes back to picking the first available loader
+    SelectLoader(Option<&'static str>),
 }
 
 #[derive(Clone)]
@@ -55,19 +66,20 @@ enum ToggleSetting {
     FlipY,
     SwapEyes,
     AmbientLight,
+    Anaglyph,
 }
 
 struct TrayState {
     pub message: Option<&'static TrayMessages>,
 }
 
-struct RecenterRequest {
-    pub delay: i64,
-    pub horizon_locked: bool,
-}
-
-const AMBIENT_BLUR_BASE_RES: u32 = 16;
-const AMBIENT_BLUR_TEMPORAL_SAMPLES: u32 = 16;
+// Size of each eye and number of frames rendered by the headless mode, enough for the ambient
+// blur history to settle
+const HEADLESS_RESOLUTION: u32 = 1024;
+const HEADLESS_FRAMES: u32 = renderer::AMBIENT_BLUR_TEMPORAL_SAMPLES * 4;
+// Size of each eye rendered by the desktop preview before it is scaled to the window
+#[cfg(not(target_os = "android"))]
+const DESKTOP_RESOLUTION: u32 = 1024;
 #[cfg(feature = "dhat-heap")]
 #[global_allocator]
 static ALLOC: dhat::Alloc = dhat::Alloc;
@@ -102,7 +114,37 @@ pub fn launch() -> anyhow::Result<()> {
 
     try_elevate_priority();
 
-    let mut xr_context = enable_xr_runtime()?;
+    let launch_params = AppConfig::parse();
+    if let Some(output_path) = &launch_params.headless_output {
+        let wgpu_context = HeadlessContext::default().load_wgpu()?;
+        return run_headless(&wgpu_context, &launch_params, output_path);
+    }
+
+    let mut xr_context = match enable_xr_runtime() {
+        Ok(xr_context) => xr_context,
+        #[cfg(not(target_os = "android"))]
+        Err(err) => {
+            log::warn!(
+                "OpenXR is not available, opening the desktop preview: {}",
+                err
+            );
+            let mut config_context = config::ConfigContext::try_setup().unwrap_or(None);
+            let mode = initial_params(&config_context)
+                .mirror
+                .unwrap_or(MirrorMode::Left);
+            let mut desktop_context = DesktopContext::new(MirrorWindow::preview_window(mode)?);
+            let wgpu_context = desktop_context.load_wgpu()?;
+            let (_tray, tray_state) = build_tray()?;
+            return run_desktop(
+                &wgpu_context,
+                &mut desktop_context,
+                &tray_state,
+                &mut config_context,
+            );
+        }
+        #[cfg(target_os = "android")]
+        Err(err) => return Err(err),
+    };
     let wgpu_context = xr_context.load_wgpu()?;
 
     #[cfg(not(target_os = "android"))]
@@ -113,12 +155,7 @@ pub fn launch() -> anyhow::Result<()> {
     let mut config_context = config::ConfigContext::try_setup().unwrap_or(None);
 
     log::info!("Finished initial setup, running main loop");
-    run(
-        &mut xr_context,
-        &wgpu_context,
-        &tray_state,
-        &mut config_context,
-    )?;
+    run(&xr_context, &wgpu_context, &tray_state, &mut config_context)?;
 
     Ok(())
 }
@@ -177,6 +214,66 @@ fn build_tray() -> anyhow::Result<(TrayItem, Arc<Mutex<TrayState>>)> {
                 "Toggle Ambient Light",
                 &TrayMessages::ToggleSettings(ToggleSetting::AmbientLight),
             ),
+            (
+                "Cycle Anaglyph Mode",
+                &TrayMessages::ToggleSettings(ToggleSetting::Anaglyph),
+            ),
+        ],
+    )?;
+
+    tray.add_label("Sources")?;
+    add_all_tray_message_senders(
+        &tray_state,
+        &mut tray,
+        vec![
+            ("Automatic", &TrayMessages::SelectLoader(None)),
+            #[cfg(target_os = "windows")]
+            (
+                "Katanga",
+                &TrayMessages::SelectLoader(Some(registry::KATANGA_LOADER)),
+            ),
+            #[cfg(target_os = "linux")]
+            (
+                "Shared Memory",
+                &TrayMessages::SelectLoader(Some(registry::SHM_LOADER)),
+            ),
+            #[cfg(target_os = "linux")]
+            (
+                "DMA-BUF",
+                &TrayMessages::SelectLoader(Some(registry::DMABUF_LOADER)),
+            ),
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            (
+                "PipeWire",
+                &TrayMessages::SelectLoader(Some(registry::PIPEWIRE_LOADER)),
+            ),
+            #[cfg(all(target_os = "linux", feature = "x11"))]
+            (
+                "X11 Window",
+                &TrayMessages::SelectLoader(Some(registry::X11_LOADER)),
+            ),
+            #[cfg(all(target_os = "linux", feature = "v4l"))]
+            (
+                "Capture Device",
+                &TrayMessages::SelectLoader(Some(registry::V4L_LOADER)),
+            ),
+            (
+                "Network",
+                &TrayMessages::SelectLoader(Some(registry::NET_LOADER)),
+            ),
+            #[cfg(feature = "video")]
+            (
+                "Video",
+                &TrayMessages::SelectLoader(Some(registry::VIDEO_LOADER)),
+            ),
+            (
+                "Image",
+                &TrayMessages::SelectLoader(Some(registry::IMAGE_LOADER)),
+            ),
+            (
+                "Test Pattern",
+                &TrayMessages::SelectLoader(Some(registry::TEST_PATTERN_LOADER)),
+            ),
         ],
     )?;
 
@@ -186,6 +283,26 @@ fn build_tray() -> anyhow::Result<(TrayItem, Arc<Mutex<TrayState>>)> {
         &mut tray,
         vec![
             ("Reload Screen", &TrayMessages::Reload),
+            (
+                "Next Image",
+                &TrayMessages::LoaderAction(LoaderAction::Next),
+            ),
+            (
+                "Previous Image",
+                &TrayMessages::LoaderAction(LoaderAction::Previous),
+            ),
+            (
+                "Play/Pause",
+                &TrayMessages::LoaderAction(LoaderAction::TogglePlayback),
+            ),
+            (
+                "Seek +10s",
+                &TrayMessages::LoaderAction(LoaderAction::Seek(10)),
+            ),
+            (
+                "Seek -10s",
+                &TrayMessages::LoaderAction(LoaderAction::Seek(-10)),
+            ),
             ("Recenter", &TrayMessages::Recenter(true)),
             ("Recenter w/ Pitch", &TrayMessages::Recenter(false)),
             ("Quit", &TrayMessages::Quit),
@@ -210,1013 +327,278 @@ fn try_elevate_priority() {
 }
 
 fn run(
-    xr_context: &mut OpenXRContext,
+    xr_context: &OpenXRContext,
     wgpu_context: &WgpuContext,
     tray_state: &Arc<Mutex<TrayState>>,
     config: &mut Option<ConfigContext>,
 ) -> anyhow::Result<()> {
-    // Load the shaders from disk
-    let screen_shader = wgpu_context
-        .device
-        .create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
-    let blit_shader = wgpu_context
-        .device
-        .create_shader_module(wgpu::include_wgsl!("blit.wgsl"));
-
-    // We don't need to configure the texture view much, so let's
-    // let wgpu define it.
-
-    let mut aspect_ratio = 1.0;
-    let mut stereo_mode = StereoMode::Mono;
-    let mut current_loader = None;
-
-    //Load blank texture
-    let blank_texture = Texture2D::<Unbound>::from_bytes(
-        &wgpu_context.device,
-        &wgpu_context.queue,
-        include_bytes!("../assets/blank_grey.png"),
-        "Blank",
-    )?;
-
-    let mut loaders: Vec<Box<dyn Loader>> = vec![
-        #[cfg(target_os = "windows")]
-        {
-            Box::<KatangaLoaderContext>::default()
-        },
-    ];
-
-    let texture_bind_group_layout =
-        wgpu_context
-            .device
-            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-
-    let mut screen_texture =
-        blank_texture.bind_to_context(wgpu_context, &texture_bind_group_layout);
-    let mut ambient_texture = get_ambient_texture(
-        &screen_texture,
-        1.0,
-        &StereoMode::Mono,
-        wgpu_context,
-        &texture_bind_group_layout,
-    )?;
-
-    if let Some((texture, aspect, mode, loader)) = try_to_load_texture(&mut loaders, wgpu_context) {
-        screen_texture = texture.bind_to_context(wgpu_context, &texture_bind_group_layout);
-        ambient_texture = get_ambient_texture(
-            &screen_texture,
-            aspect,
-            &mode,
-            wgpu_context,
-            &texture_bind_group_layout,
-        )?;
-        aspect_ratio = aspect;
-        stereo_mode = mode;
-        current_loader = Some(loader);
-    }
-
-    let fullscreen_triangle_index_buffer =
-        wgpu_context
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Fullscreen Tri Index Buffer"),
-                contents: bytemuck::cast_slice(&[0, 1, 2]),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-    let mut screen_params = match config {
-        Some(ConfigContext {
-            last_config: Some(config),
-            ..
-        }) => config.clone(),
-        _ => AppConfig::parse(),
-    };
-
-    let mut temporal_blur_params = TemporalBlurParams {
-        jitter: [0.0, 0.0],
-        scale: [1.1, 1.1],
-        resolution: [
-            ambient_texture.current().texture.width() as f32,
-            ambient_texture.current().texture.height() as f32,
-        ],
-        history_decay: 0.985,
-    };
+    let screen_params = initial_params(config);
+    #[cfg(not(target_os = "android"))]
+    let mut mirror = screen_params.mirror.and_then(|mode| {
+        MirrorWindow::new(wgpu_context, mode, screen_params.mirror_interval)
+            .map_err(|err| log::warn!("Cannot open the mirror window: {}", err))
+            .ok()
+    });
+    let mut renderer = Renderer::new(wgpu_context, screen_params)?;
 
-    let mut screen = Screen::new(
-        &wgpu_context.device,
-        -screen_params.distance,
-        screen_params.scale,
-        aspect_ratio,
-        screen_params.ambient,
-    );
+    let mut xr_session = OpenXRSession::new(xr_context, wgpu_context)?;
+    let mut frame_loop = FrameLoop::default();
 
-    let screen_params_buffer =
-        wgpu_context
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Screen Params Buffer"),
-                contents: bytemuck::cast_slice(&[screen_params.uniform(1.0, 1, 1)]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            });
-
-    let temporal_blur_params_buffer =
-        wgpu_context
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Temporal Blur Params Buffer"),
-                contents: bytemuck::cast_slice(&[temporal_blur_params.uniform()]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            });
-
-    let screen_model_matrix_buffer =
-        wgpu_context
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Screen Model Matrix Buffer"),
-                contents: bytemuck::cast_slice(&[screen.entity.uniform()]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            });
-
-    let mut cameras = vec![Camera::default(), Camera::default()];
-    let mut camera_uniform = vec![CameraUniform::new(), CameraUniform::new()];
-
-    let camera_buffer = wgpu_context
-        .device
-        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(camera_uniform.as_slice()),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-    let global_temporal_blur_uniform_layout =
-        wgpu_context
-            .device
-            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("global_temporal_blur_bind_group_layout"),
-            });
-
-    let global_uniform_bind_group_layout =
-        wgpu_context
-            .device
-            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("global_uniform_bind_group_layout"),
-            });
-
-    let global_uniform_bind_group =
-        wgpu_context
-            .device
-            .create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &global_uniform_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: camera_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: screen_params_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: screen_model_matrix_buffer.as_entire_binding(),
-                    },
-                ],
-                label: Some("global_uniform_bind_group"),
-            });
-
-    let global_temporal_blur_uniform_bind_group =
-        wgpu_context
-            .device
-            .create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &global_temporal_blur_uniform_layout,
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: temporal_blur_params_buffer.as_entire_binding(),
-                }],
-                label: Some("global_temporal_blur_uniform_bind_group"),
-            });
-
-    let render_pipeline_layout =
-        wgpu_context
-            .device
-            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    &global_uniform_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-
-    let blit_pipeline_layout =
-        wgpu_context
-            .device
-            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Blit Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    &texture_bind_group_layout,
-                    &global_temporal_blur_uniform_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-
-    let screen_render_pipeline =
-        wgpu_context
-            .device
-            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Render Pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &screen_shader,
-                    entry_point: "vs_main",
-                    buffers: &[ModelVertex::desc()],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &screen_shader,
-                    entry_point: "fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: SWAPCHAIN_COLOR_FORMAT,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                }),
-                primitive: wgpu::PrimitiveState::default(),
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState::default(),
-                multiview: NonZeroU32::new(VIEW_COUNT),
-            });
-
-    let ambient_dome_pipeline =
-        wgpu_context
-            .device
-            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Ambient Dome Pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &screen_shader,
-                    entry_point: "mv_vs_main",
-                    buffers: &[ModelVertex::desc()],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &screen_shader,
-                    entry_point: "vignette_fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: SWAPCHAIN_COLOR_FORMAT,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                }),
-                primitive: wgpu::PrimitiveState::default(),
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState::default(),
-                multiview: NonZeroU32::new(VIEW_COUNT),
-            });
-
-    let temporal_blur_pipeline =
-        wgpu_context
-            .device
-            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Blit Pipeline"),
-                layout: Some(&blit_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &blit_shader,
-                    entry_point: "vs_main",
-                    buffers: &[],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &blit_shader,
-                    entry_point: "temporal_fs_main",
-                    targets: &[
-                        Some(wgpu::ColorTargetState {
-                            format: SWAPCHAIN_COLOR_FORMAT,
-                            blend: Some(wgpu::BlendState::REPLACE),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        }),
-                        Some(wgpu::ColorTargetState {
-                            format: SWAPCHAIN_COLOR_FORMAT,
-                            blend: Some(wgpu::BlendState::REPLACE),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        }),
-                    ],
-                }),
-                primitive: wgpu::PrimitiveState::default(),
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState::default(),
-                multiview: None,
-            });
-
-    // Start the OpenXR session
-    let (xr_session, mut frame_wait, mut frame_stream) = unsafe {
-        xr_context.instance.create_session::<openxr::Vulkan>(
-            xr_context.system,
-            &openxr::vulkan::SessionCreateInfo {
-                instance: wgpu_context.vk_instance_ptr as _,
-                physical_device: wgpu_context.vk_phys_device_ptr as _,
-                device: wgpu_context.vk_device_ptr as _,
-                queue_family_index: wgpu_context.queue_index,
-                queue_index: 0,
-            },
-        )?
-    };
-
-    // Create a room-scale reference space
-    let xr_reference_space = xr_session
-        .create_reference_space(openxr::ReferenceSpaceType::LOCAL, openxr::Posef::IDENTITY)?;
-    let xr_view_space = xr_session
-        .create_reference_space(openxr::ReferenceSpaceType::VIEW, openxr::Posef::IDENTITY)?;
-    let mut xr_space = xr_session
-        .create_reference_space(openxr::ReferenceSpaceType::LOCAL, openxr::Posef::IDENTITY)?;
-
-    let mut event_storage = openxr::EventDataBuffer::new();
-    let mut session_running = false;
-    let mut swapchain = None;
-    let mut screen_invalidated = false;
-    let mut recenter_request = None;
-    let mut last_invalidation_check = std::time::Instant::now();
-    let mut input_context = InputContext::init(&xr_context.instance)
-        .map(Some)
-        .unwrap_or(None);
-
-    if input_context.is_some() {
-        let mut attach_context = input_context
-            .take()
-            .context("Cannot attach input context to session")?;
-        if attach_context.attach_to_session(&xr_session).is_ok() {
-            input_context = Some(attach_context);
-        }
-    }
-
-    let mut jitter_frame: u32 = 0;
     // Handle OpenXR events
     loop {
         #[cfg(feature = "profiling")]
         profiling::scope!("main loop");
 
-        let time = std::time::Instant::now();
-
-        if current_loader.is_some() || time.duration_since(last_invalidation_check).as_secs() > 10 {
-            check_loader_invalidation(current_loader, &loaders, &mut screen_invalidated)?;
-            last_invalidation_check = time;
-        }
+        renderer.update_source(wgpu_context)?;
 
-        if screen_invalidated {
-            if let Some((texture, aspect, mode, loader)) =
-                try_to_load_texture(&mut loaders, wgpu_context)
-            {
-                screen_texture = texture.bind_to_context(wgpu_context, &texture_bind_group_layout);
-                ambient_texture = get_ambient_texture(
-                    &screen_texture,
-                    aspect,
-                    &mode,
-                    wgpu_context,
-                    &texture_bind_group_layout,
-                )?;
-                aspect_ratio = aspect;
-                stereo_mode = mode;
-                current_loader = Some(loader);
-                screen.change_aspect_ratio(aspect_ratio);
-
-                wgpu_context.queue.write_buffer(
-                    &screen_model_matrix_buffer,
-                    0,
-                    bytemuck::cast_slice(&[screen.entity.uniform()]),
-                );
-
-                let width_multiplier = match &stereo_mode {
-                    StereoMode::FullSbs => 2,
-                    _ => 1,
-                };
-
-                wgpu_context.queue.write_buffer(
-                    &screen_params_buffer,
-                    0,
-                    bytemuck::cast_slice(&[screen_params.uniform(
-                        aspect,
-                        screen_texture.texture.width() * width_multiplier,
-                        ambient_texture.current().texture.width() * width_multiplier,
-                    )]),
-                );
+        let control = frame_loop.step(&mut xr_session, |views, target, display_time| {
+            renderer.render(wgpu_context, views, target, display_time)?;
+            #[cfg(not(target_os = "android"))]
+            if let Some(mirror) = &mut mirror {
+                mirror.present(wgpu_context, target);
             }
-            screen_invalidated = false;
+            Ok(())
+        })?;
+        if control == LoopControl::Exit {
+            break;
         }
 
-        let event = xr_context.instance.poll_event(&mut event_storage)?;
-        match event {
-            Some(openxr::Event::SessionStateChanged(e)) => {
-                // Session state change is where we can begin and end sessions, as well as
-                // find quit messages!
-                log::info!("Entered state {:?}", e.state());
-                match e.state() {
-                    openxr::SessionState::READY => {
-                        xr_session.begin(VIEW_TYPE)?;
-                        session_running = true;
-                    }
-                    openxr::SessionState::STOPPING => {
-                        xr_session.end()?;
-                        session_running = false;
-                    }
-                    openxr::SessionState::EXITING => {
-                        break;
-                    }
-                    _ => {}
-                }
-            }
-            Some(openxr::Event::InstanceLossPending(_)) => {}
-            Some(openxr::Event::EventsLost(e)) => {
-                log::error!("Lost {} OpenXR events", e.lost_event_count());
-            }
-            Some(openxr::Event::ReferenceSpaceChangePending(_)) => {
-                //Reset XR space to follow runtime
-                xr_space = xr_session.create_reference_space(
-                    openxr::ReferenceSpaceType::LOCAL,
-                    openxr::Posef::IDENTITY,
-                )?;
-            }
-            _ => {
-                // Render to HMD only if we have an active session
-                if session_running {
-                    // Block until the previous frame is finished displaying, and is ready for
-                    // another one. Also returns a prediction of when the next frame will be
-                    // displayed, for use with predicting locations of controllers, viewpoints, etc.
-                    #[cfg(feature = "profiling")]
-                    profiling::scope!("Wait for frame");
-                    let xr_frame_state = frame_wait.wait()?;
-
-                    // Must be called before any rendering is done!
-                    frame_stream.begin()?;
-
-                    #[cfg(feature = "profiling")]
-                    profiling::scope!("FrameStream Recording");
-
-                    // Only render if we should
-                    if !xr_frame_state.should_render {
-                        #[cfg(feature = "profiling")]
-                        {
-                            let predicted_display_time_nanos =
-                                xr_frame_state.predicted_display_time.as_nanos();
-                            profiling::scope!(
-                                "Show Time Calculation",
-                                format!("{predicted_display_time_nanos}").as_str()
-                            );
-                        }
-
-                        // Early bail
-                        if let Err(err) = frame_stream.end(
-                            xr_frame_state.predicted_display_time,
-                            xr_context.blend_mode,
-                            &[],
-                        ) {
-                            log::error!(
-                                "Failed to end frame stream when should_render is FALSE : {:?}",
-                                err
-                            );
-                        };
-                        continue;
-                    }
-
-                    #[cfg(feature = "profiling")]
-                    profiling::scope!("Swapchain Setup");
-
-                    // If we do not have a swapchain yet, create it
-                    let (xr_swapchain, resolution, swapchain_textures) = match swapchain {
-                        Some(ref mut swapchain) => swapchain,
-                        None => {
-                            let new_swapchain =
-                                xr_context.create_swapchain(&xr_session, &wgpu_context.device)?;
-                            swapchain.get_or_insert(new_swapchain)
-                        }
-                    };
-                    // Check which image we need to render to and wait until the compositor is
-                    // done with this image
-                    let image_index = xr_swapchain.acquire_image()?;
-                    xr_swapchain.wait_image(openxr::Duration::INFINITE)?;
-
-                    let swapchain_view = &swapchain_textures[image_index as usize].view;
-
-                    log::trace!("Encode render pass");
-                    #[cfg(feature = "profiling")]
-                    profiling::scope!("Encode Render Pass");
-                    // Render!
-                    let mut encoder = wgpu_context.device.create_command_encoder(
-                        &wgpu::CommandEncoderDescriptor {
-                            label: Some("Render Encorder"),
-                        },
-                    );
-                    if screen.ambient_enabled {
-                        ambient_texture.next();
-                        let mut blit_pass =
-                            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                                label: Some("Blit Pass"),
-                                color_attachments: &[
-                                    Some(wgpu::RenderPassColorAttachment {
-                                        view: &ambient_texture.current().view,
-                                        resolve_target: None,
-                                        ops: wgpu::Operations {
-                                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                            store: true,
-                                        },
-                                    }),
-                                    Some(wgpu::RenderPassColorAttachment {
-                                        view: &ambient_texture.previous(1).view,
-                                        resolve_target: None,
-                                        ops: wgpu::Operations {
-                                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                            store: true,
-                                        },
-                                    }),
-                                ],
-                                depth_stencil_attachment: None,
-                            });
-
-                        blit_pass.set_pipeline(&temporal_blur_pipeline);
-                        blit_pass.set_bind_group(0, screen_texture.bind_group(), &[]);
-                        blit_pass.set_bind_group(1, ambient_texture.previous(2).bind_group(), &[]);
-                        blit_pass.set_bind_group(2, &global_temporal_blur_uniform_bind_group, &[]);
-                        blit_pass.set_index_buffer(
-                            fullscreen_triangle_index_buffer.slice(..),
-                            wgpu::IndexFormat::Uint32,
-                        );
-                        blit_pass.draw_indexed(0..3, 0, 0..1);
-                    }
-                    {
-                        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                            label: Some("Render Pass"),
-                            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                                view: swapchain_view,
-                                resolve_target: None,
-                                ops: wgpu::Operations {
-                                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                    store: true,
-                                },
-                            })],
-                            depth_stencil_attachment: None,
-                        });
-
-                        // Render the ambient dome
-                        if screen.ambient_enabled {
-                            let ambient_mesh = &screen.ambient_mesh;
-                            rpass.set_pipeline(&ambient_dome_pipeline);
-                            rpass.set_bind_group(0, ambient_texture.current().bind_group(), &[]);
-                            rpass.set_bind_group(1, &global_uniform_bind_group, &[]);
-                            rpass.set_vertex_buffer(0, ambient_mesh.vertex_buffer().slice(..));
-                            rpass.set_index_buffer(
-                                ambient_mesh.index_buffer().slice(..),
-                                wgpu::IndexFormat::Uint32,
-                            );
-                            rpass.draw_indexed(0..ambient_mesh.indices(), 0, 0..1);
-                        }
-
-                        // Render the screen
-                        rpass.set_pipeline(&screen_render_pipeline);
-                        rpass.set_bind_group(0, screen_texture.bind_group(), &[]);
-                        rpass.set_bind_group(1, &global_uniform_bind_group, &[]);
-                        rpass.set_vertex_buffer(0, screen.mesh.vertex_buffer().slice(..));
-                        rpass.set_index_buffer(
-                            screen.mesh.index_buffer().slice(..),
-                            wgpu::IndexFormat::Uint32,
-                        );
-                        rpass.draw_indexed(0..screen.mesh.indices(), 0, 0..1);
-                    }
-
-                    #[cfg(feature = "profiling")]
-                    profiling::scope!("Locate Views");
-                    log::trace!("Locate views");
-                    // Fetch the view transforms. To minimize latency, we intentionally do this
-                    // *after* recording commands to render the scene, i.e. at the last possible
-                    // moment before rendering begins in earnest on the GPU. Uniforms dependent on
-                    // this data can be sent to the GPU just-in-time by writing them to per-frame
-                    // host-visible memory which the GPU will only read once the command buffer is
-                    // submitted.
-                    let (_, views) = xr_session.locate_views(
-                        VIEW_TYPE,
-                        xr_frame_state.predicted_display_time,
-                        &xr_space,
-                    )?;
-
-                    for (view_idx, view) in views.iter().enumerate() {
-                        let mut eye = cameras
-                            .get_mut(view_idx)
-                            .context("Cannot borrow camera as mutable")?;
-                        eye.entity.position.x = view.pose.position.x;
-                        eye.entity.position.y = view.pose.position.y;
-                        eye.entity.position.z = view.pose.position.z;
-                        eye.entity.rotation.v.x = view.pose.orientation.x;
-                        eye.entity.rotation.v.y = view.pose.orientation.y;
-                        eye.entity.rotation.v.z = view.pose.orientation.z;
-                        eye.entity.rotation.s = view.pose.orientation.w;
-                        eye.entity.update_matrices(&[]);
-                        eye.update_projection_from_tangents(view.fov);
-                        let camera_uniform = camera_uniform
-                            .get_mut(view_idx)
-                            .context("Cannot borrow camera uniform buffer as mutable")?;
-                        camera_uniform.update_view_proj(eye)?;
-                    }
-
-                    log::trace!("Write views");
-                    wgpu_context.queue.write_buffer(
-                        &camera_buffer,
-                        0,
-                        bytemuck::cast_slice(camera_uniform.as_slice()),
-                    );
-
-                    if screen.ambient_enabled {
-                        log::trace!("Writing temporal blur uniforms");
-                        let ambient_texture = &ambient_texture.current().texture;
-                        let resolution = [
-                            ambient_texture.width() as f32,
-                            ambient_texture.height() as f32,
-                        ];
-                        jitter_frame = (jitter_frame + 1) % AMBIENT_BLUR_TEMPORAL_SAMPLES;
-                        temporal_blur_params.jitter =
-                            engine::jitter::get_jitter(jitter_frame, &resolution);
-                        temporal_blur_params.resolution = resolution;
-                        wgpu_context.queue.write_buffer(
-                            &temporal_blur_params_buffer,
-                            0,
-                            bytemuck::cast_slice(&[temporal_blur_params.uniform()]),
-                        );
-                    }
-
-                    #[cfg(feature = "profiling")]
-                    profiling::scope!("Encode Submit");
-
-                    log::trace!("Submit command buffer");
-                    wgpu_context.queue.submit(iter::once(encoder.finish()));
-
-                    #[cfg(feature = "profiling")]
-                    profiling::scope!("Release Swapchain");
-                    log::trace!("Release swapchain image");
-                    xr_swapchain.release_image()?;
-
-                    // End rendering and submit the images
-                    let rect = openxr::Rect2Di {
-                        offset: openxr::Offset2Di { x: 0, y: 0 },
-                        extent: openxr::Extent2Di {
-                            width: resolution.width as _,
-                            height: resolution.height as _,
-                        },
-                    };
-
-                    log::trace!("End frame stream");
-
-                    #[cfg(feature = "profiling")]
-                    {
-                        let predicted_display_time_nanos =
-                            xr_frame_state.predicted_display_time.as_nanos();
-                        profiling::scope!(
-                            "Show Time Calculation",
-                            format!("{predicted_display_time_nanos}").as_str()
-                        );
-                    }
-                    if let Err(err) = frame_stream.end(
-                        xr_frame_state.predicted_display_time,
-                        xr_context.blend_mode,
-                        &[&openxr::CompositionLayerProjection::new()
-                            .space(&xr_space)
-                            .views(&[
-                                openxr::CompositionLayerProjectionView::new()
-                                    .pose(views[0].pose)
-                                    .fov(views[0].fov)
-                                    .sub_image(
-                                        openxr::SwapchainSubImage::new()
-                                            .swapchain(xr_swapchain)
-                                            .image_array_index(0)
-                                            .image_rect(rect),
-                                    ),
-                                openxr::CompositionLayerProjectionView::new()
-                                    .pose(views[1].pose)
-                                    .fov(views[1].fov)
-                                    .sub_image(
-                                        openxr::SwapchainSubImage::new()
-                                            .swapchain(xr_swapchain)
-                                            .image_array_index(1)
-                                            .image_rect(rect),
-                                    ),
-                            ])],
-                    ) {
-                        log::error!("Failed to end frame stream: {}", err);
-                    };
-
-                    //XR Input processing
-                    if input_context.is_some() {
-                        #[cfg(feature = "profiling")]
-                        profiling::scope!("Process Inputs");
-
-                        let input_context = input_context
-                            .as_mut()
-                            .context("Cannot borrow input context as mutable")?;
-
-                        if input_context
-                            .process_inputs(
-                                &xr_session,
-                                &xr_frame_state,
-                                &xr_reference_space,
-                                &xr_view_space,
-                            )
-                            .is_ok()
-                        {
-                            if let Some(new_state) = &input_context.input_state {
-                                if new_state.hands_near_head > 0
-                                    && new_state.near_start.elapsed().as_secs() > 3
-                                {
-                                    let should_unlock_horizon = new_state.hands_near_head > 1
-                                        || (new_state.hands_near_head == 1
-                                            && new_state.count_change.elapsed().as_secs() < 1);
-
-                                    if recenter_request.is_none() {
-                                        recenter_request = Some(RecenterRequest {
-                                            horizon_locked: !should_unlock_horizon,
-                                            delay: 0,
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    if let Some(recenter_request) = recenter_request.take() {
-                        if let Err(err) = recenter_scene(
-                            &xr_session,
-                            &xr_reference_space,
-                            &xr_view_space,
-                            xr_frame_state.predicted_display_time,
-                            recenter_request.horizon_locked,
-                            recenter_request.delay,
-                            &mut xr_space,
-                        ) {
-                            log::error!("Failed to recenter scene: {}", err);
-                        }
-                    }
-                }
-
-                #[cfg(feature = "profiling")]
-                profiling::finish_frame!();
-            }
+        #[cfg(not(target_os = "android"))]
+        if mirror
+            .as_mut()
+            .is_some_and(|mirror| !mirror.poll_events(wgpu_context))
+        {
+            log::info!("Mirror window closed");
+            mirror = None;
         }
 
         // Non-XR Input processing
-        match tray_state
-            .lock()
-            .ok()
-            .context("Cannot get lock on icon tray state")?
-            .message
-            .take()
-        {
+        match handle_messages(&mut renderer, tray_state, config)? {
             Some(TrayMessages::Quit) => {
                 log::info!("Qutting app manually...");
                 break;
             }
-            Some(TrayMessages::Reload) => {
-                check_loader_invalidation(current_loader, &loaders, &mut screen_invalidated)?;
-            }
             Some(TrayMessages::Recenter(horizon_locked)) => {
-                recenter_request = Some(RecenterRequest {
-                    horizon_locked: *horizon_locked,
-                    delay: 0,
-                });
+                frame_loop.request_recenter(*horizon_locked);
             }
-            Some(TrayMessages::ToggleSettings(setting)) => match setting {
-                ToggleSetting::SwapEyes => {
-                    screen_params.swap_eyes = !screen_params.swap_eyes;
-                    screen_invalidated = true;
-                }
-                ToggleSetting::FlipX => {
-                    screen_params.flip_x = !screen_params.flip_x;
-                    match stereo_mode {
-                        StereoMode::Sbs | StereoMode::FullSbs => {
-                            screen_params.swap_eyes = !screen_params.swap_eyes;
-                        }
-                        _ => {}
-                    }
-                    screen_invalidated = true;
-                }
-                ToggleSetting::FlipY => {
-                    screen_params.flip_y = !screen_params.flip_y;
-                    match stereo_mode {
-                        StereoMode::Tab | StereoMode::FullTab => {
-                            screen_params.swap_eyes = !screen_params.swap_eyes;
-                        }
-                        _ => {}
-                    }
-                    screen_invalidated = true;
-                }
-                ToggleSetting::AmbientLight => {
-                    screen_params.ambient = !screen_params.ambient;
-                    screen.change_ambient_mode(screen_params.ambient);
-                    screen_invalidated = true;
-                }
-            },
             _ => {}
         }
+    }
+
+    Ok(())
+}
 
-        if let Some(ConfigContext {
-            config_notifier: Some(config_receiver),
+// Falls back to the command line when there is no configuration file
+fn initial_params(config: &Option<ConfigContext>) -> AppConfig {
+    match config {
+        Some(ConfigContext {
+            last_config: Some(config),
             ..
-        }) = config
-        {
-            if config_receiver.try_recv().is_ok() {
-                let config = config
-                    .as_mut()
-                    .context("Cannot borrow configuration as mutable")?;
-                let config_changed = config.update_config().is_ok();
-
-                if config_changed {
-                    if let Some(new_params) = config.last_config.clone() {
-                        screen_params = new_params;
-                        screen.change_scale(screen_params.scale);
-                        screen.change_distance(-screen_params.distance);
-                        screen.change_ambient_mode(screen_params.ambient);
-                        screen_invalidated = true;
-                    }
+        }) => config.clone(),
+        _ => AppConfig::parse(),
+    }
+}
+
+// Applies the tray entries and configuration file changes every frontend shares, returns the
+// messages left to the frontend (quitting and recentering)
+fn handle_messages(
+    renderer: &mut Renderer,
+    tray_state: &Arc<Mutex<TrayState>>,
+    config: &mut Option<ConfigContext>,
+) -> anyhow::Result<Option<&'static TrayMessages>> {
+    let message = tray_state
+        .lock()
+        .ok()
+        .context("Cannot get lock on icon tray state")?
+        .message
+        .take();
+    match message {
+        Some(TrayMessages::Reload) => {
+            renderer.reload()?;
+        }
+        Some(TrayMessages::SelectLoader(name)) => {
+            renderer.select_loader(*name);
+        }
+        Some(TrayMessages::LoaderAction(action)) => {
+            renderer.handle_loader_action(action);
+        }
+        Some(TrayMessages::ToggleSettings(setting)) => {
+            renderer.apply_config(toggle_setting(
+                renderer.screen_params(),
+                renderer.stereo_mode(),
+                setting,
+            ));
+        }
+        _ => {}
+    }
+
+    if let Some(ConfigContext {
+        config_notifier: Some(config_receiver),
+        ..
+    }) = config
+    {
+        if config_receiver.try_recv().is_ok() {
+            let config = config
+                .as_mut()
+                .context("Cannot borrow configuration as mutable")?;
+            let config_changed = config.update_config().is_ok();
+
+            if config_changed {
+                if let Some(new_params) = config.last_config.clone() {
+                    renderer.apply_config(new_params);
                 }
             }
         }
     }
-
-    Ok(())
+    Ok(message)
 }
 
-fn get_ambient_texture(
-    screen_texture: &Texture2D<Bound>,
-    aspect: f32,
-    stereo_mode: &StereoMode,
+// Shows the scene in a desktop window with a mouse-look camera when no OpenXR runtime is around
+#[cfg(not(target_os = "android"))]
+fn run_desktop(
     wgpu_context: &WgpuContext,
-    bind_group_layout: &BindGroupLayout,
-) -> anyhow::Result<RoundRobinTextureBuffer<Texture2D<Bound>, 3>> {
-    let height_multiplier = match stereo_mode {
-        StereoMode::FullTab => 2,
-        _ => 1,
-    };
+    desktop_context: &mut DesktopContext,
+    tray_state: &Arc<Mutex<TrayState>>,
+    config: &mut Option<ConfigContext>,
+) -> anyhow::Result<()> {
+    let screen_params = initial_params(config);
+    let (window, surface) = desktop_context.take_window()?;
+    let mut window = MirrorWindow::preview(
+        wgpu_context,
+        window,
+        surface,
+        screen_params.mirror.unwrap_or(MirrorMode::Left),
+    )?;
+    let mut renderer = Renderer::new(wgpu_context, screen_params)?;
+    let target = OffscreenTarget::new(&wgpu_context.device, DESKTOP_RESOLUTION, DESKTOP_RESOLUTION);
+    let mut cameras = [Camera::default(), Camera::default()];
+    let mut mouse_look = MouseLook::default();
+    let start_time = std::time::Instant::now();
 
-    let width_multiplier = match stereo_mode {
-        StereoMode::FullSbs => 2,
-        _ => 1,
-    };
+    loop {
+        #[cfg(feature = "profiling")]
+        profiling::scope!("main loop");
 
-    let buffer = RoundRobinTextureBuffer::new(
-        (0..3)
-            .map(|idx| {
-                screen_texture
-                    .as_render_target_with_extent(
-                        format!("Ambient Texture {idx}").as_str(),
-                        wgpu::Extent3d {
-                            width: AMBIENT_BLUR_BASE_RES * width_multiplier,
-                            height: (AMBIENT_BLUR_BASE_RES as f32 / aspect) as u32
-                                * height_multiplier,
-                            depth_or_array_layers: screen_texture.texture.depth_or_array_layers(),
-                        },
-                        SWAPCHAIN_COLOR_FORMAT,
-                        &wgpu_context.device,
-                    )
-                    .bind_to_context(wgpu_context, bind_group_layout)
-            })
-            .collect::<Vec<_>>()
-            .try_into()
-            .ok()
-            .context("Cannot create ambient texture buffer")?,
-    );
+        renderer.update_source(wgpu_context)?;
+        if !window.poll_events(wgpu_context) {
+            log::info!("Preview window closed");
+            break;
+        }
 
-    Ok(buffer)
-}
+        mouse_look.rotate(window.take_look_delta());
+        mouse_look.update_cameras(&mut cameras, window.eye_aspect_ratio());
+        renderer.render_cameras(
+            wgpu_context,
+            &cameras,
+            &target.texture.view,
+            start_time.elapsed(),
+        )?;
+        window.present(wgpu_context, &target.texture.view);
 
-fn recenter_scene(
-    xr_session: &openxr::Session<openxr::Vulkan>,
-    xr_reference_space: &openxr::Space,
-    xr_view_space: &openxr::Space,
-    last_predicted_frame_time: openxr::Time,
-    horizon_locked: bool,
-    delay: i64,
-    xr_space: &mut openxr::Space,
-) -> anyhow::Result<()> {
-    let mut view_location_pose = xr_view_space
-        .locate(
-            xr_reference_space,
-            openxr::Time::from_nanos(last_predicted_frame_time.as_nanos() - delay),
-        )?
-        .pose;
-    let quaternion =
-        cgmath::Quaternion::from(mint::Quaternion::from(view_location_pose.orientation));
-    let forward = cgmath::Vector3::new(0.0, 0.0, 1.0);
-    let look_dir = quaternion * forward;
-    let yaw = cgmath::Rad(look_dir.x.atan2(look_dir.z));
-    let clean_orientation = if horizon_locked {
-        cgmath::Quaternion::from_angle_y(yaw)
-    } else {
-        let padj = (look_dir.x * look_dir.x + look_dir.z * look_dir.z).sqrt();
-        let pitch = -cgmath::Rad(look_dir.y.atan2(padj));
-        cgmath::Quaternion::from_angle_y(yaw) * cgmath::Quaternion::from_angle_x(pitch)
-    };
-    view_location_pose.orientation = openxr::Quaternionf {
-        x: clean_orientation.v.x,
-        y: clean_orientation.v.y,
-        z: clean_orientation.v.z,
-        w: clean_orientation.s,
-    };
-    *xr_space = xr_session.create_reference_space(ReferenceSpaceType::LOCAL, view_location_pose)?;
+        #[cfg(feature = "profiling")]
+        profiling::finish_frame!();
+
+        match handle_messages(&mut renderer, tray_state, config)? {
+            Some(TrayMessages::Quit) => {
+                log::info!("Qutting app manually...");
+                break;
+            }
+            Some(TrayMessages::Recenter(_)) => mouse_look.reset(),
+            _ => {}
+        }
+    }
 
     Ok(())
 }
 
-fn check_loader_invalidation(
-    current_loader: Option<usize>,
-    loaders: &[Box<dyn loaders::Loader>],
-    screen_invalidated: &mut bool,
-) -> anyhow::Result<()> {
-    if let Some(loader) = current_loader {
-        if loaders
-            .get(loader)
-            .context("Error getting loader")?
-            .is_invalid()
-        {
-            log::info!("Reloading app...");
-            *screen_invalidated = true;
+// Returns a copy of the settings with a tray toggle applied
+fn toggle_setting(
+    screen_params: &AppConfig,
+    stereo_mode: StereoMode,
+    setting: &ToggleSetting,
+) -> AppConfig {
+    let mut screen_params = screen_params.clone();
+    match setting {
+        ToggleSetting::SwapEyes => {
+            screen_params.swap_eyes = !screen_params.swap_eyes;
+        }
+        ToggleSetting::FlipX => {
+            screen_params.flip_x = !screen_params.flip_x;
+            if stereo_mode.flip_swaps_eyes(true) {
+                screen_params.swap_eyes = !screen_params.swap_eyes;
+            }
+        }
+        ToggleSetting::FlipY => {
+            screen_params.flip_y = !screen_params.flip_y;
+            if stereo_mode.flip_swaps_eyes(false) {
+                screen_params.swap_eyes = !screen_params.swap_eyes;
+            }
+        }
+        ToggleSetting::AmbientLight => {
+            screen_params.ambient = !screen_params.ambient;
+        }
+        ToggleSetting::Anaglyph => {
+            screen_params.anaglyph = screen_params.anaglyph.next();
         }
-    } else {
-        *screen_invalidated = true;
     }
+    screen_params
+}
 
+// Renders the configured source without an OpenXR runtime and saves both eyes side by side
+fn run_headless(
+    wgpu_context: &WgpuContext,
+    screen_params: &AppConfig,
+    output_path: &str,
+) -> anyhow::Result<()> {
+    let layers = render_headless(
+        wgpu_context,
+        screen_params,
+        HEADLESS_RESOLUTION,
+        HEADLESS_FRAMES,
+    )?;
+    side_by_side(&layers).save(output_path)?;
+    log::info!(
+        "Saved {} headless frames to {}",
+        HEADLESS_FRAMES,
+        output_path
+    );
     Ok(())
 }
 
-fn try_to_load_texture(
-    loaders: &mut [Box<dyn loaders::Loader>],
+// Renders frames from the fixed headless views and reads back every eye of the last one
+fn render_headless(
     wgpu_context: &WgpuContext,
-) -> Option<(Texture2D<Unbound>, f32, StereoMode, usize)> {
-    for (loader_idx, loader) in loaders.iter_mut().enumerate() {
-        if let Ok(tex_source) = loader.load(&wgpu_context.instance, &wgpu_context.device) {
-            return Some((
-                tex_source.texture,
-                (tex_source.width as f32 / 2.0) / tex_source.height as f32,
-                tex_source.stereo_mode,
-                loader_idx,
-            ));
-        }
+    screen_params: &AppConfig,
+    resolution: u32,
+    frames: u32,
+) -> anyhow::Result<Vec<image::RgbaImage>> {
+    let mut renderer = Renderer::new(wgpu_context, screen_params.clone())?;
+    if !renderer.has_source() {
+        anyhow::bail!("No source available, try --test-pattern");
     }
-    None
+
+    let views = headless_views();
+    let target = OffscreenTarget::new(&wgpu_context.device, resolution, resolution);
+    let start_time = std::time::Instant::now();
+    for _ in 0..frames {
+        renderer.render(
+            wgpu_context,
+            &views,
+            &target.texture.view,
+            start_time.elapsed(),
+        )?;
+    }
+
+    target.read_layers(&wgpu_context.device, &wgpu_context.queue)
+}
+
+fn side_by_side(layers: &[image::RgbaImage]) -> image::RgbaImage {
+    let (width, height) = layers.first().map_or((0, 0), |layer| layer.dimensions());
+    let mut output = image::RgbaImage::new(width * layers.len() as u32, height);
+    for (eye, layer) in layers.iter().enumerate() {
+        image::imageops::replace(&mut output, layer, (eye as u32 * width) as i64, 0);
+    }
+    output
 }
 
 #[cfg_attr(target_os = "android", ndk_glue::main(backtrace = "full"))]
@@ -1225,3 +607,180 @@ pub fn main() {
         log::error!("VRScreenCap closed unexpectedly with an error: {}", err);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::loaders::{registry, test_pattern_loader::TestPattern};
+
+    // Small eyes keep software rendering fast
+    const GOLDEN_RESOLUTION: u32 = 256;
+    // Channels closer than this to the reference count as matching
+    const GOLDEN_CHANNEL_TOLERANCE: u8 = 8;
+    // Fraction of mismatched pixels allowed per eye, absorbs rasterization differences between drivers
+    const GOLDEN_MISMATCH_TOLERANCE: f64 = 0.002;
+
+    fn pattern_config(pattern: TestPattern) -> AppConfig {
+        AppConfig {
+            test_pattern: Some(pattern),
+            loader_priority: vec![registry::TEST_PATTERN_LOADER.to_owned()],
+            swap_eyes: false,
+            ..Default::default()
+        }
+    }
+
+    fn mismatch_ratio(actual: &image::RgbaImage, expected: &image::RgbaImage) -> f64 {
+        let mismatched =
+            actual
+                .pixels()
+                .zip(expected.pixels())
+                .filter(|(actual, expected)| {
+                    actual.0.iter().zip(expected.0).any(|(actual, expected)| {
+                        actual.abs_diff(expected) > GOLDEN_CHANNEL_TOLERANCE
+                    })
+                })
+                .count();
+        mismatched as f64 / (actual.width() * actual.height()) as f64
+    }
+
+    fn golden_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("golden")
+            .join(format!("{name}.png"))
+    }
+
+    // Renders through the headless path on a software adapter (references come from lavapipe) and
+    // compares both eyes with tests/golden/<name>.png. Only UPDATE_GOLDEN=1 writes the references.
+    fn assert_golden(name: &str, config: AppConfig) {
+        // References only hold for software rendering, machines without it skip the comparison
+        let mut headless_context = HeadlessContext {
+            force_fallback_adapter: true,
+        };
+        let Ok(wgpu_context) = headless_context.load_wgpu() else {
+            log::warn!("Skipping golden test {name}, no software Vulkan adapter like lavapipe");
+            return;
+        };
+        let layers =
+            render_headless(&wgpu_context, &config, GOLDEN_RESOLUTION, HEADLESS_FRAMES).unwrap();
+
+        let path = golden_path(name);
+        if std::env::var("UPDATE_GOLDEN").as_deref() == Ok("1") {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            side_by_side(&layers).save(&path).unwrap();
+            log::info!("Wrote reference image {}", path.display());
+            return;
+        }
+
+        let reference = image::open(&path)
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Missing reference {} ({err}), create it with UPDATE_GOLDEN=1",
+                    path.display()
+                )
+            })
+            .to_rgba8();
+        assert_eq!(
+            reference.dimensions(),
+            (GOLDEN_RESOLUTION * layers.len() as u32, GOLDEN_RESOLUTION),
+            "{name} reference has the wrong size"
+        );
+        for (eye, layer) in layers.iter().enumerate() {
+            let expected = image::imageops::crop_imm(
+                &reference,
+                eye as u32 * GOLDEN_RESOLUTION,
+                0,
+                GOLDEN_RESOLUTION,
+                GOLDEN_RESOLUTION,
+            )
+            .to_image();
+            let ratio = mismatch_ratio(layer, &expected);
+            if ratio > GOLDEN_MISMATCH_TOLERANCE {
+                let actual_path = std::env::temp_dir().join(format!("{name}.actual.png"));
+                side_by_side(&layers).save(&actual_path).unwrap();
+                panic!(
+                    "{name} eye {eye} differs from the reference on {:.2}% of the pixels, rendered image saved to {}",
+                    ratio * 100.0,
+                    actual_path.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn golden_comparison_tolerates_small_differences() {
+        let expected = image::RgbaImage::from_pixel(10, 10, image::Rgba([100, 150, 200, 255]));
+        let mut actual = expected.clone();
+        actual.put_pixel(0, 0, image::Rgba([104, 146, 200, 255]));
+        assert_eq!(mismatch_ratio(&actual, &expected), 0.0);
+        actual.put_pixel(1, 0, image::Rgba([100, 150, 220, 255]));
+        assert_eq!(mismatch_ratio(&actual, &expected), 0.01);
+    }
+
+    #[test]
+    fn golden_eye_markers_curved() {
+        assert_golden(
+            "eye_markers_curved",
+            pattern_config(TestPattern::EyeMarkers),
+        );
+    }
+
+    #[test]
+    fn golden_eye_markers_flat() {
+        assert_golden(
+            "eye_markers_flat",
+            AppConfig {
+                x_curvature: 0.0,
+                y_curvature: 0.0,
+                ..pattern_config(TestPattern::EyeMarkers)
+            },
+        );
+    }
+
+    #[test]
+    fn golden_eye_markers_swapped() {
+        assert_golden(
+            "eye_markers_swapped",
+            AppConfig {
+                swap_eyes: true,
+                ..pattern_config(TestPattern::EyeMarkers)
+            },
+        );
+    }
+
+    #[test]
+    fn golden_eye_markers_flipped() {
+        assert_golden(
+            "eye_markers_flipped",
+            AppConfig {
+                flip_x: true,
+                flip_y: true,
+                ..pattern_config(TestPattern::EyeMarkers)
+            },
+        );
+    }
+
+    #[test]
+    fn golden_convergence_grid_top_and_bottom() {
+        assert_golden(
+            "convergence_grid_top_and_bottom",
+            AppConfig {
+                test_pattern_stereo_mode: StereoMode::FullTab,
+                ..pattern_config(TestPattern::ConvergenceGrid)
+            },
+        );
+    }
+
+    #[test]
+    fn golden_color_bars_ambient() {
+        assert_golden(
+            "color_bars_ambient",
+            AppConfig {
+                ambient: true,
+                ..pattern_config(TestPattern::ColorBars)
+            },
+        );
+    }
+}
@@ -1,28 +1,327 @@
-use wgpu::{Device, Instance};
+use std::{path::Path, time::Duration};
 
-use crate::engine::texture::{Texture2D, Unbound};
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use wgpu::{CommandEncoder, Device, Instance, Queue};
 
+use crate::engine::{
+    texture::{Bound, Texture2D, Unbound},
+    yuv::YuvPlanes,
+};
+
+#[cfg(target_os = "linux")]
+pub mod dmabuf_loader;
+pub mod image_loader;
 #[cfg(target_os = "windows")]
 pub mod katanga_loader;
+pub mod net_loader;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub mod pipewire_loader;
+pub mod registry;
+#[cfg(target_os = "linux")]
+pub mod shm_loader;
+pub mod slideshow_loader;
+pub mod stereo_photo;
+pub mod test_pattern_loader;
+#[cfg(all(target_os = "linux", feature = "v4l"))]
+pub mod v4l_loader;
+#[cfg(feature = "video")]
+pub mod video_loader;
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub mod x11_loader;
+
+pub struct FrameContext<'a> {
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+    pub encoder: &'a mut CommandEncoder,
+    pub texture: &'a Texture2D<Bound>,
+    // Chroma plane of planar sources, the texture then holds the luma plane
+    pub planes: Option<&'a YuvPlanes<Bound>>,
+    // Predicted display time of the frame being rendered
+    pub display_time: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameUpdate {
+    // The texture is written outside of the loader (e.g. shared GPU textures), any frame can be new
+    Untracked,
+    Unchanged,
+    NewFrame,
+}
+
+impl FrameUpdate {
+    pub fn is_new_frame(&self) -> bool {
+        *self != FrameUpdate::Unchanged
+    }
+}
 
 pub struct TextureSource {
     pub texture: Texture2D<Unbound>,
     pub width: u32,
     pub height: u32,
     pub stereo_mode: StereoMode,
+    // Set by loaders handing over planar frames, the texture is then their luma plane
+    pub planes: Option<YuvPlanes<Unbound>>,
 }
 
-#[allow(unused)]
+impl TextureSource {
+    // The frame sequential buffer only keeps the bound texture, the chroma of planar frames would be lost
+    pub fn validate(self) -> anyhow::Result<Self> {
+        if self.planes.is_some() && self.stereo_mode == StereoMode::FrameSequential {
+            bail!("Frame sequential sources can't hand over planar frames");
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
 pub enum StereoMode {
     Mono,
     Sbs,
     Tab,
     FullSbs,
     FullTab,
+    RowInterlaced,
+    ColumnInterlaced,
+    Checkerboard,
+    FrameSequential,
+}
+
+impl StereoMode {
+    // UV offset (xy) and scale (zw) of the texture region holding the given eye (0 = left, 1 = right)
+    pub fn eye_uv_rect(&self, eye: usize) -> [f32; 4] {
+        let eye = eye.min(1) as f32;
+        match self {
+            StereoMode::Sbs | StereoMode::FullSbs => [0.5 * eye, 0.0, 0.5, 1.0],
+            StereoMode::Tab | StereoMode::FullTab => [0.0, 0.5 * eye, 1.0, 0.5],
+            // Interlaced eyes cover the whole texture, the shader picks the matching texels
+            StereoMode::Mono
+            | StereoMode::RowInterlaced
+            | StereoMode::ColumnInterlaced
+            | StereoMode::Checkerboard
+            | StereoMode::FrameSequential => [0.0, 0.0, 1.0, 1.0],
+        }
+    }
+
+    // Aspect ratio of a single eye once displayed, half resolution modes are stretched back
+    pub fn aspect_ratio(&self, width: u32, height: u32) -> f32 {
+        let (width, height) = (width as f32, height as f32);
+        match self {
+            StereoMode::FullSbs => (width / 2.0) / height,
+            StereoMode::FullTab => width / (height / 2.0),
+            _ => width / height,
+        }
+    }
+
+    // Whether mirroring the texture horizontally or vertically also swaps the eyes
+    pub fn flip_swaps_eyes(&self, horizontal: bool) -> bool {
+        match self {
+            StereoMode::Sbs | StereoMode::FullSbs | StereoMode::ColumnInterlaced => horizontal,
+            StereoMode::Tab | StereoMode::FullTab | StereoMode::RowInterlaced => !horizontal,
+            StereoMode::Checkerboard => true,
+            StereoMode::Mono | StereoMode::FrameSequential => false,
+        }
+    }
+
+    pub fn width_multiplier(&self) -> u32 {
+        match self {
+            StereoMode::Sbs | StereoMode::FullSbs => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn height_multiplier(&self) -> u32 {
+        match self {
+            StereoMode::Tab | StereoMode::FullTab => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LoaderAction {
+    Next,
+    Previous,
+    TogglePlayback,
+    // Relative seek in seconds
+    Seek(i32),
 }
 
 pub trait Loader {
-    fn load(&mut self, instance: &Instance, device: &Device) -> anyhow::Result<TextureSource>;
+    fn load(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource>;
 
     fn is_invalid(&self) -> bool;
+
+    // Called when the user reloads, loaders that gave up on a broken source try it again
+    fn clear_error(&mut self) {}
+
+    fn handle_action(&mut self, _action: &LoaderAction) {}
+
+    // Called before every rendered frame, streaming sources write their latest pixels into the
+    // bound texture here and report whether the displayed frame changed
+    fn update(&mut self, _context: &mut FrameContext) -> anyhow::Result<FrameUpdate> {
+        Ok(FrameUpdate::Untracked)
+    }
+}
+
+impl TryFrom<u32> for StereoMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u32) -> anyhow::Result<Self> {
+        StereoMode::value_variants()
+            .iter()
+            .copied()
+            .find(|stereo_mode| *stereo_mode as u32 == value)
+            .with_context(|| format!("Unknown stereo mode {}", value))
+    }
+}
+
+// Rejects frames too big for a single texture on this device, their size often comes from another
+// process
+pub fn check_texture_size(device: &Device, width: u32, height: u32) -> anyhow::Result<()> {
+    let max_dimension = device.limits().max_texture_dimension_2d;
+    if width > max_dimension || height > max_dimension {
+        bail!(
+            "Frame size {}x{} exceeds the maximum texture size {}",
+            width,
+            height,
+            max_dimension
+        );
+    }
+    Ok(())
+}
+
+// Guesses the stereo layout from common file naming conventions (e.g. photo_sbs.png, clip-ou.jpg)
+pub fn stereo_mode_from_path(path: &Path, default: StereoMode) -> StereoMode {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    if let Some("jps" | "mpo") = extension.as_deref() {
+        return StereoMode::FullSbs;
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    // Right-left (rl) files keep the default, no stereo mode swaps the eyes of a side by side pair
+    stem.split(|c: char| !c.is_ascii_alphanumeric())
+        .rev()
+        .find_map(|token| match token {
+            "sbs" | "lr" | "fsbs" => Some(StereoMode::FullSbs),
+            "hsbs" | "halfsbs" => Some(StereoMode::Sbs),
+            "tab" | "ou" | "tb" | "ftab" | "fou" => Some(StereoMode::FullTab),
+            "htab" | "hou" | "halftab" | "halfou" => Some(StereoMode::Tab),
+            "mono" | "2d" => Some(StereoMode::Mono),
+            _ => None,
+        })
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{stereo_mode_from_path, StereoMode};
+
+    // Mirrors uv_to_stereo_uv in shader.wgsl
+    fn stereo_uv(mode: StereoMode, eye: usize, uv: [f32; 2]) -> [f32; 2] {
+        let rect = mode.eye_uv_rect(eye);
+        [uv[0] * rect[2] + rect[0], uv[1] * rect[3] + rect[1]]
+    }
+
+    #[test]
+    fn mono_uses_whole_texture_for_both_eyes() {
+        for eye in 0..2 {
+            assert_eq!(stereo_uv(StereoMode::Mono, eye, [0.0, 0.0]), [0.0, 0.0]);
+            assert_eq!(stereo_uv(StereoMode::Mono, eye, [1.0, 1.0]), [1.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn side_by_side_splits_horizontally() {
+        for mode in [StereoMode::Sbs, StereoMode::FullSbs] {
+            assert_eq!(stereo_uv(mode, 0, [0.0, 0.0]), [0.0, 0.0]);
+            assert_eq!(stereo_uv(mode, 0, [1.0, 1.0]), [0.5, 1.0]);
+            assert_eq!(stereo_uv(mode, 1, [0.0, 0.0]), [0.5, 0.0]);
+            assert_eq!(stereo_uv(mode, 1, [1.0, 1.0]), [1.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn top_and_bottom_splits_vertically() {
+        for mode in [StereoMode::Tab, StereoMode::FullTab] {
+            assert_eq!(stereo_uv(mode, 0, [0.0, 0.0]), [0.0, 0.0]);
+            assert_eq!(stereo_uv(mode, 0, [1.0, 1.0]), [1.0, 0.5]);
+            assert_eq!(stereo_uv(mode, 1, [0.0, 0.0]), [0.0, 0.5]);
+            assert_eq!(stereo_uv(mode, 1, [1.0, 1.0]), [1.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn aspect_ratio_accounts_for_packing() {
+        assert_eq!(StereoMode::Mono.aspect_ratio(1920, 1080), 1920.0 / 1080.0);
+        assert_eq!(
+            StereoMode::FullSbs.aspect_ratio(3840, 1080),
+            1920.0 / 1080.0
+        );
+        assert_eq!(
+            StereoMode::FullTab.aspect_ratio(1920, 2160),
+            1920.0 / 1080.0
+        );
+        assert_eq!(StereoMode::Sbs.aspect_ratio(1920, 1080), 1920.0 / 1080.0);
+        assert_eq!(StereoMode::Tab.aspect_ratio(1920, 1080), 1920.0 / 1080.0);
+        assert_eq!(
+            StereoMode::RowInterlaced.aspect_ratio(1920, 1080),
+            1920.0 / 1080.0
+        );
+        assert_eq!(
+            StereoMode::Checkerboard.aspect_ratio(1920, 1080),
+            1920.0 / 1080.0
+        );
+    }
+
+    #[test]
+    fn interlaced_modes_cover_the_whole_texture() {
+        for mode in [
+            StereoMode::RowInterlaced,
+            StereoMode::ColumnInterlaced,
+            StereoMode::Checkerboard,
+            StereoMode::FrameSequential,
+        ] {
+            for eye in 0..2 {
+                assert_eq!(mode.eye_uv_rect(eye), [0.0, 0.0, 1.0, 1.0]);
+            }
+        }
+    }
+
+    #[test]
+    fn file_name_tokens_select_stereo_mode() {
+        let mode = |name: &str| stereo_mode_from_path(Path::new(name), StereoMode::Mono);
+        assert_eq!(mode("photo_sbs.png"), StereoMode::FullSbs);
+        assert_eq!(mode("photo-lr.png"), StereoMode::FullSbs);
+        assert_eq!(mode("clip.hsbs.mp4"), StereoMode::Sbs);
+        assert_eq!(mode("clip-ou.jpg"), StereoMode::FullTab);
+        assert_eq!(mode("picture.jps"), StereoMode::FullSbs);
+        assert_eq!(mode("holiday.png"), StereoMode::Mono);
+    }
+
+    #[test]
+    fn right_left_files_keep_the_default_mode() {
+        assert_eq!(
+            stereo_mode_from_path(Path::new("photo_rl.png"), StereoMode::Mono),
+            StereoMode::Mono
+        );
+        assert_eq!(
+            stereo_mode_from_path(Path::new("photo_rl.png"), StereoMode::Tab),
+            StereoMode::Tab
+        );
+    }
 }
@@ -0,0 +1,382 @@
+use std::{
+    ffi::CStr,
+    os::{
+        fd::{AsRawFd, OwnedFd, RawFd},
+        unix::net::UnixListener,
+    },
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context};
+use ash::vk;
+use wgpu::{Device, Instance, Queue, TextureFormat};
+use wgpu_hal::{api::Vulkan, MemoryFlags, TextureDescriptor, TextureUses};
+
+use crate::{
+    conversions::{
+        map_texture_format, query_format_capabilities, unmap_drm_format, vulkan_image_to_texture,
+    },
+    engine::texture::{Texture2D, Unbound},
+    protocol::dmabuf::{
+        receive_dmabuf, DmaBufFrameDescriptor, DRM_FORMAT_MOD_INVALID, DRM_FORMAT_MOD_LINEAR,
+    },
+};
+
+use super::{check_texture_size, Loader, StereoMode, TextureSource};
+
+type ReceivedBuffer = Arc<Mutex<Option<(DmaBufFrameDescriptor, OwnedFd)>>>;
+
+// Imports DMA-BUFs received over a Unix socket, producers send a new descriptor only when
+// their buffer changes, frames are then shared in place without copies
+pub struct DmaBufLoader {
+    socket_path: PathBuf,
+    received: ReceivedBuffer,
+    listening: bool,
+    current: Option<(DmaBufFrameDescriptor, OwnedFd)>,
+}
+
+impl DmaBufLoader {
+    pub fn new(socket_path: impl Into<PathBuf>) -> Self {
+        Self {
+            socket_path: socket_path.into(),
+            received: Arc::new(Mutex::new(None)),
+            listening: false,
+            current: None,
+        }
+    }
+
+    fn ensure_listening(&mut self) -> anyhow::Result<()> {
+        if self.listening {
+            return Ok(());
+        }
+
+        // Remove stale sockets left behind by previous runs
+        let _ = std::fs::remove_file(&self.socket_path);
+        let listener = UnixListener::bind(&self.socket_path)
+            .with_context(|| format!("Cannot bind {}", self.socket_path.display()))?;
+        let received = self.received.clone();
+        std::thread::Builder::new()
+            .name("DMA-BUF Receiver".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    log::info!("DMA-BUF producer connected");
+                    loop {
+                        match receive_dmabuf(&stream) {
+                            Ok(Some(buffer)) => {
+                                if let Ok(mut received) = received.lock() {
+                                    *received = Some(buffer);
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(err) => {
+                                log::error!("Failed to receive DMA-BUF: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    log::info!("DMA-BUF producer disconnected");
+                }
+            })?;
+
+        log::info!("Listening for DMA-BUFs on {}", self.socket_path.display());
+        self.listening = true;
+        Ok(())
+    }
+}
+
+// Single plane DMA-BUF as described by its producer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DmaBufPlane {
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    pub stride: u32,
+    pub offset: u32,
+    pub modifier: u64,
+}
+
+// First device extension the import relies on that the device was created without, ash panics
+// when calling into a missing one
+fn missing_extension(device: &wgpu_hal::vulkan::Device) -> Option<&'static CStr> {
+    [
+        ash::extensions::khr::ExternalMemoryFd::name(),
+        vk::ExtExternalMemoryDmaBufFn::name(),
+        vk::ExtImageDrmFormatModifierFn::name(),
+    ]
+    .into_iter()
+    .find(|extension| !device.enabled_device_extensions().contains(extension))
+}
+
+pub fn supports_import(device: &Device) -> bool {
+    unsafe {
+        device.as_hal::<Vulkan, _, _>(|device| {
+            device.map(|device| missing_extension(device).is_none())
+        })
+    }
+    .unwrap_or(false)
+}
+
+// Releases the imported image and its memory once wgpu is done with the texture
+struct ImportedImage {
+    device: ash::Device,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+}
+
+impl Drop for ImportedImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image(self.image, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+// Imports the DMA-BUF as a sampled texture sharing the producer memory, the fd is duplicated
+// so the caller keeps its own
+pub fn import_dmabuf(
+    device: &Device,
+    plane: &DmaBufPlane,
+    dmabuf_fd: RawFd,
+    label: &str,
+) -> anyhow::Result<wgpu::Texture> {
+    check_texture_size(device, plane.width, plane.height)?;
+    let vk_format = map_texture_format(plane.format);
+    // Producers that don't know their modifier can only share linear buffers
+    let modifier = match plane.modifier {
+        DRM_FORMAT_MOD_INVALID => DRM_FORMAT_MOD_LINEAR,
+        modifier => modifier,
+    };
+
+    let imported: Option<anyhow::Result<ImportedImage>> = unsafe {
+        device.as_hal::<Vulkan, _, _>(|device| {
+            device.map(|device| {
+                if let Some(missing) = missing_extension(device) {
+                    bail!(
+                        "Cannot import DMA-BUFs, the device was created without {:?}",
+                        missing
+                    );
+                }
+
+                let raw_device = device.raw_device();
+                let raw_instance = device.shared_instance().raw_instance();
+                let handle_type = vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT;
+
+                // Checked up front, create_image fails with an opaque error otherwise
+                let capabilities = query_format_capabilities(
+                    raw_instance,
+                    device.raw_physical_device(),
+                    vk_format,
+                    vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT,
+                    vk::ImageCreateFlags::empty(),
+                    Some(modifier),
+                    Some(handle_type),
+                );
+                // The screen sampler filters linearly
+                if !capabilities.importable || !capabilities.sampled || !capabilities.filterable {
+                    bail!(
+                        "The device cannot import {:?} DMA-BUFs with modifier {:#x}",
+                        plane.format,
+                        modifier
+                    );
+                }
+
+                let plane_layouts = [vk::SubresourceLayout {
+                    offset: plane.offset as u64,
+                    size: 0,
+                    row_pitch: plane.stride as u64,
+                    array_pitch: 0,
+                    depth_pitch: 0,
+                }];
+                let mut modifier_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::builder()
+                    .drm_format_modifier(modifier)
+                    .plane_layouts(&plane_layouts);
+                let mut ext_create_info =
+                    vk::ExternalMemoryImageCreateInfo::builder().handle_types(handle_type);
+                let image_create_info = vk::ImageCreateInfo::builder()
+                    .push_next(&mut ext_create_info)
+                    .push_next(&mut modifier_info)
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(vk_format)
+                    .extent(vk::Extent3D {
+                        width: plane.width,
+                        height: plane.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+                    .usage(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::SAMPLED)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+                let raw_image = raw_device.create_image(&image_create_info, None)?;
+                let memory_requirements = raw_device.get_image_memory_requirements(raw_image);
+
+                let external_memory_fd =
+                    ash::extensions::khr::ExternalMemoryFd::new(raw_instance, raw_device);
+                let mut fd_properties = vk::MemoryFdPropertiesKHR::default();
+                if let Err(err) = external_memory_fd.get_memory_fd_properties(
+                    handle_type,
+                    dmabuf_fd,
+                    &mut fd_properties,
+                ) {
+                    raw_device.destroy_image(raw_image, None);
+                    return Err(err.into());
+                }
+
+                let memory_type_bits =
+                    memory_requirements.memory_type_bits & fd_properties.memory_type_bits;
+                if memory_type_bits == 0 {
+                    raw_device.destroy_image(raw_image, None);
+                    bail!("No memory type can import the DMA-BUF");
+                }
+
+                // Vulkan takes ownership of the imported fd, keep ours for reloads
+                let import_fd = match nix::unistd::dup(dmabuf_fd) {
+                    Ok(import_fd) => import_fd,
+                    Err(err) => {
+                        raw_device.destroy_image(raw_image, None);
+                        return Err(err.into());
+                    }
+                };
+                let mut import_memory_info = vk::ImportMemoryFdInfoKHR::builder()
+                    .handle_type(handle_type)
+                    .fd(import_fd);
+                let mut dedicated_allocate_info =
+                    vk::MemoryDedicatedAllocateInfo::builder().image(raw_image);
+                let allocate_info = vk::MemoryAllocateInfo::builder()
+                    .push_next(&mut import_memory_info)
+                    .push_next(&mut dedicated_allocate_info)
+                    .allocation_size(memory_requirements.size)
+                    .memory_type_index(memory_type_bits.trailing_zeros());
+
+                let allocated_memory = match raw_device.allocate_memory(&allocate_info, None) {
+                    Ok(allocated_memory) => allocated_memory,
+                    Err(err) => {
+                        let _ = nix::unistd::close(import_fd);
+                        raw_device.destroy_image(raw_image, None);
+                        return Err(err.into());
+                    }
+                };
+
+                let imported = ImportedImage {
+                    device: raw_device.clone(),
+                    image: raw_image,
+                    memory: allocated_memory,
+                };
+                // Dropping the guard releases both the image and the memory
+                raw_device.bind_image_memory(raw_image, allocated_memory, 0)?;
+
+                Ok(imported)
+            })
+        })
+    };
+
+    let imported = match imported {
+        Some(imported) => imported?,
+        None => bail!("Cannot import DMA-BUF, the device is not using Vulkan"),
+    };
+    let size = wgpu::Extent3d {
+        width: plane.width,
+        height: plane.height,
+        depth_or_array_layers: 1,
+    };
+    Ok(vulkan_image_to_texture(
+        device,
+        imported.image,
+        wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: plane.format,
+            view_formats: &[],
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        },
+        TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: plane.format,
+            view_formats: vec![],
+            usage: TextureUses::RESOURCE | TextureUses::COPY_SRC,
+            memory_flags: MemoryFlags::empty(),
+        },
+        Some(Box::new(imported)),
+    ))
+}
+
+impl Loader for DmaBufLoader {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        _queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        self.ensure_listening()?;
+
+        if let Some(buffer) = self
+            .received
+            .lock()
+            .ok()
+            .and_then(|mut received| received.take())
+        {
+            self.current = Some(buffer);
+        }
+
+        let (descriptor, dmabuf_fd) = self.current.as_ref().context("No DMA-BUF received yet")?;
+        let format = unmap_drm_format(descriptor.fourcc)
+            .with_context(|| format!("Unsupported DRM format {:#x}", descriptor.fourcc))?;
+        let stereo_mode = StereoMode::try_from(descriptor.stereo_mode)?;
+
+        log::info!(
+            "Importing DMA-BUF {}x{} {:?} with modifier {:#x}",
+            descriptor.width,
+            descriptor.height,
+            format,
+            descriptor.modifier
+        );
+
+        let texture = import_dmabuf(
+            device,
+            &DmaBufPlane {
+                width: descriptor.width,
+                height: descriptor.height,
+                format,
+                stride: descriptor.stride,
+                offset: descriptor.offset,
+                modifier: descriptor.modifier,
+            },
+            dmabuf_fd.as_raw_fd(),
+            "DmaBufStream",
+        )?;
+
+        Ok(TextureSource {
+            texture: Texture2D::<Unbound>::from_wgpu(device, texture),
+            width: descriptor.width,
+            height: descriptor.height,
+            stereo_mode,
+            planes: None,
+        })
+    }
+
+    fn is_invalid(&self) -> bool {
+        self.received
+            .lock()
+            .map(|received| received.is_some())
+            .unwrap_or(true)
+    }
+}
+
+impl Drop for DmaBufLoader {
+    fn drop(&mut self) {
+        if self.listening {
+            let _ = std::fs::remove_file(&self.socket_path);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    cell::Cell,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use image::GenericImageView;
+use wgpu::{Device, Instance, Queue};
+
+use crate::engine::texture::{Texture2D, Unbound};
+
+use super::{stereo_photo, Loader, StereoMode, TextureSource};
+
+// The renderer asks for invalidation every frame, the file is only checked this often
+const MODIFIED_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ImageLoader {
+    path: PathBuf,
+    stereo_mode: StereoMode,
+    last_modified: Option<SystemTime>,
+    // Time and result of the last modification check
+    last_check: Cell<Option<(Instant, bool)>>,
+}
+
+impl ImageLoader {
+    pub fn new(path: impl Into<PathBuf>, stereo_mode: StereoMode) -> Self {
+        Self {
+            path: path.into(),
+            stereo_mode,
+            last_modified: None,
+            last_check: Cell::new(None),
+        }
+    }
+
+    fn modified_time(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
+
+impl Loader for ImageLoader {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        let (image, stereo_mode) = stereo_photo::open(&self.path, self.stereo_mode)
+            .with_context(|| format!("Cannot open image {}", self.path.display()))?;
+        self.last_modified = self.modified_time();
+        self.last_check.set(None);
+
+        let (width, height) = image.dimensions();
+        log::info!(
+            "Loaded image {} ({}x{}, {:?})",
+            self.path.display(),
+            width,
+            height,
+            stereo_mode
+        );
+
+        Ok(TextureSource {
+            texture: Texture2D::<Unbound>::from_image(device, queue, &image, Some("ImageLoader")),
+            width,
+            height,
+            stereo_mode,
+            planes: None,
+        })
+    }
+
+    fn is_invalid(&self) -> bool {
+        let now = Instant::now();
+        match self.last_check.get() {
+            Some((time, invalid)) if now.duration_since(time) < MODIFIED_CHECK_INTERVAL => invalid,
+            _ => {
+                let invalid = self.modified_time() != self.last_modified;
+                self.last_check.set(Some((now, invalid)));
+                invalid
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::{bail, Context};
 use ash::vk::{self, ImageCreateInfo};
-use wgpu::{Device, Instance, TextureFormat};
+use wgpu::{Device, Instance, Queue};
 use wgpu_hal::{api::Vulkan, MemoryFlags, TextureDescriptor, TextureUses};
 use windows::{
     core::s,
@@ -14,6 +14,7 @@ use windows::{
                 D3D11_TEXTURE2D_DESC,
             },
             Direct3D12::{D3D12CreateDevice, ID3D12Device, ID3D12Resource},
+            Dxgi::Common::DXGI_FORMAT,
         },
         System::Memory::{
             MapViewOfFile, OpenFileMappingA, UnmapViewOfFile, FILE_MAP_ALL_ACCESS,
@@ -23,7 +24,10 @@ use windows::{
 };
 
 use crate::{
-    conversions::{map_texture_format, unmap_texture_format, vulkan_image_to_texture},
+    conversions::{
+        is_typeless_format, map_texture_format, query_format_capabilities, srgb_view_formats,
+        unmap_texture_format, vulkan_image_to_texture,
+    },
     engine::texture::{Texture2D, Unbound},
 };
 
@@ -37,7 +41,12 @@ pub struct KatangaLoaderContext {
 }
 
 impl Loader for KatangaLoaderContext {
-    fn load(&mut self, _instance: &Instance, device: &Device) -> anyhow::Result<TextureSource> {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        _queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
         self.katanga_file_handle = unsafe {
             OpenFileMappingA(FILE_MAP_ALL_ACCESS.0, false, s!("Local\\KatangaMappedFile"))?
         };
@@ -75,16 +84,26 @@ impl Loader for KatangaLoaderContext {
             log::info!("Actual Handle: {:?}", self.katanga_file_handle);
         }
 
-        let vk_format = map_texture_format(tex_info.format);
+        // Unknown formats leave the screen on the blank texture instead of taking the app down
+        let format = unmap_texture_format(tex_info.format).map_err(|err| {
+            log::error!("Cannot show the Katanga texture: {}", err);
+            err
+        })?;
+        let view_formats = if is_typeless_format(tex_info.format) {
+            srgb_view_formats(format)
+        } else {
+            vec![]
+        };
+        let vk_format = map_texture_format(format);
 
-        log::info!("Mapped DXGI format to {:?}", tex_info.format);
+        log::info!("Mapped DXGI format to {:?}", format);
         log::info!("Mapped WGPU format to Vulkan {:?}", vk_format);
 
         let raw_image: Option<anyhow::Result<vk::Image>> = unsafe {
             device.as_hal::<Vulkan, _, _>(|device| {
                 device.map(|device| {
                     let raw_device = device.raw_device();
-                    //let raw_phys_device = device.raw_physical_device();
+                    let raw_instance = device.shared_instance().raw_instance();
                     let handle_type = match tex_info.external_api {
                         ExternalApi::D3D11 => {
                             vk::ExternalMemoryHandleTypeFlags::D3D11_TEXTURE_KMT_KHR
@@ -92,6 +111,31 @@ impl Loader for KatangaLoaderContext {
                         ExternalApi::D3D12 => vk::ExternalMemoryHandleTypeFlags::D3D12_RESOURCE_KHR,
                     };
 
+                    let image_flags = if view_formats.is_empty() {
+                        vk::ImageCreateFlags::empty()
+                    } else {
+                        vk::ImageCreateFlags::MUTABLE_FORMAT
+                    };
+
+                    let capabilities = query_format_capabilities(
+                        raw_instance,
+                        device.raw_physical_device(),
+                        vk_format,
+                        vk::ImageTiling::OPTIMAL,
+                        image_flags,
+                        None,
+                        Some(handle_type),
+                    );
+                    // The screen sampler filters linearly
+                    if !capabilities.importable || !capabilities.sampled || !capabilities.filterable
+                    {
+                        bail!(
+                            "The device cannot import {:?} textures from {:?}",
+                            format,
+                            tex_info.external_api
+                        );
+                    }
+
                     let mut import_memory_info = vk::ImportMemoryWin32HandleInfoKHR::builder()
                         .handle_type(handle_type)
                         .handle(tex_handle);
@@ -107,6 +151,7 @@ impl Loader for KatangaLoaderContext {
 
                     let image_create_info = ImageCreateInfo::builder()
                         .push_next(&mut ext_create_info)
+                        .flags(image_flags)
                         //.push_next(&mut dedicated_creation_info)
                         .image_type(vk::ImageType::TYPE_2D)
                         .format(vk_format)
@@ -145,8 +190,8 @@ impl Loader for KatangaLoaderContext {
                     mip_level_count: tex_info.mip_levels,
                     sample_count: tex_info.sample_count,
                     dimension: wgpu::TextureDimension::D2,
-                    format: tex_info.format,
-                    view_formats: &[],
+                    format,
+                    view_formats: &view_formats,
                     usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
                 },
                 TextureDescriptor {
@@ -159,11 +204,12 @@ impl Loader for KatangaLoaderContext {
                     mip_level_count: tex_info.mip_levels,
                     sample_count: tex_info.sample_count,
                     dimension: wgpu::TextureDimension::D2,
-                    format: tex_info.format,
-                    view_formats: vec![],
+                    format,
+                    view_formats: view_formats.clone(),
                     usage: TextureUses::RESOURCE | TextureUses::COPY_SRC,
                     memory_flags: MemoryFlags::empty(),
                 },
+                None,
             );
 
             return Ok(TextureSource {
@@ -171,6 +217,7 @@ impl Loader for KatangaLoaderContext {
                 width: tex_info.width,
                 height: tex_info.height,
                 stereo_mode: crate::loaders::StereoMode::FullSbs,
+                planes: None,
             });
         }
 
@@ -208,10 +255,11 @@ struct ExternalTextureInfo {
     array_size: u32,
     sample_count: u32,
     mip_levels: u32,
-    format: TextureFormat,
+    format: DXGI_FORMAT,
     actual_handle: usize,
 }
 
+#[derive(Debug)]
 enum ExternalApi {
     D3D11,
     D3D12,
@@ -256,7 +304,7 @@ fn get_d3d11_texture_info(handle: HANDLE) -> anyhow::Result<ExternalTextureInfo>
         array_size: texture_desc.ArraySize,
         sample_count: texture_desc.SampleDesc.Count,
         mip_levels: texture_desc.MipLevels,
-        format: unmap_texture_format(texture_desc.Format),
+        format: texture_desc.Format,
         actual_handle: handle.0 as usize,
     })
 }
@@ -294,7 +342,7 @@ fn get_d3d12_texture_info() -> anyhow::Result<ExternalTextureInfo> {
         array_size: tex_info.DepthOrArraySize as u32,
         sample_count: tex_info.SampleDesc.Count,
         mip_levels: tex_info.MipLevels as u32,
-        format: unmap_texture_format(tex_info.Format),
+        format: tex_info.Format,
         actual_handle: named_handle.0 as usize,
     })
 }
@@ -0,0 +1,244 @@
+use std::{
+    io::{BufReader, Read},
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{bail, Context};
+use image::ImageFormat;
+use wgpu::{Device, Instance, Queue};
+
+use crate::{
+    engine::texture::{Texture2D, Unbound},
+    protocol::net::{read_frame, NetCodec, NetFrameHeader},
+};
+
+use super::{check_texture_size, FrameContext, FrameUpdate, Loader, StereoMode, TextureSource};
+
+#[derive(Default)]
+struct NetFrame {
+    width: u32,
+    height: u32,
+    stereo_mode: u32,
+    pixels: Vec<u8>,
+    sequence: u64,
+}
+
+impl NetFrame {
+    fn layout_changed(&self, other: &NetFrame) -> bool {
+        self.width != other.width
+            || self.height != other.height
+            || self.stereo_mode != other.stereo_mode
+    }
+}
+
+// Receives raw or JPEG frames over TCP, one sender at a time
+pub struct NetLoader {
+    address: String,
+    frame: Arc<Mutex<NetFrame>>,
+    // Largest texture side the device supports, bigger frames drop the sender
+    max_dimension: Arc<AtomicU32>,
+    listening: bool,
+    loaded_frame: Option<NetFrame>,
+}
+
+impl NetLoader {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_owned(),
+            frame: Arc::new(Mutex::new(NetFrame::default())),
+            max_dimension: Arc::new(AtomicU32::new(0)),
+            listening: false,
+            loaded_frame: None,
+        }
+    }
+
+    fn ensure_listening(&mut self) -> anyhow::Result<()> {
+        if self.listening {
+            return Ok(());
+        }
+
+        let listener = TcpListener::bind(&self.address)
+            .with_context(|| format!("Cannot listen on {}", self.address))?;
+        let frame = self.frame.clone();
+        let max_dimension = self.max_dimension.clone();
+        std::thread::Builder::new()
+            .name("Network Receiver".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let peer = stream
+                        .peer_addr()
+                        .map(|peer| peer.to_string())
+                        .unwrap_or_default();
+                    log::info!("Network sender {} connected", peer);
+                    let _ = stream.set_nodelay(true);
+                    let mut reader = BufReader::new(stream);
+                    loop {
+                        let max_dimension = max_dimension.load(Ordering::Relaxed);
+                        match receive_frame(&mut reader, max_dimension) {
+                            Ok(Some(decoded)) => {
+                                if let Ok(mut frame) = frame.lock() {
+                                    let sequence = frame.sequence.wrapping_add(1);
+                                    *frame = NetFrame {
+                                        sequence,
+                                        ..decoded
+                                    };
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(err) => {
+                                log::error!("Failed to receive network frame: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    log::info!("Network sender {} disconnected", peer);
+                }
+            })?;
+
+        log::info!("Listening for network frames on {}", self.address);
+        self.listening = true;
+        Ok(())
+    }
+
+    fn upload_frame(frame: &NetFrame, queue: &Queue, texture: &wgpu::Texture) {
+        queue.write_texture(
+            texture.as_image_copy(),
+            &frame.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * frame.width),
+                rows_per_image: Some(frame.height),
+            },
+            texture.size(),
+        );
+    }
+}
+
+fn receive_frame(reader: &mut impl Read, max_dimension: u32) -> anyhow::Result<Option<NetFrame>> {
+    match read_frame(reader)? {
+        Some((header, payload)) => {
+            if header.width > max_dimension || header.height > max_dimension {
+                bail!(
+                    "Frame of {}x{} exceeds the maximum texture size of {}",
+                    header.width,
+                    header.height,
+                    max_dimension
+                );
+            }
+            decode_frame(&header, payload).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+fn decode_frame(header: &NetFrameHeader, payload: Vec<u8>) -> anyhow::Result<NetFrame> {
+    let pixels = match header.codec {
+        NetCodec::RawRgba => payload,
+        NetCodec::Jpeg => {
+            let image = image::load_from_memory_with_format(&payload, ImageFormat::Jpeg)?;
+            if image.width() != header.width || image.height() != header.height {
+                bail!(
+                    "JPEG frame is {}x{} but the header says {}x{}",
+                    image.width(),
+                    image.height(),
+                    header.width,
+                    header.height
+                );
+            }
+            image.to_rgba8().into_raw()
+        }
+    };
+
+    Ok(NetFrame {
+        width: header.width,
+        height: header.height,
+        stereo_mode: header.stereo_mode,
+        pixels,
+        sequence: 0,
+    })
+}
+
+impl Loader for NetLoader {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        self.loaded_frame = None;
+        self.max_dimension
+            .store(device.limits().max_texture_dimension_2d, Ordering::Relaxed);
+        self.ensure_listening()?;
+
+        let frame = self
+            .frame
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Network frame lock poisoned"))?;
+        if frame.pixels.is_empty() {
+            bail!("No network frame received yet");
+        }
+        let stereo_mode = StereoMode::try_from(frame.stereo_mode)?;
+        check_texture_size(device, frame.width, frame.height)?;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("NetworkStream"),
+            size: wgpu::Extent3d {
+                width: frame.width,
+                height: frame.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        Self::upload_frame(&frame, queue, &texture);
+
+        let (width, height) = (frame.width, frame.height);
+        self.loaded_frame = Some(NetFrame {
+            width,
+            height,
+            stereo_mode: frame.stereo_mode,
+            pixels: vec![],
+            sequence: frame.sequence,
+        });
+
+        Ok(TextureSource {
+            texture: Texture2D::<Unbound>::from_wgpu(device, texture),
+            width,
+            height,
+            stereo_mode,
+            planes: None,
+        })
+    }
+
+    fn is_invalid(&self) -> bool {
+        match (&self.loaded_frame, self.frame.lock()) {
+            (Some(loaded_frame), Ok(frame)) => frame.layout_changed(loaded_frame),
+            _ => true,
+        }
+    }
+
+    fn update(&mut self, context: &mut FrameContext) -> anyhow::Result<FrameUpdate> {
+        if let Some(loaded_frame) = &mut self.loaded_frame {
+            let Ok(frame) = self.frame.lock() else {
+                return Ok(FrameUpdate::Unchanged);
+            };
+            // Size or layout changes are handled by reloading through is_invalid
+            if frame.sequence != loaded_frame.sequence && !frame.layout_changed(loaded_frame) {
+                Self::upload_frame(&frame, context.queue, &context.texture.texture);
+                loaded_frame.sequence = frame.sequence;
+                return Ok(FrameUpdate::NewFrame);
+            }
+        }
+        Ok(FrameUpdate::Unchanged)
+    }
+}
@@ -0,0 +1,676 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use anyhow::{bail, Context};
+use pipewire as pw;
+use pw::{
+    properties,
+    spa::{
+        self,
+        format::{FormatProperties, MediaSubtype, MediaType},
+        param::{format_utils, video::VideoFormat, video::VideoInfoRaw, ParamType},
+        pod::{serialize::PodSerializer, Pod, Property, PropertyFlags, Value},
+        utils::{Direction, Fraction, Rectangle, SpaTypes},
+    },
+    stream::{Stream, StreamFlags},
+};
+use wgpu::{Device, Instance, Queue, TextureFormat};
+
+use crate::{
+    conversions::unmap_spa_video_format,
+    engine::texture::{Texture2D, Unbound},
+    protocol::dmabuf::DRM_FORMAT_MOD_LINEAR,
+};
+
+use super::{
+    check_texture_size,
+    dmabuf_loader::{import_dmabuf, supports_import, DmaBufPlane},
+    FrameContext, FrameUpdate, Loader, StereoMode, TextureSource,
+};
+
+// PipeWire buffer kept dequeued so the producer can't reuse it while its DMA-BUF may be read,
+// only handed back to the stream on the PipeWire thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HeldBuffer(*mut pw::sys::pw_buffer);
+
+// Never dereferenced outside of the PipeWire thread
+unsafe impl Send for HeldBuffer {}
+
+// DMA-BUF holding the latest frame, imported once per PipeWire buffer and copied on the GPU
+struct PipeWireDmaBuf {
+    buffer: HeldBuffer,
+    // Descriptor inside the PipeWire buffer, identifies the buffer across frames
+    buffer_fd: RawFd,
+    // Duplicated so the import doesn't depend on the buffer still being around
+    fd: OwnedFd,
+    offset: u32,
+}
+
+#[derive(Default)]
+struct PipeWireFrame {
+    width: u32,
+    height: u32,
+    stride: u32,
+    format: Option<TextureFormat>,
+    modifier: u64,
+    pixels: Vec<u8>,
+    dmabuf: Option<PipeWireDmaBuf>,
+    sequence: u64,
+    // Buffer the render thread copied last, held until that copy has been submitted
+    in_use: Option<HeldBuffer>,
+    // Buffers replaced by newer frames while in use, requeued once released
+    retired: Vec<HeldBuffer>,
+    // Bumped on every format negotiation, the stream reallocates its buffers
+    negotiation: u64,
+}
+
+impl PipeWireFrame {
+    // Buffers can be shorter than the negotiated size right after a renegotiation
+    fn is_complete(&self) -> bool {
+        self.dmabuf.is_some()
+            || (!self.pixels.is_empty()
+                && self.pixels.len() as u64 >= self.stride as u64 * self.height as u64)
+    }
+
+    fn dmabuf_plane(&self, dmabuf: &PipeWireDmaBuf) -> anyhow::Result<DmaBufPlane> {
+        Ok(DmaBufPlane {
+            width: self.width,
+            height: self.height,
+            format: self.format.context("Unsupported PipeWire video format")?,
+            stride: self.stride,
+            offset: dmabuf.offset,
+            modifier: self.modifier,
+        })
+    }
+
+    fn layout_changed(&self, other: &PipeWireFrame) -> bool {
+        self.width != other.width || self.height != other.height || self.format != other.format
+    }
+
+    // Gives the held buffer back to the producer, or retires it while the render thread uses it
+    fn release_dmabuf(&mut self, stream: &Stream) {
+        if let Some(dmabuf) = self.dmabuf.take() {
+            if self.in_use == Some(dmabuf.buffer) {
+                self.retired.push(dmabuf.buffer);
+            } else {
+                unsafe { stream.queue_raw_buffer(dmabuf.buffer.0) };
+            }
+        }
+    }
+
+    fn requeue_retired(&mut self, stream: &Stream) {
+        let in_use = self.in_use;
+        self.retired.retain(|&buffer| {
+            if in_use == Some(buffer) {
+                return true;
+            }
+            unsafe { stream.queue_raw_buffer(buffer.0) };
+            false
+        });
+    }
+}
+
+// Imported DMA-BUFs by the PipeWire buffer they belong to. Renegotiated buffers can reuse the
+// descriptors of the old ones, so the imports only hold for a single negotiation
+#[derive(Default)]
+struct ImportedBuffers {
+    negotiation: u64,
+    textures: HashMap<RawFd, wgpu::Texture>,
+}
+
+impl ImportedBuffers {
+    fn get_or_import(
+        &mut self,
+        device: &Device,
+        frame: &PipeWireFrame,
+        dmabuf: &PipeWireDmaBuf,
+    ) -> anyhow::Result<&wgpu::Texture> {
+        if self.negotiation != frame.negotiation {
+            self.textures.clear();
+            self.negotiation = frame.negotiation;
+        }
+        Ok(match self.textures.entry(dmabuf.buffer_fd) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(import_dmabuf(
+                device,
+                &frame.dmabuf_plane(dmabuf)?,
+                dmabuf.fd.as_raw_fd(),
+                "PipeWireDmaBuf",
+            )?),
+        })
+    }
+}
+
+struct StreamData {
+    format: VideoInfoRaw,
+    frame: Arc<Mutex<PipeWireFrame>>,
+    dmabuf_import: bool,
+}
+
+enum StreamMessage {
+    Terminate,
+    // The render thread submitted its copies, retired buffers can be requeued
+    Release,
+}
+
+// Consumes a PipeWire video node. DMA-BUF buffers are imported and copied on the GPU when the
+// device can import them, other frames are copied out of SHM buffers on the PipeWire thread and
+// uploaded on the render thread
+pub struct PipeWireLoader {
+    node_id: u32,
+    stereo_mode: StereoMode,
+    frame: Arc<Mutex<PipeWireFrame>>,
+    running: Arc<AtomicBool>,
+    stream_thread: Option<(JoinHandle<()>, pw::channel::Sender<StreamMessage>)>,
+    loaded_frame: Option<PipeWireFrame>,
+    imported: ImportedBuffers,
+    // Whether the last update recorded a DMA-BUF copy, submitted by the time of the next call
+    copy_recorded: bool,
+    import_failed: bool,
+}
+
+impl PipeWireLoader {
+    pub fn new(node_id: u32, stereo_mode: StereoMode) -> Self {
+        Self {
+            node_id,
+            stereo_mode,
+            frame: Arc::new(Mutex::new(PipeWireFrame::default())),
+            running: Arc::new(AtomicBool::new(false)),
+            stream_thread: None,
+            loaded_frame: None,
+            imported: ImportedBuffers::default(),
+            copy_recorded: false,
+            import_failed: false,
+        }
+    }
+
+    fn ensure_streaming(&mut self, dmabuf_import: bool) -> anyhow::Result<()> {
+        if self.running.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.stop_streaming();
+
+        let node_id = self.node_id;
+        let frame = self.frame.clone();
+        let running = self.running.clone();
+        let (sender, receiver) = pw::channel::channel::<StreamMessage>();
+        running.store(true, Ordering::Relaxed);
+        let handle = std::thread::Builder::new()
+            .name("PipeWire Stream".to_owned())
+            .spawn(move || {
+                if let Err(err) = run_stream(node_id, frame, receiver, dmabuf_import) {
+                    log::error!("PipeWire stream failed: {}", err);
+                }
+                running.store(false, Ordering::Relaxed);
+            })?;
+
+        self.stream_thread = Some((handle, sender));
+        Ok(())
+    }
+
+    fn stop_streaming(&mut self) {
+        if let Some((handle, sender)) = self.stream_thread.take() {
+            let _ = sender.send(StreamMessage::Terminate);
+            let _ = handle.join();
+        }
+    }
+
+    fn upload_frame(frame: &PipeWireFrame, queue: &Queue, texture: &wgpu::Texture) {
+        queue.write_texture(
+            texture.as_image_copy(),
+            &frame.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(frame.stride),
+                rows_per_image: Some(frame.height),
+            },
+            texture.size(),
+        );
+    }
+
+    // Lets the PipeWire thread requeue the buffer copied by the previous update
+    fn release_copied_buffer(&mut self) {
+        if !std::mem::take(&mut self.copy_recorded) {
+            return;
+        }
+        if let Ok(mut frame) = self.frame.lock() {
+            frame.in_use = None;
+        }
+        if let Some((_, sender)) = &self.stream_thread {
+            let _ = sender.send(StreamMessage::Release);
+        }
+    }
+
+    fn copy_dmabuf(
+        imported: &mut ImportedBuffers,
+        device: &Device,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &PipeWireFrame,
+        dmabuf: &PipeWireDmaBuf,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<()> {
+        let source = imported.get_or_import(device, frame, dmabuf)?;
+        encoder.copy_texture_to_texture(
+            source.as_image_copy(),
+            texture.as_image_copy(),
+            texture.size(),
+        );
+        Ok(())
+    }
+}
+
+fn serialize_pod(value: Value) -> anyhow::Result<Vec<u8>> {
+    Ok(
+        PodSerializer::serialize(std::io::Cursor::new(Vec::new()), &value)?
+            .0
+            .into_inner(),
+    )
+}
+
+// Buffer types the stream accepts, DMA-BUFs only when they can be imported
+fn buffers_param(dmabuf_import: bool) -> anyhow::Result<Vec<u8>> {
+    let mut data_types = (1 << spa::sys::SPA_DATA_MemPtr) | (1 << spa::sys::SPA_DATA_MemFd);
+    if dmabuf_import {
+        data_types |= 1 << spa::sys::SPA_DATA_DmaBuf;
+    }
+    serialize_pod(Value::Object(spa::pod::Object {
+        type_: SpaTypes::ObjectParamBuffers.as_raw(),
+        id: ParamType::Buffers.as_raw(),
+        properties: vec![Property {
+            key: spa::sys::SPA_PARAM_BUFFERS_dataType,
+            flags: PropertyFlags::empty(),
+            value: Value::Int(data_types as i32),
+        }],
+    }))
+}
+
+fn run_stream(
+    node_id: u32,
+    frame: Arc<Mutex<PipeWireFrame>>,
+    receiver: pw::channel::Receiver<StreamMessage>,
+    dmabuf_import: bool,
+) -> anyhow::Result<()> {
+    pw::init();
+    let mainloop = pw::MainLoop::new()?;
+    let context = pw::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    let stream = Rc::new(Stream::new(
+        &core,
+        "vr-screen-cap",
+        properties! {
+            *pw::keys::MEDIA_TYPE => "Video",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Screen",
+        },
+    )?);
+
+    let _receiver = receiver.attach(&mainloop, {
+        let mainloop = mainloop.clone();
+        let stream = stream.clone();
+        let frame = frame.clone();
+        move |message| match message {
+            StreamMessage::Terminate => mainloop.quit(),
+            StreamMessage::Release => {
+                if let Ok(mut frame) = frame.lock() {
+                    frame.requeue_retired(&stream);
+                }
+            }
+        }
+    });
+
+    let _listener = stream
+        .add_local_listener_with_user_data(StreamData {
+            format: VideoInfoRaw::default(),
+            frame,
+            dmabuf_import,
+        })
+        .state_changed(|old, new| {
+            log::info!("PipeWire stream state changed {:?} -> {:?}", old, new);
+        })
+        .param_changed(|stream, id, user_data, param| {
+            let Some(param) = param else {
+                return;
+            };
+            if id != ParamType::Format.as_raw() {
+                return;
+            }
+            match format_utils::parse_format(param) {
+                Ok((MediaType::Video, MediaSubtype::Raw)) => {}
+                _ => return,
+            }
+            if let Err(err) = user_data.format.parse(param) {
+                log::error!("Cannot parse PipeWire video format: {:?}", err);
+                return;
+            }
+
+            let size = user_data.format.size();
+            log::info!(
+                "PipeWire stream negotiated {}x{} {:?} with modifier {:#x}",
+                size.width,
+                size.height,
+                user_data.format.format(),
+                user_data.format.modifier()
+            );
+            // Size and format changes make is_invalid reload the texture. The stream reallocates
+            // its buffers, including the held ones
+            if let Ok(mut frame) = user_data.frame.lock() {
+                frame.width = size.width;
+                frame.height = size.height;
+                frame.format = unmap_spa_video_format(user_data.format.format());
+                frame.modifier = user_data.format.modifier();
+                frame.pixels.clear();
+                frame.dmabuf = None;
+                frame.retired.clear();
+                frame.negotiation = frame.negotiation.wrapping_add(1);
+            }
+
+            match buffers_param(user_data.dmabuf_import) {
+                Ok(buffers) => match Pod::from_bytes(&buffers) {
+                    Some(buffers) => {
+                        if let Err(err) = stream.update_params(&mut [buffers]) {
+                            log::error!("Cannot update PipeWire buffer params: {}", err);
+                        }
+                    }
+                    None => log::error!("Invalid PipeWire buffers pod"),
+                },
+                Err(err) => log::error!("Cannot serialize PipeWire buffer params: {}", err),
+            }
+        })
+        .process(|stream, user_data| {
+            let buffer = stream.dequeue_raw_buffer();
+            if buffer.is_null() {
+                return;
+            }
+            // DMA-BUF buffers stay dequeued until the render thread copied them
+            if !unsafe { process_buffer(stream, user_data, buffer) } {
+                unsafe { stream.queue_raw_buffer(buffer) };
+            }
+        })
+        .register()?;
+
+    let format = |modifier: Option<u64>| {
+        let mut format = spa::pod::object!(
+            SpaTypes::ObjectParamFormat,
+            ParamType::EnumFormat,
+            spa::pod::property!(FormatProperties::MediaType, Id, MediaType::Video),
+            spa::pod::property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
+            spa::pod::property!(
+                FormatProperties::VideoFormat,
+                Choice,
+                Enum,
+                Id,
+                VideoFormat::BGRx,
+                VideoFormat::BGRx,
+                VideoFormat::BGRA,
+                VideoFormat::RGBx,
+                VideoFormat::RGBA,
+                VideoFormat::xBGR_210LE,
+                VideoFormat::ABGR_210LE
+            ),
+            spa::pod::property!(
+                FormatProperties::VideoSize,
+                Choice,
+                Range,
+                Rectangle,
+                Rectangle {
+                    width: 1920,
+                    height: 1080
+                },
+                Rectangle {
+                    width: 1,
+                    height: 1
+                },
+                Rectangle {
+                    width: 8192,
+                    height: 8192
+                }
+            ),
+            spa::pod::property!(
+                FormatProperties::VideoFramerate,
+                Choice,
+                Range,
+                Fraction,
+                Fraction { num: 60, denom: 1 },
+                Fraction { num: 0, denom: 1 },
+                Fraction {
+                    num: 1000,
+                    denom: 1
+                }
+            ),
+        );
+        // Only producers able to share a buffer with this modifier pick the DMA-BUF format
+        if let Some(modifier) = modifier {
+            format.properties.push(Property {
+                key: FormatProperties::VideoModifier.as_raw(),
+                flags: PropertyFlags::MANDATORY,
+                value: Value::Long(modifier as i64),
+            });
+        }
+        serialize_pod(Value::Object(format))
+    };
+    // Preferred formats come first, linear DMA-BUFs can be imported without a fixation round
+    let mut formats = vec![];
+    if dmabuf_import {
+        formats.push(format(Some(DRM_FORMAT_MOD_LINEAR))?);
+    }
+    formats.push(format(None)?);
+    let mut params = formats
+        .iter()
+        .map(|format| Pod::from_bytes(format).context("Invalid PipeWire format pod"))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    stream.connect(
+        Direction::Input,
+        Some(node_id),
+        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    log::info!(
+        "Connected to PipeWire node {} (DMA-BUF import {})",
+        node_id,
+        if dmabuf_import { "enabled" } else { "disabled" }
+    );
+    mainloop.run();
+    Ok(())
+}
+
+// Stores the frame held by the buffer, returns whether the buffer was kept dequeued
+unsafe fn process_buffer(
+    stream: &Stream,
+    user_data: &StreamData,
+    buffer: *mut pw::sys::pw_buffer,
+) -> bool {
+    let spa_buffer = (*buffer).buffer;
+    if spa_buffer.is_null() || (*spa_buffer).n_datas == 0 {
+        return false;
+    }
+    let data = &*(*spa_buffer).datas;
+    if data.chunk.is_null() {
+        return false;
+    }
+    let chunk = &*data.chunk;
+    let (offset, size, stride) = (chunk.offset as usize, chunk.size as usize, chunk.stride);
+    let Ok(mut frame) = user_data.frame.lock() else {
+        return false;
+    };
+
+    if data.type_ == spa::sys::SPA_DATA_DmaBuf {
+        if !user_data.dmabuf_import || stride <= 0 {
+            log::error!("Received a DMA-BUF that cannot be imported, skipping frame");
+            return false;
+        }
+        let buffer_fd = data.fd as RawFd;
+        let fd = match nix::unistd::dup(buffer_fd) {
+            Ok(fd) => OwnedFd::from_raw_fd(fd),
+            Err(err) => {
+                log::error!("Cannot duplicate PipeWire DMA-BUF: {}", err);
+                return false;
+            }
+        };
+        frame.release_dmabuf(stream);
+        frame.stride = stride as u32;
+        frame.pixels.clear();
+        frame.dmabuf = Some(PipeWireDmaBuf {
+            buffer: HeldBuffer(buffer),
+            buffer_fd,
+            fd,
+            offset: offset as u32,
+        });
+        frame.sequence = frame.sequence.wrapping_add(1);
+        return true;
+    }
+
+    if data.data.is_null() || stride <= 0 || offset + size > data.maxsize as usize {
+        return false;
+    }
+    let pixels = std::slice::from_raw_parts(data.data as *const u8, data.maxsize as usize);
+    frame.release_dmabuf(stream);
+    frame.stride = stride as u32;
+    frame.pixels.clear();
+    frame
+        .pixels
+        .extend_from_slice(&pixels[offset..offset + size]);
+    frame.sequence = frame.sequence.wrapping_add(1);
+    false
+}
+
+impl Loader for PipeWireLoader {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        self.release_copied_buffer();
+        self.loaded_frame = None;
+        self.imported = ImportedBuffers::default();
+        self.import_failed = false;
+        self.ensure_streaming(supports_import(device))?;
+
+        let frame = self
+            .frame
+            .lock()
+            .map_err(|_| anyhow::anyhow!("PipeWire frame lock poisoned"))?;
+        if !frame.is_complete() {
+            bail!("No PipeWire frame received yet");
+        }
+        let format = frame.format.context("Unsupported PipeWire video format")?;
+        check_texture_size(device, frame.width, frame.height)?;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("PipeWireStream"),
+            size: wgpu::Extent3d {
+                width: frame.width,
+                height: frame.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        match &frame.dmabuf {
+            Some(dmabuf) => {
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("PipeWire DMA-BUF Copy"),
+                });
+                Self::copy_dmabuf(
+                    &mut self.imported,
+                    device,
+                    &mut encoder,
+                    &frame,
+                    dmabuf,
+                    &texture,
+                )?;
+                queue.submit(Some(encoder.finish()));
+            }
+            None => Self::upload_frame(&frame, queue, &texture),
+        }
+
+        let (width, height) = (frame.width, frame.height);
+        self.loaded_frame = Some(PipeWireFrame {
+            width,
+            height,
+            stride: frame.stride,
+            format: frame.format,
+            modifier: frame.modifier,
+            sequence: frame.sequence,
+            ..Default::default()
+        });
+
+        Ok(TextureSource {
+            texture: Texture2D::<Unbound>::from_wgpu(device, texture),
+            width,
+            height,
+            stereo_mode: self.stereo_mode,
+            planes: None,
+        })
+    }
+
+    fn is_invalid(&self) -> bool {
+        // Reloading reports the import error and lets another source take over
+        if self.import_failed || !self.running.load(Ordering::Relaxed) {
+            return true;
+        }
+        match (&self.loaded_frame, self.frame.lock()) {
+            (Some(loaded_frame), Ok(frame)) => frame.layout_changed(loaded_frame),
+            _ => true,
+        }
+    }
+
+    fn update(&mut self, context: &mut FrameContext) -> anyhow::Result<FrameUpdate> {
+        self.release_copied_buffer();
+        if let Some(loaded_frame) = &mut self.loaded_frame {
+            let Ok(mut frame) = self.frame.lock() else {
+                return Ok(FrameUpdate::Unchanged);
+            };
+            // Renegotiated streams are handled by reloading through is_invalid
+            if frame.sequence != loaded_frame.sequence
+                && frame.is_complete()
+                && !frame.layout_changed(loaded_frame)
+            {
+                match &frame.dmabuf {
+                    Some(dmabuf) => {
+                        if let Err(err) = Self::copy_dmabuf(
+                            &mut self.imported,
+                            context.device,
+                            context.encoder,
+                            &frame,
+                            dmabuf,
+                            &context.texture.texture,
+                        ) {
+                            self.import_failed = true;
+                            return Err(err);
+                        }
+                        frame.in_use = Some(dmabuf.buffer);
+                        self.copy_recorded = true;
+                    }
+                    None => Self::upload_frame(&frame, context.queue, &context.texture.texture),
+                }
+                loaded_frame.sequence = frame.sequence;
+                return Ok(FrameUpdate::NewFrame);
+            }
+        }
+        Ok(FrameUpdate::Unchanged)
+    }
+}
+
+impl Drop for PipeWireLoader {
+    fn drop(&mut self) {
+        self.stop_streaming();
+    }
+}
@@ -0,0 +1,237 @@
+use wgpu::{Device, Instance, Queue};
+
+use super::{Loader, TextureSource};
+
+// Names used to refer to loaders from the configuration and the tray
+#[cfg(target_os = "windows")]
+pub const KATANGA_LOADER: &str = "katanga";
+#[cfg(target_os = "linux")]
+pub const SHM_LOADER: &str = "shm";
+#[cfg(target_os = "linux")]
+pub const DMABUF_LOADER: &str = "dmabuf";
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub const PIPEWIRE_LOADER: &str = "pipewire";
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub const X11_LOADER: &str = "x11";
+#[cfg(all(target_os = "linux", feature = "v4l"))]
+pub const V4L_LOADER: &str = "v4l";
+#[cfg(feature = "video")]
+pub const VIDEO_LOADER: &str = "video";
+pub const NET_LOADER: &str = "network";
+pub const IMAGE_LOADER: &str = "image";
+pub const TEST_PATTERN_LOADER: &str = "test-pattern";
+
+struct RegisteredLoader {
+    name: &'static str,
+    loader: Box<dyn Loader>,
+}
+
+// Loaders are tried following the configured priority, falling back to the registration order
+#[derive(Default)]
+pub struct LoaderRegistry {
+    entries: Vec<RegisteredLoader>,
+    priority: Vec<String>,
+    forced: Option<&'static str>,
+}
+
+impl LoaderRegistry {
+    pub fn register(&mut self, name: &'static str, loader: Box<dyn Loader>) {
+        self.entries.push(RegisteredLoader { name, loader });
+    }
+
+    pub fn set_priority(&mut self, priority: &[String]) {
+        for name in priority {
+            if !self.entries.iter().any(|entry| entry.name == name) {
+                log::warn!("Unknown or unavailable loader {} in loader priority", name);
+            }
+        }
+        self.priority = priority.to_vec();
+    }
+
+    // Restricts loading to a single loader, None goes back to automatic selection
+    pub fn force(&mut self, name: Option<&'static str>) {
+        match name {
+            Some(name) if !self.entries.iter().any(|entry| entry.name == name) => {
+                log::warn!("Cannot force loader {}, it is not available", name);
+            }
+            _ => {
+                log::info!("Selected loader: {}", name.unwrap_or("automatic"));
+                self.forced = name;
+            }
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&dyn Loader> {
+        self.entries.get(idx).map(|entry| entry.loader.as_ref())
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut (dyn Loader + 'static)> {
+        self.entries.get_mut(idx).map(|entry| entry.loader.as_mut())
+    }
+
+    // Indices of the loaders in the order they should be tried
+    pub fn load_order(&self) -> Vec<usize> {
+        if let Some(forced) = self.forced {
+            return self
+                .entries
+                .iter()
+                .position(|entry| entry.name == forced)
+                .into_iter()
+                .collect();
+        }
+
+        let mut order: Vec<usize> = (0..self.entries.len()).collect();
+        // Stable sort keeps the registration order among loaders missing from the priority list
+        order.sort_by_key(|&idx| {
+            self.priority
+                .iter()
+                .position(|name| name == self.entries[idx].name)
+                .unwrap_or(self.priority.len())
+        });
+        order
+    }
+
+    // Loaders ranked above the active one, empty when a loader is forced
+    pub fn preferred_order(&self, active: usize) -> Vec<usize> {
+        self.load_order()
+            .into_iter()
+            .take_while(|&idx| idx != active)
+            .collect()
+    }
+
+    pub fn clear_errors(&mut self) {
+        for entry in &mut self.entries {
+            entry.loader.clear_error();
+        }
+    }
+
+    pub fn load(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> Option<(usize, TextureSource)> {
+        self.load_from(self.load_order(), instance, device, queue)
+    }
+
+    // Only tries the loaders ranked above the active one, so sources that become available later
+    // (e.g. streams without a frame when first tried) still take over lower priority ones
+    pub fn load_preferred(
+        &mut self,
+        active: usize,
+        instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> Option<(usize, TextureSource)> {
+        self.load_from(self.preferred_order(active), instance, device, queue)
+    }
+
+    fn load_from(
+        &mut self,
+        order: Vec<usize>,
+        instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> Option<(usize, TextureSource)> {
+        for idx in order {
+            let entry = &mut self.entries[idx];
+            match entry
+                .loader
+                .load(instance, device, queue)
+                .and_then(TextureSource::validate)
+            {
+                Ok(texture_source) => {
+                    log::info!("Loaded screen from {} loader", entry.name);
+                    return Some((idx, texture_source));
+                }
+                Err(err) => log::debug!("Loader {} unavailable: {}", entry.name, err),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu::{Device, Instance, Queue};
+
+    use super::{LoaderRegistry, IMAGE_LOADER, NET_LOADER, TEST_PATTERN_LOADER};
+    use crate::loaders::{Loader, TextureSource};
+
+    struct UnavailableLoader;
+
+    impl Loader for UnavailableLoader {
+        fn load(
+            &mut self,
+            _instance: &Instance,
+            _device: &Device,
+            _queue: &Queue,
+        ) -> anyhow::Result<TextureSource> {
+            anyhow::bail!("Unavailable")
+        }
+
+        fn is_invalid(&self) -> bool {
+            true
+        }
+    }
+
+    // Registered as network (0), image (1), test pattern (2)
+    fn registry() -> LoaderRegistry {
+        let mut registry = LoaderRegistry::default();
+        for name in [NET_LOADER, IMAGE_LOADER, TEST_PATTERN_LOADER] {
+            registry.register(name, Box::new(UnavailableLoader));
+        }
+        registry
+    }
+
+    #[test]
+    fn registration_order_is_the_default() {
+        assert_eq!(registry().load_order(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn priority_comes_before_unlisted_loaders() {
+        let mut registry = registry();
+        registry.set_priority(&[TEST_PATTERN_LOADER.to_owned()]);
+        assert_eq!(registry.load_order(), vec![2, 0, 1]);
+
+        registry.set_priority(&[IMAGE_LOADER.to_owned(), TEST_PATTERN_LOADER.to_owned()]);
+        assert_eq!(registry.load_order(), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn unknown_priority_names_are_ignored() {
+        let mut registry = registry();
+        registry.set_priority(&["unknown".to_owned(), IMAGE_LOADER.to_owned()]);
+        assert_eq!(registry.load_order(), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn forced_loader_is_the_only_one_tried() {
+        let mut registry = registry();
+        registry.force(Some(IMAGE_LOADER));
+        assert_eq!(registry.load_order(), vec![1]);
+
+        registry.force(None);
+        assert_eq!(registry.load_order(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn forcing_an_unavailable_loader_keeps_the_selection() {
+        let mut registry = registry();
+        registry.force(Some(IMAGE_LOADER));
+        registry.force(Some("unknown"));
+        assert_eq!(registry.load_order(), vec![1]);
+    }
+
+    #[test]
+    fn preferred_order_only_holds_higher_priority_loaders() {
+        let mut registry = registry();
+        registry.set_priority(&[TEST_PATTERN_LOADER.to_owned()]);
+        assert_eq!(registry.preferred_order(1), vec![2, 0]);
+        assert_eq!(registry.preferred_order(2), Vec::<usize>::new());
+
+        registry.force(Some(IMAGE_LOADER));
+        assert_eq!(registry.preferred_order(1), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::Context;
+use wgpu::{Device, Instance, Queue, TextureFormat};
+
+use crate::{
+    engine::texture::{Texture2D, Unbound},
+    protocol::shm::{SharedMemory, ShmFrameHeader, ShmPixelFormat},
+};
+
+use super::{check_texture_size, FrameContext, FrameUpdate, Loader, StereoMode, TextureSource};
+
+pub struct ShmLoader {
+    name: String,
+    memory: Option<SharedMemory>,
+    loaded_header: Option<ShmFrameHeader>,
+    last_frame: u64,
+}
+
+impl ShmLoader {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            memory: None,
+            loaded_header: None,
+            last_frame: 0,
+        }
+    }
+
+    fn upload_frame(
+        memory: &SharedMemory,
+        header: &ShmFrameHeader,
+        queue: &Queue,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<()> {
+        queue.write_texture(
+            texture.as_image_copy(),
+            memory.pixels(header)?,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(header.stride),
+                rows_per_image: Some(header.height),
+            },
+            texture.size(),
+        );
+        Ok(())
+    }
+}
+
+fn texture_format(format: ShmPixelFormat) -> TextureFormat {
+    match format {
+        ShmPixelFormat::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+        ShmPixelFormat::Bgra8Unorm => TextureFormat::Bgra8Unorm,
+        ShmPixelFormat::Rgba8UnormSrgb => TextureFormat::Rgba8UnormSrgb,
+        ShmPixelFormat::Bgra8UnormSrgb => TextureFormat::Bgra8UnormSrgb,
+    }
+}
+
+impl Loader for ShmLoader {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        self.memory = None;
+        self.loaded_header = None;
+
+        let memory = SharedMemory::open(&self.name)
+            .with_context(|| format!("Cannot open shared memory {}", self.name))?;
+        let header = memory.read_header()?;
+        header.validate()?;
+        check_texture_size(device, header.width, header.height)?;
+        let stereo_mode = StereoMode::try_from(header.stereo_mode)?;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("SharedMemoryStream"),
+            size: wgpu::Extent3d {
+                width: header.width,
+                height: header.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture_format(ShmPixelFormat::try_from(header.format)?),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        Self::upload_frame(&memory, &header, queue, &texture)?;
+
+        log::info!(
+            "Opened shared memory {} ({}x{}, {:?})",
+            self.name,
+            header.width,
+            header.height,
+            stereo_mode
+        );
+
+        self.last_frame = header.frame_counter;
+        self.loaded_header = Some(header);
+        self.memory = Some(memory);
+
+        Ok(TextureSource {
+            texture: Texture2D::<Unbound>::from_wgpu(device, texture),
+            width: header.width,
+            height: header.height,
+            stereo_mode,
+            planes: None,
+        })
+    }
+
+    fn is_invalid(&self) -> bool {
+        match (&self.memory, &self.loaded_header) {
+            (Some(memory), Some(loaded_header)) => match memory.read_header() {
+                Ok(header) => header.validate().is_err() || header.layout_changed(loaded_header),
+                Err(_) => true,
+            },
+            _ => true,
+        }
+    }
+
+    fn update(&mut self, context: &mut FrameContext) -> anyhow::Result<FrameUpdate> {
+        if let (Some(memory), Some(loaded_header)) = (&self.memory, &self.loaded_header) {
+            let header = memory.read_header()?;
+            // Layout changes are handled by reloading through is_invalid
+            if header.frame_counter != self.last_frame && !header.layout_changed(loaded_header) {
+                // A frame written while uploading gets uploaded again on the next update
+                Self::upload_frame(memory, &header, context.queue, &context.texture.texture)?;
+                self.last_frame = header.frame_counter;
+                return Ok(FrameUpdate::NewFrame);
+            }
+        }
+        Ok(FrameUpdate::Unchanged)
+    }
+}
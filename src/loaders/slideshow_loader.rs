@@ -0,0 +1,139 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use wgpu::{Device, Instance, Queue};
+
+use super::{
+    image_loader::ImageLoader, stereo_mode_from_path, Loader, LoaderAction, StereoMode,
+    TextureSource,
+};
+
+const SUPPORTED_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "jps", "mpo"];
+
+pub struct SlideshowLoader {
+    folder: PathBuf,
+    default_stereo_mode: StereoMode,
+    interval: Option<Duration>,
+    files: Vec<PathBuf>,
+    index: usize,
+    loaded_index: Option<usize>,
+    current: Option<ImageLoader>,
+    last_switch: Instant,
+}
+
+impl SlideshowLoader {
+    pub fn new(
+        folder: impl Into<PathBuf>,
+        default_stereo_mode: StereoMode,
+        interval: Option<Duration>,
+    ) -> Self {
+        Self {
+            folder: folder.into(),
+            default_stereo_mode,
+            interval,
+            files: vec![],
+            index: 0,
+            loaded_index: None,
+            current: None,
+            last_switch: Instant::now(),
+        }
+    }
+
+    fn scan_folder(&mut self) -> anyhow::Result<()> {
+        let selected = self.files.get(self.index).cloned();
+
+        let mut files = std::fs::read_dir(&self.folder)
+            .with_context(|| format!("Cannot read folder {}", self.folder.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .map(|extension| extension.to_string_lossy().to_lowercase())
+                        .map_or(false, |extension| {
+                            SUPPORTED_EXTENSIONS.contains(&extension.as_str())
+                        })
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+
+        // Keep showing the same picture if new files were added to the folder
+        self.index = selected
+            .and_then(|selected| files.iter().position(|path| *path == selected))
+            .unwrap_or(0);
+        self.files = files;
+        Ok(())
+    }
+
+    fn step(&mut self, offset: isize) {
+        if self.files.is_empty() {
+            return;
+        }
+        let count = self.files.len() as isize;
+        self.index = (self.index as isize + offset).rem_euclid(count) as usize;
+    }
+
+    fn timer_elapsed(&self) -> bool {
+        self.interval
+            .map_or(false, |interval| self.last_switch.elapsed() >= interval)
+    }
+}
+
+impl Loader for SlideshowLoader {
+    fn load(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        if self.timer_elapsed() && self.loaded_index == Some(self.index) {
+            self.step(1);
+        }
+        self.scan_folder()?;
+
+        // Skip over files that cannot be decoded instead of stopping the slideshow
+        for _ in 0..self.files.len() {
+            let path = &self.files[self.index];
+            let mut loader =
+                ImageLoader::new(path, stereo_mode_from_path(path, self.default_stereo_mode));
+            match loader.load(instance, device, queue) {
+                Ok(texture_source) => {
+                    self.current = Some(loader);
+                    self.loaded_index = Some(self.index);
+                    self.last_switch = Instant::now();
+                    return Ok(texture_source);
+                }
+                Err(err) => {
+                    log::warn!("Skipping slideshow image: {}", err);
+                    self.step(1);
+                }
+            }
+        }
+
+        self.current = None;
+        self.loaded_index = None;
+        bail!("No loadable images in {}", self.folder.display())
+    }
+
+    fn is_invalid(&self) -> bool {
+        match &self.current {
+            Some(current) => {
+                current.is_invalid()
+                    || self.loaded_index != Some(self.index)
+                    || self.timer_elapsed()
+            }
+            None => true,
+        }
+    }
+
+    fn handle_action(&mut self, action: &LoaderAction) {
+        match action {
+            LoaderAction::Next => self.step(1),
+            LoaderAction::Previous => self.step(-1),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::{ops::Range, path::Path};
+
+use anyhow::{bail, Context};
+use image::{imageops, DynamicImage, GenericImageView, ImageFormat, RgbaImage};
+
+use super::StereoMode;
+
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+const JPEG_SOS: u8 = 0xDA;
+const JPEG_EOI: u8 = 0xD9;
+const JPEG_APP2: u8 = 0xE2;
+const MPF_IDENTIFIER: &[u8] = b"MPF\0";
+const MP_ENTRY_TAG: u16 = 0xB002;
+const MP_ENTRY_SIZE: usize = 16;
+
+// Opens an image decoding the stereo photo containers (MPO, JPS) into a left|right FullSbs image
+pub fn open(path: &Path, default_mode: StereoMode) -> anyhow::Result<(DynamicImage, StereoMode)> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("mpo") => {
+            let bytes = std::fs::read(path)?;
+            Ok((decode_mpo(&bytes)?, StereoMode::FullSbs))
+        }
+        Some("jps") => {
+            let bytes = std::fs::read(path)?;
+            Ok((decode_jps(&bytes)?, StereoMode::FullSbs))
+        }
+        _ => Ok((image::open(path)?, default_mode)),
+    }
+}
+
+pub fn decode_mpo(bytes: &[u8]) -> anyhow::Result<DynamicImage> {
+    let frames = mpo_frame_ranges(bytes)?;
+    if frames.len() < 2 {
+        bail!("MPO file contains a single picture");
+    }
+
+    // The first two individual images of a stereoscopic MPO are the left and right views
+    let left = image::load_from_memory_with_format(
+        bytes
+            .get(frames[0].clone())
+            .context("Invalid MPO left frame")?,
+        ImageFormat::Jpeg,
+    )?;
+    let right = image::load_from_memory_with_format(
+        bytes
+            .get(frames[1].clone())
+            .context("Invalid MPO right frame")?,
+        ImageFormat::Jpeg,
+    )?;
+
+    Ok(compose_sbs(&left, &right))
+}
+
+pub fn decode_jps(bytes: &[u8]) -> anyhow::Result<DynamicImage> {
+    let image = image::load_from_memory_with_format(bytes, ImageFormat::Jpeg)?;
+    let (width, height) = image.dimensions();
+    let eye_width = width / 2;
+
+    // JPS files are stored for cross-eyed viewing, the right view comes first
+    let right = image.crop_imm(0, 0, eye_width, height);
+    let left = image.crop_imm(eye_width, 0, eye_width, height);
+
+    Ok(compose_sbs(&left, &right))
+}
+
+fn compose_sbs(left: &DynamicImage, right: &DynamicImage) -> DynamicImage {
+    let (left_width, left_height) = left.dimensions();
+    let (right_width, right_height) = right.dimensions();
+    let mut output = RgbaImage::new(left_width + right_width, left_height.max(right_height));
+    imageops::replace(&mut output, &left.to_rgba8(), 0, 0);
+    imageops::replace(&mut output, &right.to_rgba8(), left_width as i64, 0);
+    DynamicImage::ImageRgba8(output)
+}
+
+fn read_bytes(bytes: &[u8], offset: usize, len: usize) -> anyhow::Result<&[u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .context("Unexpected end of MPO data")
+}
+
+// Walks the JPEG markers of the first image looking for the APP2 MP Extensions segment
+fn mpo_frame_ranges(bytes: &[u8]) -> anyhow::Result<Vec<Range<usize>>> {
+    if read_bytes(bytes, 0, 2)? != JPEG_SOI {
+        bail!("MPO data doesn't start with a JPEG SOI marker");
+    }
+
+    let mut position = 2;
+    loop {
+        let marker = read_bytes(bytes, position, 2)?;
+        if marker[0] != 0xFF {
+            bail!("Invalid JPEG marker at {}", position);
+        }
+        if marker[1] == JPEG_SOS || marker[1] == JPEG_EOI {
+            bail!("MPO file has no MP Extensions segment");
+        }
+
+        let length = read_bytes(bytes, position + 2, 2)?;
+        let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+        let segment = read_bytes(bytes, position + 4, length.saturating_sub(2))?;
+
+        if marker[1] == JPEG_APP2 && segment.starts_with(MPF_IDENTIFIER) {
+            // MP entry offsets are relative to the TIFF header that follows the identifier
+            let header_offset = position + 4 + MPF_IDENTIFIER.len();
+            return parse_mp_index(&segment[MPF_IDENTIFIER.len()..], header_offset, bytes.len());
+        }
+
+        position += 2 + length;
+    }
+}
+
+fn parse_mp_index(
+    data: &[u8],
+    header_offset: usize,
+    file_len: usize,
+) -> anyhow::Result<Vec<Range<usize>>> {
+    let little_endian = match read_bytes(data, 0, 2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => bail!("Invalid MP index byte order"),
+    };
+    let read_u16 = |offset: usize| -> anyhow::Result<u16> {
+        let value: [u8; 2] = read_bytes(data, offset, 2)?.try_into()?;
+        Ok(match little_endian {
+            true => u16::from_le_bytes(value),
+            false => u16::from_be_bytes(value),
+        })
+    };
+    let read_u32 = |offset: usize| -> anyhow::Result<u32> {
+        let value: [u8; 4] = read_bytes(data, offset, 4)?.try_into()?;
+        Ok(match little_endian {
+            true => u32::from_le_bytes(value),
+            false => u32::from_be_bytes(value),
+        })
+    };
+
+    let ifd_offset = read_u32(4)? as usize;
+    let tag_count = read_u16(ifd_offset)? as usize;
+    let (entries_offset, entries_count) = (0..tag_count)
+        .map(|tag_idx| ifd_offset + 2 + tag_idx * 12)
+        .find_map(|tag_offset| match read_u16(tag_offset) {
+            Ok(MP_ENTRY_TAG) => Some((read_u32(tag_offset + 8), read_u32(tag_offset + 4))),
+            _ => None,
+        })
+        .context("MP index has no MP entries")?;
+    let entries_offset = entries_offset? as usize;
+    let entries_count = entries_count? as usize / MP_ENTRY_SIZE;
+
+    (0..entries_count)
+        .map(|entry_idx| {
+            let entry_offset = entries_offset + entry_idx * MP_ENTRY_SIZE;
+            let size = read_u32(entry_offset + 4)? as usize;
+            // The first image offset is always zero, as it starts at the beginning of the file
+            let start = match read_u32(entry_offset + 8)? as usize {
+                0 => 0,
+                offset => header_offset + offset,
+            };
+            match start.checked_add(size) {
+                Some(end) if end <= file_len => Ok(start..end),
+                _ => bail!("MP entry {} points outside of the file", entry_idx),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::jpeg::JpegEncoder, GenericImageView, Rgb, RgbImage};
+
+    use super::{decode_jps, decode_mpo, read_bytes, JPEG_APP2, MPF_IDENTIFIER, MP_ENTRY_TAG};
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    fn jpeg(image: &RgbImage) -> Vec<u8> {
+        let mut bytes = vec![];
+        JpegEncoder::new_with_quality(&mut bytes, 100)
+            .encode_image(image)
+            .unwrap();
+        bytes
+    }
+
+    // Two pictures, the first one carrying a big endian MP index right after its SOI marker
+    fn mpo(left: &[u8], right: &[u8]) -> Vec<u8> {
+        // Offsets within the MP index, relative to its TIFF header
+        const IFD_OFFSET: u32 = 8;
+        const ENTRIES_OFFSET: u32 = IFD_OFFSET + 2 + 12 + 4;
+        const INDEX_LEN: usize = ENTRIES_OFFSET as usize + 2 * 16;
+        // SOI, APP2 marker and length, MPF identifier
+        const HEADER_OFFSET: usize = 2 + 4 + 4;
+
+        let left_len = left.len() + 4 + MPF_IDENTIFIER.len() + INDEX_LEN;
+        let mut index = vec![];
+        index.extend_from_slice(b"MM\0\x2A");
+        index.extend_from_slice(&IFD_OFFSET.to_be_bytes());
+        index.extend_from_slice(&1u16.to_be_bytes());
+        index.extend_from_slice(&MP_ENTRY_TAG.to_be_bytes());
+        index.extend_from_slice(&7u16.to_be_bytes());
+        index.extend_from_slice(&32u32.to_be_bytes());
+        index.extend_from_slice(&ENTRIES_OFFSET.to_be_bytes());
+        index.extend_from_slice(&0u32.to_be_bytes());
+        for (size, offset) in [(left_len, 0), (right.len(), left_len - HEADER_OFFSET)] {
+            index.extend_from_slice(&0u32.to_be_bytes());
+            index.extend_from_slice(&(size as u32).to_be_bytes());
+            index.extend_from_slice(&(offset as u32).to_be_bytes());
+            index.extend_from_slice(&0u32.to_be_bytes());
+        }
+
+        let mut bytes = left[..2].to_vec();
+        bytes.extend_from_slice(&[0xFF, JPEG_APP2]);
+        bytes.extend_from_slice(&((2 + MPF_IDENTIFIER.len() + INDEX_LEN) as u16).to_be_bytes());
+        bytes.extend_from_slice(MPF_IDENTIFIER);
+        bytes.extend_from_slice(&index);
+        bytes.extend_from_slice(&left[2..]);
+        assert_eq!(bytes.len(), left_len);
+        bytes.extend_from_slice(right);
+        bytes
+    }
+
+    fn assert_color(image: &image::DynamicImage, x: u32, y: u32, color: [u8; 3]) {
+        let pixel = image.get_pixel(x, y);
+        assert!(
+            pixel
+                .0
+                .iter()
+                .zip(color)
+                .all(|(value, expected)| value.abs_diff(expected) < 16),
+            "Pixel at {x}x{y} is {pixel:?} instead of {color:?}"
+        );
+    }
+
+    #[test]
+    fn mpo_frames_become_left_and_right() {
+        let left = jpeg(&RgbImage::from_pixel(16, 8, Rgb(RED)));
+        let right = jpeg(&RgbImage::from_pixel(16, 8, Rgb(BLUE)));
+
+        let image = decode_mpo(&mpo(&left, &right)).unwrap();
+        assert_eq!(image.dimensions(), (32, 8));
+        assert_color(&image, 4, 4, RED);
+        assert_color(&image, 28, 4, BLUE);
+    }
+
+    #[test]
+    fn mpo_without_a_second_frame_is_rejected() {
+        let left = jpeg(&RgbImage::from_pixel(16, 8, Rgb(RED)));
+        let mut bytes = mpo(&left, &left);
+        bytes.truncate(bytes.len() - left.len() / 2);
+        assert!(decode_mpo(&bytes).is_err());
+        assert!(decode_mpo(&left).is_err());
+    }
+
+    #[test]
+    fn jps_right_view_comes_first() {
+        let mut cross_eyed = RgbImage::from_pixel(32, 8, Rgb(BLUE));
+        for x in 16..32 {
+            for y in 0..8 {
+                cross_eyed.put_pixel(x, y, Rgb(RED));
+            }
+        }
+
+        let image = decode_jps(&jpeg(&cross_eyed)).unwrap();
+        assert_eq!(image.dimensions(), (32, 8));
+        assert_color(&image, 4, 4, RED);
+        assert_color(&image, 28, 4, BLUE);
+    }
+
+    #[test]
+    fn out_of_range_reads_fail() {
+        let bytes = [0u8; 4];
+        assert!(read_bytes(&bytes, 2, 2).is_ok());
+        assert!(read_bytes(&bytes, 3, 2).is_err());
+        assert!(read_bytes(&bytes, usize::MAX, 2).is_err());
+    }
+}
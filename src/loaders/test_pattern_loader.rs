@@ -0,0 +1,433 @@
+use std::{ops::Range, time::Duration};
+
+use clap::ValueEnum;
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use wgpu::{Device, Instance, Queue};
+
+use crate::engine::texture::{Texture2D, Unbound};
+
+use super::{FrameContext, FrameUpdate, Loader, StereoMode, TextureSource};
+
+// Resolution of a single eye for full resolution layouts
+const EYE_WIDTH: u32 = 1920;
+const EYE_HEIGHT: u32 = 1080;
+// Time the sweep bar takes to cross the screen
+const SWEEP_PERIOD: Duration = Duration::from_secs(2);
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const GREY: Rgba<u8> = Rgba([48, 48, 48, 255]);
+const LEFT_COLOR: Rgba<u8> = Rgba([255, 64, 64, 255]);
+const RIGHT_COLOR: Rgba<u8> = Rgba([64, 160, 255, 255]);
+
+// 5x7 bitmaps of the eye labels, one row per byte with the leftmost pixel in the highest bit
+const GLYPH_L: [u8; 7] = [
+    0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+];
+const GLYPH_R: [u8; 7] = [
+    0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum TestPattern {
+    EyeMarkers,
+    ConvergenceGrid,
+    ColorBars,
+    Sweep,
+    ResolutionWedge,
+}
+
+// Frame of an animated pattern with the columns its sweep bar is currently drawn over
+struct AnimatedFrame {
+    background: RgbaImage,
+    bar: Vec<Range<u32>>,
+}
+
+// Generates calibration patterns laid out following the selected stereo mode. Animated patterns
+// are rendered once without their sweep bar, only the columns the bar moves over are rewritten
+// on every frame.
+pub struct TestPatternLoader {
+    pattern: TestPattern,
+    stereo_mode: StereoMode,
+    frame: u64,
+    // The bound texture, or one per eye for frame sequential patterns copied on every frame
+    animated_frames: Vec<(AnimatedFrame, Option<Texture2D<Unbound>>)>,
+}
+
+impl TestPatternLoader {
+    pub fn new(pattern: TestPattern, stereo_mode: StereoMode) -> Self {
+        Self {
+            pattern,
+            stereo_mode,
+            frame: 0,
+            animated_frames: vec![],
+        }
+    }
+
+    fn is_animated(&self) -> bool {
+        self.pattern == TestPattern::Sweep || self.stereo_mode == StereoMode::FrameSequential
+    }
+
+    fn eye_size(&self) -> (u32, u32) {
+        match self.stereo_mode {
+            StereoMode::Sbs => (EYE_WIDTH / 2, EYE_HEIGHT),
+            StereoMode::Tab => (EYE_WIDTH, EYE_HEIGHT / 2),
+            _ => (EYE_WIDTH, EYE_HEIGHT),
+        }
+    }
+
+    // Frame sequential sources alternate the eyes on every frame, starting with the left one
+    fn frame_eye(&self) -> usize {
+        (self.frame % 2) as usize
+    }
+
+    // Columns of the packed image covered by the sweep bar, both eyes show it at the same place
+    fn bar_columns(&self, time: Duration) -> Vec<Range<u32>> {
+        if self.pattern != TestPattern::Sweep {
+            return vec![];
+        }
+        let (eye_width, _) = self.eye_size();
+        let progress =
+            (time.as_secs_f64() % SWEEP_PERIOD.as_secs_f64()) / SWEEP_PERIOD.as_secs_f64();
+        let bar_x = (progress * eye_width as f64) as u32;
+        let bar = bar_x..(bar_x + (eye_width / 64).max(1)).min(eye_width);
+        match self.stereo_mode {
+            StereoMode::Sbs | StereoMode::FullSbs => {
+                vec![bar.clone(), bar.start + eye_width..bar.end + eye_width]
+            }
+            _ => vec![bar],
+        }
+    }
+
+    // Renders both eyes and packs them the way sources using the stereo mode would
+    pub fn render(&self, time: Duration) -> RgbaImage {
+        let mut image = self.render_background(self.frame_eye());
+        for columns in self.bar_columns(time) {
+            for y in 0..image.height() {
+                for x in columns.clone() {
+                    let pixel = bar_pixel(*image.get_pixel(x, y));
+                    image.put_pixel(x, y, pixel);
+                }
+            }
+        }
+        image
+    }
+
+    // The packed pattern without the sweep bar, frame sequential patterns only hold the given eye
+    fn render_background(&self, frame_eye: usize) -> RgbaImage {
+        let (eye_width, eye_height) = self.eye_size();
+        let render_eye = |eye| render_pattern(self.pattern, eye, eye_width, eye_height);
+
+        match self.stereo_mode {
+            StereoMode::Mono => render_eye(0),
+            StereoMode::FrameSequential => render_eye(frame_eye),
+            StereoMode::Sbs | StereoMode::FullSbs => {
+                let mut output = RgbaImage::new(eye_width * 2, eye_height);
+                imageops::replace(&mut output, &render_eye(0), 0, 0);
+                imageops::replace(&mut output, &render_eye(1), eye_width as i64, 0);
+                output
+            }
+            StereoMode::Tab | StereoMode::FullTab => {
+                let mut output = RgbaImage::new(eye_width, eye_height * 2);
+                imageops::replace(&mut output, &render_eye(0), 0, 0);
+                imageops::replace(&mut output, &render_eye(1), 0, eye_height as i64);
+                output
+            }
+            StereoMode::RowInterlaced | StereoMode::ColumnInterlaced | StereoMode::Checkerboard => {
+                let (left, right) = (render_eye(0), render_eye(1));
+                RgbaImage::from_fn(eye_width, eye_height, |x, y| {
+                    let right_eye = match self.stereo_mode {
+                        StereoMode::RowInterlaced => y % 2 == 1,
+                        StereoMode::ColumnInterlaced => x % 2 == 1,
+                        _ => (x + y) % 2 == 1,
+                    };
+                    if right_eye {
+                        *right.get_pixel(x, y)
+                    } else {
+                        *left.get_pixel(x, y)
+                    }
+                })
+            }
+        }
+    }
+}
+
+// Eye tags and tick marks stay on top of the sweep bar
+fn bar_pixel(background: Rgba<u8>) -> Rgba<u8> {
+    match background {
+        LEFT_COLOR | RIGHT_COLOR => background,
+        _ => WHITE,
+    }
+}
+
+// Writes the given columns of the background into the texture, with or without the sweep bar
+fn write_columns(
+    queue: &Queue,
+    texture: &wgpu::Texture,
+    background: &RgbaImage,
+    columns: &Range<u32>,
+    bar: bool,
+) {
+    let width = columns.end.saturating_sub(columns.start);
+    if width == 0 {
+        return;
+    }
+    let mut pixels = Vec::with_capacity((width * background.height() * 4) as usize);
+    for y in 0..background.height() {
+        for x in columns.clone() {
+            let pixel = *background.get_pixel(x, y);
+            let pixel = if bar { bar_pixel(pixel) } else { pixel };
+            pixels.extend_from_slice(&pixel.0);
+        }
+    }
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: columns.start,
+                y: 0,
+                z: 0,
+            },
+            aspect: wgpu::TextureAspect::All,
+        },
+        &pixels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * width),
+            rows_per_image: Some(background.height()),
+        },
+        wgpu::Extent3d {
+            width,
+            height: background.height(),
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+// Moves the sweep bar, restoring the columns it covered before
+fn move_bar(
+    queue: &Queue,
+    texture: &wgpu::Texture,
+    frame: &mut AnimatedFrame,
+    bar: Vec<Range<u32>>,
+) {
+    if frame.bar == bar {
+        return;
+    }
+    for columns in &frame.bar {
+        write_columns(queue, texture, &frame.background, columns, false);
+    }
+    for columns in &bar {
+        write_columns(queue, texture, &frame.background, columns, true);
+    }
+    frame.bar = bar;
+}
+
+// Static part of the pattern, the sweep bar is drawn separately
+fn render_pattern(pattern: TestPattern, eye: usize, width: u32, height: u32) -> RgbaImage {
+    let eye_color = match eye {
+        0 => LEFT_COLOR,
+        _ => RIGHT_COLOR,
+    };
+    let mut image = match pattern {
+        TestPattern::EyeMarkers => {
+            let mut image = RgbaImage::from_pixel(width, height, GREY);
+            let glyph = match eye {
+                0 => &GLYPH_L,
+                _ => &GLYPH_R,
+            };
+            let scale = height / 14;
+            draw_glyph(
+                &mut image,
+                glyph,
+                (width - 5 * scale) / 2,
+                (height - 7 * scale) / 2,
+                scale,
+                eye_color,
+            );
+            image
+        }
+        TestPattern::ConvergenceGrid => {
+            let cell = height / 9;
+            let (center_x, center_y) = (width / 2, height / 2);
+            RgbaImage::from_fn(width, height, |x, y| {
+                let on_center = x.abs_diff(center_x) < 2 || y.abs_diff(center_y) < 2;
+                let on_grid = x.abs_diff(center_x) % cell == 0 || y.abs_diff(center_y) % cell == 0;
+                match (on_center, on_grid) {
+                    (true, _) => eye_color,
+                    (_, true) => WHITE,
+                    _ => BLACK,
+                }
+            })
+        }
+        TestPattern::ColorBars => {
+            const BARS: [[u8; 3]; 7] = [
+                [191, 191, 191],
+                [191, 191, 0],
+                [0, 191, 191],
+                [0, 191, 0],
+                [191, 0, 191],
+                [191, 0, 0],
+                [0, 0, 191],
+            ];
+            RgbaImage::from_fn(width, height, |x, y| {
+                if y < height * 2 / 3 {
+                    let [r, g, b] = BARS[(x * BARS.len() as u32 / width) as usize];
+                    Rgba([r, g, b, 255])
+                } else if y < height * 5 / 6 {
+                    // Continuous gamma ramp
+                    let value = (x * 255 / (width - 1)) as u8;
+                    Rgba([value, value, value, 255])
+                } else {
+                    // 16 steps to spot crushed blacks and clipped whites
+                    let value = (x * 16 / width * 17) as u8;
+                    Rgba([value, value, value, 255])
+                }
+            })
+        }
+        TestPattern::Sweep => RgbaImage::from_fn(width, height, |x, y| {
+            if y > height * 15 / 16 && x % (width / 32) == 0 {
+                // Fixed ticks to judge the bar motion against
+                eye_color
+            } else {
+                BLACK
+            }
+        }),
+        TestPattern::ResolutionWedge => {
+            // Stripes narrow from 16 pixels at the top down to single pixel lines at the bottom
+            RgbaImage::from_fn(width, height, |x, y| {
+                let period = 1 + 15 * (height - 1 - y) / (height - 1);
+                match (x / period) % 2 {
+                    0 => WHITE,
+                    _ => BLACK,
+                }
+            })
+        }
+    };
+
+    // Small eye tag in the corner so every pattern can be checked for swapped eyes
+    if pattern != TestPattern::EyeMarkers {
+        let glyph = match eye {
+            0 => &GLYPH_L,
+            _ => &GLYPH_R,
+        };
+        let scale = (height / 54).max(1);
+        draw_glyph(&mut image, glyph, scale * 2, scale * 2, scale, eye_color);
+    }
+    image
+}
+
+fn draw_glyph(image: &mut RgbaImage, glyph: &[u8; 7], x: u32, y: u32, scale: u32, color: Rgba<u8>) {
+    for (row, bits) in glyph.iter().enumerate() {
+        for column in 0..5 {
+            if bits & (0b10000 >> column) == 0 {
+                continue;
+            }
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    let (px, py) = (x + column * scale + dx, y + row as u32 * scale + dy);
+                    if px < image.width() && py < image.height() {
+                        image.put_pixel(px, py, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Loader for TestPatternLoader {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        // A new texture also means a new frame sequential buffer, which starts with the left eye
+        self.frame = 0;
+        self.animated_frames.clear();
+        let image = self.render(Duration::ZERO);
+        let (width, height) = image.dimensions();
+
+        if self.stereo_mode == StereoMode::FrameSequential {
+            for eye in 0..2 {
+                let background = self.render_background(eye);
+                let texture = Texture2D::<Unbound>::from_image(
+                    device,
+                    queue,
+                    &DynamicImage::ImageRgba8(background.clone()),
+                    Some("TestPatternEye"),
+                );
+                self.animated_frames.push((
+                    AnimatedFrame {
+                        background,
+                        bar: vec![],
+                    },
+                    Some(texture),
+                ));
+            }
+        } else if self.is_animated() {
+            self.animated_frames.push((
+                AnimatedFrame {
+                    background: self.render_background(0),
+                    bar: self.bar_columns(Duration::ZERO),
+                },
+                None,
+            ));
+        }
+        log::info!(
+            "Generated {:?} test pattern ({}x{}, {:?})",
+            self.pattern,
+            width,
+            height,
+            self.stereo_mode
+        );
+
+        Ok(TextureSource {
+            texture: Texture2D::<Unbound>::from_image(
+                device,
+                queue,
+                &DynamicImage::ImageRgba8(image),
+                Some("TestPattern"),
+            ),
+            width,
+            height,
+            stereo_mode: self.stereo_mode,
+            planes: None,
+        })
+    }
+
+    fn is_invalid(&self) -> bool {
+        false
+    }
+
+    fn update(&mut self, context: &mut FrameContext) -> anyhow::Result<FrameUpdate> {
+        if !self.is_animated() {
+            return Ok(FrameUpdate::Unchanged);
+        }
+
+        let bar = self.bar_columns(context.display_time);
+        let frame_idx = match self.stereo_mode {
+            StereoMode::FrameSequential => self.frame_eye(),
+            _ => 0,
+        };
+        let Some((frame, eye_texture)) = self.animated_frames.get_mut(frame_idx) else {
+            return Ok(FrameUpdate::Unchanged);
+        };
+        match eye_texture {
+            // Written before the copy, queue writes run ahead of the frame commands
+            Some(eye_texture) => {
+                move_bar(context.queue, &eye_texture.texture, frame, bar);
+                context.encoder.copy_texture_to_texture(
+                    eye_texture.texture.as_image_copy(),
+                    context.texture.texture.as_image_copy(),
+                    context.texture.texture.size(),
+                );
+            }
+            None => move_bar(context.queue, &context.texture.texture, frame, bar),
+        }
+        self.frame += 1;
+        Ok(FrameUpdate::NewFrame)
+    }
+}
@@ -0,0 +1,501 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use anyhow::{bail, Context};
+use image::ImageFormat;
+use v4l::{
+    buffer::Type,
+    format::{Colorspace, Quantization},
+    io::{mmap::Stream, traits::CaptureStream},
+    video::Capture,
+    FourCC,
+};
+use wgpu::{Device, Instance, Queue};
+
+use crate::engine::{
+    texture::{Texture2D, Unbound},
+    yuv::{YuvColorSpace, YuvConverter, YuvFormat},
+};
+
+use super::{FrameContext, FrameUpdate, Loader, StereoMode, TextureSource};
+
+// Formats asked to the device in order of preference, MJPEG is decoded on the CPU
+const PREFERRED_FORMATS: [&[u8; 4]; 3] = [b"NV12", b"YUYV", b"MJPG"];
+const CAPTURE_BUFFERS: u32 = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum CaptureFormat {
+    #[default]
+    Rgba,
+    Yuv(YuvFormat),
+}
+
+#[derive(Default)]
+struct V4lFrame {
+    format: CaptureFormat,
+    width: u32,
+    height: u32,
+    stride: u32,
+    data: Vec<u8>,
+    sequence: u64,
+}
+
+// Bytes a complete frame takes, the planes of NV12 share the same stride
+fn frame_size(format: CaptureFormat, stride: u32, height: u32) -> usize {
+    let rows = match format {
+        CaptureFormat::Yuv(YuvFormat::Nv12) => height + (height + 1) / 2,
+        _ => height,
+    };
+    stride as usize * rows as usize
+}
+
+impl V4lFrame {
+    fn expected_size(&self) -> usize {
+        frame_size(self.format, self.stride, self.height)
+    }
+
+    fn layout_changed(&self, other: &V4lFrame) -> bool {
+        self.format != other.format
+            || self.width != other.width
+            || self.height != other.height
+            || self.stride != other.stride
+    }
+}
+
+// Captures a V4L2 device (webcams, HDMI grabbers, v4l2loopback) on a background thread
+pub struct V4lLoader {
+    device_path: String,
+    stereo_mode: StereoMode,
+    frame: Arc<Mutex<V4lFrame>>,
+    running: Arc<AtomicBool>,
+    capture_thread: Option<JoinHandle<()>>,
+    loaded_frame: Option<V4lFrame>,
+    converter: Option<YuvConverter>,
+    // Color space and range of YUV frames, as negotiated with the device
+    yuv_color: (YuvColorSpace, bool),
+}
+
+impl V4lLoader {
+    pub fn new(device_path: &str, stereo_mode: StereoMode) -> Self {
+        Self {
+            device_path: device_path.to_owned(),
+            stereo_mode,
+            frame: Arc::new(Mutex::new(V4lFrame::default())),
+            running: Arc::new(AtomicBool::new(false)),
+            capture_thread: None,
+            loaded_frame: None,
+            converter: None,
+            yuv_color: (YuvColorSpace::Bt601, false),
+        }
+    }
+
+    fn ensure_capturing(&mut self) -> anyhow::Result<()> {
+        if self.running.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.stop_capturing();
+
+        let device = v4l::Device::with_path(&self.device_path)
+            .with_context(|| format!("Cannot open {}", self.device_path))?;
+        let format = negotiate_format(&device)?;
+        log::info!(
+            "Capturing {} as {} {}x{}",
+            self.device_path,
+            format.fourcc,
+            format.width,
+            format.height
+        );
+        self.yuv_color = yuv_color(&format);
+
+        let frame = self.frame.clone();
+        let running = self.running.clone();
+        running.store(true, Ordering::Relaxed);
+        self.capture_thread = Some(
+            std::thread::Builder::new()
+                .name("V4L2 Capture".to_owned())
+                .spawn(move || {
+                    if let Err(err) = capture(&device, &format, &frame, &running) {
+                        log::error!("V4L2 capture failed: {}", err);
+                    }
+                    running.store(false, Ordering::Relaxed);
+                })?,
+        );
+        Ok(())
+    }
+
+    fn stop_capturing(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(capture_thread) = self.capture_thread.take() {
+            let _ = capture_thread.join();
+        }
+    }
+
+    fn upload_frame(
+        &self,
+        frame: &V4lFrame,
+        queue: &Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        view: &wgpu::TextureView,
+    ) -> anyhow::Result<()> {
+        if frame.data.len() < frame.expected_size() {
+            bail!(
+                "V4L2 frame of {} bytes is shorter than the expected {}",
+                frame.data.len(),
+                frame.expected_size()
+            );
+        }
+        match &self.converter {
+            Some(converter) => {
+                converter.upload(queue, &frame.data, frame.stride);
+                converter.convert(encoder, view);
+            }
+            None => queue.write_texture(
+                texture.as_image_copy(),
+                &frame.data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(frame.stride),
+                    rows_per_image: Some(frame.height),
+                },
+                texture.size(),
+            ),
+        }
+        Ok(())
+    }
+}
+
+// Devices that don't tell fall back to the V4L2 defaults: full range for JPEG, limited otherwise
+fn yuv_color(format: &v4l::Format) -> (YuvColorSpace, bool) {
+    let color_space = match format.colorspace {
+        Colorspace::Rec709 => YuvColorSpace::Bt709,
+        Colorspace::SMPTE170M
+        | Colorspace::BT878
+        | Colorspace::NTSC
+        | Colorspace::EBUTech3213
+        | Colorspace::JPEG
+        | Colorspace::SRGB => YuvColorSpace::Bt601,
+        _ => YuvColorSpace::from_height(format.height),
+    };
+    let full_range = match format.quantization {
+        Quantization::FullRange => true,
+        Quantization::LimitedRange => false,
+        _ => format.colorspace == Colorspace::JPEG,
+    };
+    (color_space, full_range)
+}
+
+fn negotiate_format(device: &v4l::Device) -> anyhow::Result<v4l::Format> {
+    let mut format = device.format()?;
+    for fourcc in PREFERRED_FORMATS {
+        format.fourcc = FourCC::new(fourcc);
+        let negotiated = device.set_format(&format)?;
+        if negotiated.fourcc == format.fourcc {
+            return Ok(negotiated);
+        }
+    }
+    bail!(
+        "The device doesn't support any of NV12, YUYV or MJPG (got {})",
+        device.format()?.fourcc
+    )
+}
+
+fn capture(
+    device: &v4l::Device,
+    format: &v4l::Format,
+    frame: &Mutex<V4lFrame>,
+    running: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut stream = Stream::with_buffers(device, Type::VideoCapture, CAPTURE_BUFFERS)?;
+    while running.load(Ordering::Relaxed) {
+        let (buffer, metadata) = stream.next()?;
+        let buffer = &buffer[..(metadata.bytesused as usize).min(buffer.len())];
+
+        let (capture_format, stride, data) = match &format.fourcc.repr {
+            b"NV12" => (CaptureFormat::Yuv(YuvFormat::Nv12), format.stride, None),
+            b"YUYV" => (CaptureFormat::Yuv(YuvFormat::Yuyv), format.stride, None),
+            _ => {
+                // Some devices send broken frames while starting up, skip them
+                let Ok(image) = image::load_from_memory_with_format(buffer, ImageFormat::Jpeg)
+                else {
+                    continue;
+                };
+                (
+                    CaptureFormat::Rgba,
+                    image.width() * 4,
+                    Some(image.to_rgba8().into_raw()),
+                )
+            }
+        };
+
+        let data = data.as_deref().unwrap_or(buffer);
+        // Truncated buffers are dropped, the previous frame stays on screen
+        if data.len() < frame_size(capture_format, stride, format.height) {
+            log::warn!("Skipping a truncated V4L2 frame of {} bytes", data.len());
+            continue;
+        }
+        if let Ok(mut frame) = frame.lock() {
+            frame.format = capture_format;
+            frame.width = format.width;
+            frame.height = format.height;
+            frame.stride = stride;
+            frame.data.clear();
+            frame.data.extend_from_slice(data);
+            frame.sequence = frame.sequence.wrapping_add(1);
+        }
+    }
+    Ok(())
+}
+
+impl Loader for V4lLoader {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        self.loaded_frame = None;
+        self.converter = None;
+        self.ensure_capturing()?;
+
+        let frame = self
+            .frame
+            .lock()
+            .map_err(|_| anyhow::anyhow!("V4L2 frame lock poisoned"))?;
+        if frame.data.is_empty() {
+            bail!("No V4L2 frame captured yet");
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("V4L2Capture"),
+            size: wgpu::Extent3d {
+                width: frame.width,
+                height: frame.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // YUV frames are converted on the GPU straight into the screen texture
+        let converter = match frame.format {
+            CaptureFormat::Yuv(yuv_format) => Some(YuvConverter::new(
+                device,
+                yuv_format,
+                frame.width,
+                frame.height,
+                self.yuv_color.0,
+                self.yuv_color.1,
+                texture.format(),
+            )),
+            CaptureFormat::Rgba => None,
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("V4L2 Upload Encoder"),
+        });
+        self.converter = converter;
+        self.upload_frame(&frame, queue, &mut encoder, &texture, &view)?;
+        queue.submit(Some(encoder.finish()));
+
+        let loaded_frame = V4lFrame {
+            format: frame.format,
+            width: frame.width,
+            height: frame.height,
+            stride: frame.stride,
+            data: vec![],
+            sequence: frame.sequence,
+        };
+        let (width, height) = (loaded_frame.width, loaded_frame.height);
+        self.loaded_frame = Some(loaded_frame);
+
+        Ok(TextureSource {
+            texture: Texture2D::<Unbound>::from_wgpu(device, texture),
+            width,
+            height,
+            stereo_mode: self.stereo_mode,
+            planes: None,
+        })
+    }
+
+    fn is_invalid(&self) -> bool {
+        if !self.running.load(Ordering::Relaxed) {
+            return true;
+        }
+        match (&self.loaded_frame, self.frame.lock()) {
+            (Some(loaded_frame), Ok(frame)) => frame.layout_changed(loaded_frame),
+            _ => true,
+        }
+    }
+
+    fn update(&mut self, context: &mut FrameContext) -> anyhow::Result<FrameUpdate> {
+        let Ok(frame) = self.frame.lock() else {
+            return Ok(FrameUpdate::Unchanged);
+        };
+        let Some(loaded_frame) = &self.loaded_frame else {
+            return Ok(FrameUpdate::Unchanged);
+        };
+        // Size or format changes are handled by reloading through is_invalid
+        if frame.sequence == loaded_frame.sequence || frame.layout_changed(loaded_frame) {
+            return Ok(FrameUpdate::Unchanged);
+        }
+
+        let sequence = frame.sequence;
+        let uploaded = self.upload_frame(
+            &frame,
+            context.queue,
+            context.encoder,
+            &context.texture.texture,
+            &context.texture.view,
+        );
+        if let Some(loaded_frame) = &mut self.loaded_frame {
+            loaded_frame.sequence = sequence;
+        }
+        uploaded.map(|_| FrameUpdate::NewFrame)
+    }
+}
+
+impl Drop for V4lLoader {
+    fn drop(&mut self) {
+        self.stop_capturing();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant},
+    };
+
+    use v4l::{
+        format::{Colorspace, Quantization},
+        FourCC,
+    };
+
+    use super::{
+        capture, frame_size, negotiate_format, yuv_color, CaptureFormat, V4lFrame,
+        PREFERRED_FORMATS,
+    };
+    use crate::engine::yuv::{yuv_planes, YuvColorSpace, YuvFormat};
+
+    const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+    // The loopback device needs a producer, V4L2LOOPBACK_DEVICE overrides /dev/video0
+    fn loopback_device() -> v4l::Device {
+        let path =
+            std::env::var("V4L2LOOPBACK_DEVICE").unwrap_or_else(|_| "/dev/video0".to_owned());
+        v4l::Device::with_path(path).unwrap()
+    }
+
+    fn format(colorspace: Colorspace, quantization: Quantization, height: u32) -> v4l::Format {
+        let mut format = v4l::Format::new(640, height, FourCC::new(b"YUYV"));
+        format.colorspace = colorspace;
+        format.quantization = quantization;
+        format
+    }
+
+    #[test]
+    fn nv12_frames_include_the_chroma_rows() {
+        let nv12 = CaptureFormat::Yuv(YuvFormat::Nv12);
+        assert_eq!(frame_size(nv12, 640, 480), 640 * 720);
+        assert_eq!(frame_size(nv12, 642, 481), 642 * 722);
+    }
+
+    #[test]
+    fn packed_frames_are_one_plane() {
+        assert_eq!(
+            frame_size(CaptureFormat::Yuv(YuvFormat::Yuyv), 1280, 480),
+            1280 * 480
+        );
+        assert_eq!(frame_size(CaptureFormat::Rgba, 2560, 480), 2560 * 480);
+    }
+
+    #[test]
+    fn odd_sizes_round_the_chroma_up() {
+        let [_, (_, width, height)] = yuv_planes(YuvFormat::Nv12, 641, 481);
+        assert_eq!((width, height), (321, 241));
+        let [(_, width, height), _] = yuv_planes(YuvFormat::Yuyv, 641, 481);
+        assert_eq!((width, height), (321, 481));
+    }
+
+    #[test]
+    fn yuv_color_follows_the_device() {
+        assert_eq!(
+            yuv_color(&format(Colorspace::Rec709, Quantization::FullRange, 480)),
+            (YuvColorSpace::Bt709, true)
+        );
+        assert_eq!(
+            yuv_color(&format(
+                Colorspace::SMPTE170M,
+                Quantization::LimitedRange,
+                1080
+            )),
+            (YuvColorSpace::Bt601, false)
+        );
+    }
+
+    #[test]
+    fn yuv_color_falls_back_to_the_defaults() {
+        assert_eq!(
+            yuv_color(&format(Colorspace::JPEG, Quantization::Default, 1080)),
+            (YuvColorSpace::Bt601, true)
+        );
+        assert_eq!(
+            yuv_color(&format(Colorspace::Default, Quantization::Default, 480)),
+            (YuvColorSpace::Bt601, false)
+        );
+        assert_eq!(
+            yuv_color(&format(Colorspace::Default, Quantization::Default, 1080)),
+            (YuvColorSpace::Bt709, false)
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a fed v4l2loopback device, run with cargo test --features v4l -- --ignored v4l"]
+    fn v4l_format_is_negotiated() {
+        let device = loopback_device();
+        let format = negotiate_format(&device).unwrap();
+        assert!(PREFERRED_FORMATS.contains(&&format.fourcc.repr));
+        assert!(format.width > 0 && format.height > 0);
+        assert!(format.stride >= format.width);
+    }
+
+    #[test]
+    #[ignore = "needs a fed v4l2loopback device, run with cargo test --features v4l -- --ignored v4l"]
+    fn v4l_frames_are_captured() {
+        let device = loopback_device();
+        let format = negotiate_format(&device).unwrap();
+        let frame = Mutex::new(V4lFrame::default());
+        let running = AtomicBool::new(true);
+        std::thread::scope(|scope| {
+            let capture_thread = scope.spawn(|| capture(&device, &format, &frame, &running));
+            let deadline = Instant::now() + FIRST_FRAME_TIMEOUT;
+            while frame.lock().unwrap().sequence == 0 && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            running.store(false, Ordering::Relaxed);
+            capture_thread.join().unwrap().unwrap();
+        });
+
+        let frame = frame.into_inner().unwrap();
+        assert!(frame.sequence > 0);
+        assert_eq!((frame.width, frame.height), (format.width, format.height));
+        assert!(frame.data.len() >= frame.expected_size());
+    }
+}
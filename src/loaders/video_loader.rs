@@ -0,0 +1,610 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use ffmpeg_next as ffmpeg;
+use wgpu::{CommandEncoder, Device, Instance, Queue};
+
+use crate::engine::{
+    texture::{RoundRobinTextureBuffer, Texture2D, Unbound},
+    yuv::{YuvColorSpace, YuvFormat, YuvParamsUniform, YuvPlanes},
+};
+
+use super::{
+    stereo_mode_from_path, FrameContext, FrameUpdate, Loader, LoaderAction, StereoMode,
+    TextureSource,
+};
+
+// Decoded frames waiting to be uploaded, bounds how far the decoder can run ahead of the ring
+const DECODED_FRAME_QUEUE: usize = 2;
+// Textures decoded frames are uploaded to ahead of their display time
+const FRAME_RING_SIZE: usize = 4;
+// Timestamps going backwards by more than this mean the video looped
+const LOOP_THRESHOLD: Duration = Duration::from_secs(1);
+
+struct VideoFrame {
+    generation: u64,
+    pts: Duration,
+    // Stride and pixels of every plane, a single RGBA plane or the NV12 luma and chroma planes
+    planes: Vec<(u32, Vec<u8>)>,
+}
+
+enum VideoCommand {
+    Seek { generation: u64, position: Duration },
+}
+
+struct VideoDecoder {
+    width: u32,
+    height: u32,
+    duration: Option<Duration>,
+    // Set when frames are decoded to NV12 and handed over as planes
+    yuv_params: Option<YuvParamsUniform>,
+    frames: Receiver<VideoFrame>,
+    commands: Sender<VideoCommand>,
+    thread: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl VideoDecoder {
+    // The decoder thread only exits on errors (e.g. a corrupt stream) or when dropped
+    fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .map_or(false, |thread| !thread.is_finished())
+    }
+
+    // Error the decoder thread exited with
+    fn join(&mut self) -> anyhow::Error {
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(Err(err))) => err,
+            Some(Err(_)) => anyhow!("Video decoder panicked"),
+            _ => anyhow!("Video decoder stopped"),
+        }
+    }
+}
+
+impl Drop for VideoDecoder {
+    fn drop(&mut self) {
+        // Dropping the frame receiver unblocks the decoder, which then exits
+        let (_, frames) = mpsc::sync_channel(0);
+        drop(std::mem::replace(&mut self.frames, frames));
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct FrameRing {
+    // Every slot holds the planes of one frame, a single RGBA plane or the NV12 luma and chroma
+    textures: RoundRobinTextureBuffer<Vec<wgpu::Texture>, FRAME_RING_SIZE>,
+    // Timestamps of the frames waiting for their display time, oldest first, the newest frame is
+    // in the last written slot
+    pending: VecDeque<Duration>,
+    // Slots written since the one holding the shown frame, which must not be overwritten
+    shown: Option<usize>,
+}
+
+impl FrameRing {
+    fn new(device: &Device, width: u32, height: u32, planar: bool) -> Self {
+        let planes = match planar {
+            true => vec![
+                (wgpu::TextureFormat::R8Unorm, width, height),
+                (
+                    wgpu::TextureFormat::Rg8Unorm,
+                    (width + 1) / 2,
+                    (height + 1) / 2,
+                ),
+            ],
+            false => vec![(wgpu::TextureFormat::Rgba8UnormSrgb, width, height)],
+        };
+        let textures = std::array::from_fn(|idx| {
+            planes
+                .iter()
+                .map(|&(format, width, height)| {
+                    device.create_texture(&wgpu::TextureDescriptor {
+                        label: Some(format!("VideoLoader Ring {idx}").as_str()),
+                        size: wgpu::Extent3d {
+                            width,
+                            height,
+                            depth_or_array_layers: 1,
+                        },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format,
+                        usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+                        view_formats: &[],
+                    })
+                })
+                .collect()
+        });
+        Self {
+            textures: RoundRobinTextureBuffer::new(textures),
+            pending: VecDeque::new(),
+            shown: None,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.pending.len() >= FRAME_RING_SIZE
+            || self.shown.map_or(false, |shown| shown >= FRAME_RING_SIZE)
+    }
+
+    fn push(&mut self, queue: &Queue, frame: &VideoFrame) {
+        let textures = self.textures.next();
+        for ((stride, pixels), texture) in frame.planes.iter().zip(textures) {
+            queue.write_texture(
+                texture.as_image_copy(),
+                pixels,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(*stride),
+                    rows_per_image: Some(texture.height()),
+                },
+                texture.size(),
+            );
+        }
+        self.pending.push_back(frame.pts);
+        if let Some(shown) = &mut self.shown {
+            *shown += 1;
+        }
+    }
+
+    // Marks the oldest pending frame as shown
+    fn show_next(&mut self) {
+        self.shown = Some(self.pending.len());
+        self.pending.pop_front();
+    }
+
+    fn copy_shown(
+        &self,
+        encoder: &mut CommandEncoder,
+        texture: &wgpu::Texture,
+        chroma: Option<&wgpu::Texture>,
+    ) {
+        let Some(shown) = self.shown else {
+            return;
+        };
+        let targets = std::iter::once(texture).chain(chroma);
+        for (source, target) in self.textures.previous(shown).iter().zip(targets) {
+            encoder.copy_texture_to_texture(
+                source.as_image_copy(),
+                target.as_image_copy(),
+                target.size(),
+            );
+        }
+    }
+}
+
+// Plays local video files, frames are decoded on a background thread, uploaded ahead of time and
+// paced against the predicted display time of the XR frames
+pub struct VideoLoader {
+    path: PathBuf,
+    stereo_mode: StereoMode,
+    decoder: Option<VideoDecoder>,
+    ring: Option<FrameRing>,
+    // Set when the decoder thread failed, cleared when the user reloads
+    decode_error: Option<String>,
+    generation: u64,
+    paused: bool,
+    position: Duration,
+    last_display_time: Option<Duration>,
+    last_pts: Option<Duration>,
+}
+
+impl VideoLoader {
+    pub fn new(path: impl Into<PathBuf>, stereo_mode: StereoMode) -> Self {
+        Self {
+            path: path.into(),
+            stereo_mode,
+            decoder: None,
+            ring: None,
+            decode_error: None,
+            generation: 0,
+            paused: false,
+            position: Duration::ZERO,
+            last_display_time: None,
+            last_pts: None,
+        }
+    }
+
+    fn seek(&mut self, position: Duration) {
+        let position = match self.decoder.as_ref().and_then(|decoder| decoder.duration) {
+            Some(duration) => position.min(duration),
+            None => position,
+        };
+        self.generation += 1;
+        self.position = position;
+        self.last_pts = None;
+        if let Some(ring) = &mut self.ring {
+            ring.pending.clear();
+        }
+        if let Some(decoder) = &self.decoder {
+            let _ = decoder.commands.send(VideoCommand::Seek {
+                generation: self.generation,
+                position,
+            });
+        }
+    }
+
+    fn check_decoder(&mut self) {
+        if let Some(decoder) = self
+            .decoder
+            .as_mut()
+            .filter(|decoder| !decoder.is_running())
+        {
+            let err = decoder.join();
+            log::error!("Video decoding of {} failed: {}", self.path.display(), err);
+            self.decode_error = Some(err.to_string());
+            self.decoder = None;
+        }
+    }
+
+    // Uploads decoded frames until every free slot of the ring is used
+    fn fill_ring(&mut self, queue: &Queue) {
+        let (Some(decoder), Some(ring)) = (&self.decoder, &mut self.ring) else {
+            return;
+        };
+        while !ring.is_full() {
+            match decoder.frames.try_recv() {
+                Ok(frame) if frame.generation == self.generation => ring.push(queue, &frame),
+                // Frames decoded before the last seek are stale
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    // Shows the most recent frame that is due at the current playback position
+    fn show_due_frame(&mut self) -> bool {
+        let Some(ring) = &mut self.ring else {
+            return false;
+        };
+        let mut shown = false;
+        while let Some(&pts) = ring.pending.front() {
+            // The decoder loops back to the start once the file ends, show the last frames first
+            if self
+                .last_pts
+                .map_or(false, |last_pts| pts + LOOP_THRESHOLD < last_pts)
+            {
+                if shown {
+                    break;
+                }
+                self.position = pts;
+            }
+
+            if pts > self.position {
+                break;
+            }
+            self.last_pts = Some(pts);
+            ring.show_next();
+            shown = true;
+        }
+        shown
+    }
+}
+
+fn spawn_decoder(
+    path: PathBuf,
+    generation: u64,
+    position: Duration,
+    planar: bool,
+) -> anyhow::Result<VideoDecoder> {
+    ffmpeg::init()?;
+    let (frame_sender, frames) = mpsc::sync_channel(DECODED_FRAME_QUEUE);
+    let (commands, command_receiver) = mpsc::channel();
+    let (info_sender, info_receiver) = mpsc::sync_channel(1);
+
+    let thread = std::thread::Builder::new()
+        .name("Video Decoder".to_owned())
+        .spawn(move || {
+            let result = decode(
+                &path,
+                generation,
+                position,
+                planar,
+                &frame_sender,
+                &command_receiver,
+                &info_sender,
+            );
+            if let Err(err) = &result {
+                let _ = info_sender.try_send(Err(anyhow!("{err:#}")));
+            }
+            result
+        })?;
+
+    let (width, height, duration, yuv_params) = info_receiver
+        .recv()
+        .context("Video decoder exited before opening the file")??;
+
+    Ok(VideoDecoder {
+        width,
+        height,
+        duration,
+        yuv_params,
+        frames,
+        commands,
+        thread: Some(thread),
+    })
+}
+
+type VideoInfo = anyhow::Result<(u32, u32, Option<Duration>, Option<YuvParamsUniform>)>;
+
+fn decode(
+    path: &Path,
+    mut generation: u64,
+    position: Duration,
+    planar: bool,
+    frame_sender: &SyncSender<VideoFrame>,
+    command_receiver: &Receiver<VideoCommand>,
+    info_sender: &SyncSender<VideoInfo>,
+) -> anyhow::Result<()> {
+    let mut input = ffmpeg::format::input(path)?;
+    let stream = input
+        .streams()
+        .best(ffmpeg::media::Type::Video)
+        .context("No video stream found")?;
+    let stream_index = stream.index();
+    let time_base = f64::from(stream.time_base());
+    let mut decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .video()?;
+    // Planar frames only get repacked, the color conversion happens in the screen shader
+    let yuv_params = planar.then(|| {
+        let color_space = match decoder.color_space() {
+            ffmpeg::color::Space::BT709 => YuvColorSpace::Bt709,
+            ffmpeg::color::Space::BT470BG | ffmpeg::color::Space::SMPTE170M => YuvColorSpace::Bt601,
+            _ => YuvColorSpace::from_height(decoder.height()),
+        };
+        // swscale already brings the JPEG pixel formats down to limited range
+        let full_range = decoder.color_range() == ffmpeg::color::Range::JPEG
+            && !matches!(
+                decoder.format(),
+                ffmpeg::format::Pixel::YUVJ420P
+                    | ffmpeg::format::Pixel::YUVJ422P
+                    | ffmpeg::format::Pixel::YUVJ444P
+            );
+        YuvParamsUniform::new(YuvFormat::Nv12, color_space, full_range)
+    });
+    let mut scaler = ffmpeg::software::scaling::Context::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        match planar {
+            true => ffmpeg::format::Pixel::NV12,
+            false => ffmpeg::format::Pixel::RGBA,
+        },
+        decoder.width(),
+        decoder.height(),
+        ffmpeg::software::scaling::Flags::BILINEAR,
+    )?;
+
+    let duration = match input.duration() {
+        duration if duration > 0 => Some(Duration::from_micros(duration as u64)),
+        _ => None,
+    };
+    info_sender.send(Ok((
+        decoder.width(),
+        decoder.height(),
+        duration,
+        yuv_params,
+    )))?;
+
+    let seek = |input: &mut ffmpeg::format::context::Input, position: Duration| {
+        let timestamp = position.as_micros() as i64;
+        input.seek(timestamp, ..timestamp)
+    };
+
+    // Frames between the previous keyframe and the seek target are decoded but not shown
+    let mut skip_until = position;
+    if position > Duration::ZERO {
+        seek(&mut input, position)?;
+    }
+
+    let mut packet = ffmpeg::Packet::empty();
+    let mut decoded = ffmpeg::frame::Video::empty();
+    let mut converted = ffmpeg::frame::Video::empty();
+    loop {
+        match command_receiver.try_recv() {
+            Ok(VideoCommand::Seek {
+                generation: seek_generation,
+                position,
+            }) => {
+                generation = seek_generation;
+                skip_until = position;
+                seek(&mut input, position)?;
+                decoder.flush();
+            }
+            Err(TryRecvError::Disconnected) => return Ok(()),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        match packet.read(&mut input) {
+            Ok(()) if packet.stream() == stream_index => decoder.send_packet(&packet)?,
+            Ok(()) => continue,
+            // Loop back to the start once the file ends
+            Err(ffmpeg::Error::Eof) => {
+                decoder.send_eof()?;
+                while decoder.receive_frame(&mut decoded).is_ok() {}
+                seek(&mut input, Duration::ZERO)?;
+                decoder.flush();
+                skip_until = Duration::ZERO;
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            let pts = decoded
+                .timestamp()
+                .map(|timestamp| Duration::from_secs_f64((timestamp as f64 * time_base).max(0.0)))
+                .unwrap_or_default();
+            if pts < skip_until {
+                continue;
+            }
+
+            scaler.run(&decoded, &mut converted)?;
+            let frame = VideoFrame {
+                generation,
+                pts,
+                planes: (0..converted.planes())
+                    .map(|plane| {
+                        (
+                            converted.stride(plane) as u32,
+                            converted.data(plane).to_vec(),
+                        )
+                    })
+                    .collect(),
+            };
+            if frame_sender.send(frame).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Loader for VideoLoader {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        self.check_decoder();
+        if let Some(err) = &self.decode_error {
+            bail!("Video decoding of {} failed: {}", self.path.display(), err);
+        }
+
+        // Reloads keep the running decoder so playback continues where it was
+        let stereo_mode = stereo_mode_from_path(&self.path, self.stereo_mode);
+        if self.decoder.is_none() {
+            self.generation += 1;
+            self.last_pts = None;
+            self.ring = None;
+            // The planar path can't split frame sequential eyes, those keep the RGBA frames
+            let planar = stereo_mode != StereoMode::FrameSequential;
+            self.decoder = Some(
+                spawn_decoder(self.path.clone(), self.generation, self.position, planar)
+                    .with_context(|| format!("Cannot open video {}", self.path.display()))?,
+            );
+        }
+        let decoder = self.decoder.as_ref().context("Video decoder not running")?;
+        let (width, height) = (decoder.width, decoder.height);
+        let yuv_params = decoder.yuv_params;
+        let ring = self
+            .ring
+            .get_or_insert_with(|| FrameRing::new(device, width, height, yuv_params.is_some()));
+
+        log::info!(
+            "Playing video {} ({}x{}, {:?})",
+            self.path.display(),
+            width,
+            height,
+            stereo_mode
+        );
+
+        let (texture, planes) = match yuv_params {
+            Some(yuv_params) => {
+                let (luma, planes) =
+                    YuvPlanes::new(device, width, height, yuv_params, "VideoLoader");
+                (luma, Some(planes))
+            }
+            None => {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("VideoLoader"),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_DST
+                        | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                });
+                (texture, None)
+            }
+        };
+
+        if ring.shown.is_some() {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("VideoLoader Restore"),
+            });
+            ring.copy_shown(
+                &mut encoder,
+                &texture,
+                planes.as_ref().map(|planes| &planes.chroma),
+            );
+            queue.submit(Some(encoder.finish()));
+        }
+
+        Ok(TextureSource {
+            texture: Texture2D::<Unbound>::from_wgpu(device, texture),
+            width,
+            height,
+            stereo_mode,
+            planes,
+        })
+    }
+
+    fn is_invalid(&self) -> bool {
+        self.decode_error.is_some()
+            || !self
+                .decoder
+                .as_ref()
+                .map_or(false, VideoDecoder::is_running)
+    }
+
+    fn clear_error(&mut self) {
+        self.decode_error = None;
+    }
+
+    fn handle_action(&mut self, action: &LoaderAction) {
+        match action {
+            LoaderAction::TogglePlayback => {
+                self.paused = !self.paused;
+                self.last_display_time = None;
+            }
+            LoaderAction::Seek(seconds) => {
+                let offset = Duration::from_secs(seconds.unsigned_abs() as u64);
+                let position = if seconds.is_negative() {
+                    self.position.saturating_sub(offset)
+                } else {
+                    self.position + offset
+                };
+                self.seek(position);
+            }
+            _ => {}
+        }
+    }
+
+    fn update(&mut self, context: &mut FrameContext) -> anyhow::Result<FrameUpdate> {
+        if !self.paused {
+            if let Some(last_display_time) = self.last_display_time {
+                self.position += context.display_time.saturating_sub(last_display_time);
+            }
+            self.last_display_time = Some(context.display_time);
+        }
+
+        self.fill_ring(context.queue);
+        if !self.show_due_frame() {
+            return Ok(FrameUpdate::Unchanged);
+        }
+        if let Some(ring) = &self.ring {
+            ring.copy_shown(
+                context.encoder,
+                &context.texture.texture,
+                context.planes.map(|planes| &planes.chroma),
+            );
+        }
+        Ok(FrameUpdate::NewFrame)
+    }
+}
@@ -0,0 +1,489 @@
+use std::{
+    ffi::c_void,
+    num::NonZeroUsize,
+    os::fd::IntoRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use nix::{
+    sys::mman::{mmap, munmap, MapFlags, ProtFlags},
+    unistd::close,
+};
+use wgpu::{Device, Instance, Queue};
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        shm::{self, ConnectionExt as _},
+        xproto::{AtomEnum, ConnectionExt as _, ImageFormat, Window},
+    },
+    rust_connection::RustConnection,
+};
+
+use crate::engine::texture::{Texture2D, Unbound};
+
+use super::{check_texture_size, FrameContext, FrameUpdate, Loader, StereoMode, TextureSource};
+
+// Interval between two captures, the X server copies whole frames so this bounds the CPU usage
+const CAPTURE_INTERVAL: Duration = Duration::from_micros(1_000_000 / 60);
+// Time a freshly started capture gets to grab its first frame before loading gives up
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Default)]
+struct X11Frame {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    sequence: u64,
+}
+
+// What to capture, either the whole screen or a window looked up by id or title
+#[derive(Clone, Debug)]
+pub enum X11Target {
+    Screen,
+    WindowId(Window),
+    WindowTitle(String),
+}
+
+impl From<&str> for X11Target {
+    fn from(value: &str) -> Self {
+        let window_id = match value.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        };
+        match (value, window_id) {
+            ("root" | "screen", _) => X11Target::Screen,
+            (_, Some(window_id)) => X11Target::WindowId(window_id),
+            _ => X11Target::WindowTitle(value.to_owned()),
+        }
+    }
+}
+
+// Captures an X11 window or screen through MIT-SHM on a background thread
+pub struct X11Loader {
+    target: X11Target,
+    stereo_mode: StereoMode,
+    frame: Arc<Mutex<X11Frame>>,
+    running: Arc<AtomicBool>,
+    capture_thread: Option<JoinHandle<()>>,
+    loaded_frame: Option<X11Frame>,
+}
+
+impl X11Loader {
+    pub fn new(target: X11Target, stereo_mode: StereoMode) -> Self {
+        Self {
+            target,
+            stereo_mode,
+            frame: Arc::new(Mutex::new(X11Frame::default())),
+            running: Arc::new(AtomicBool::new(false)),
+            capture_thread: None,
+            loaded_frame: None,
+        }
+    }
+
+    fn ensure_capturing(&mut self) -> anyhow::Result<()> {
+        if self.running.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.stop_capturing();
+
+        let (connection, screen_num) = x11rb::connect(None).context("Cannot connect to X11")?;
+        connection
+            .shm_query_version()?
+            .reply()
+            .context("The X server doesn't support MIT-SHM")?;
+        let root = connection.setup().roots[screen_num].root;
+        let window = match &self.target {
+            X11Target::Screen => root,
+            X11Target::WindowId(window) => *window,
+            X11Target::WindowTitle(title) => find_window(&connection, root, title)?
+                .with_context(|| format!("No window titled {}", title))?,
+        };
+        log::info!("Capturing X11 window {:#x}", window);
+
+        let frame = self.frame.clone();
+        let running = self.running.clone();
+        running.store(true, Ordering::Relaxed);
+        self.capture_thread = Some(
+            std::thread::Builder::new()
+                .name("X11 Capture".to_owned())
+                .spawn(move || {
+                    if let Err(err) = capture(&connection, window, &frame, &running) {
+                        log::error!("X11 capture failed: {}", err);
+                    }
+                    running.store(false, Ordering::Relaxed);
+                })?,
+        );
+        Ok(())
+    }
+
+    fn stop_capturing(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(capture_thread) = self.capture_thread.take() {
+            let _ = capture_thread.join();
+        }
+    }
+
+    // Waits until the capture thread grabbed a frame, false if it stopped or timed out
+    fn wait_first_frame(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.running.load(Ordering::Relaxed) && Instant::now() < deadline {
+            if matches!(self.frame.lock(), Ok(frame) if !frame.pixels.is_empty()) {
+                return true;
+            }
+            std::thread::sleep(CAPTURE_INTERVAL / 4);
+        }
+        false
+    }
+
+    fn upload_frame(frame: &X11Frame, queue: &Queue, texture: &wgpu::Texture) {
+        queue.write_texture(
+            texture.as_image_copy(),
+            &frame.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * frame.width),
+                rows_per_image: Some(frame.height),
+            },
+            texture.size(),
+        );
+    }
+}
+
+fn window_title(connection: &RustConnection, window: Window) -> anyhow::Result<String> {
+    let net_wm_name = connection
+        .intern_atom(false, b"_NET_WM_NAME")?
+        .reply()?
+        .atom;
+    let utf8_string = connection.intern_atom(false, b"UTF8_STRING")?.reply()?.atom;
+    let title = connection
+        .get_property(false, window, net_wm_name, utf8_string, 0, 1024)?
+        .reply()?
+        .value;
+    if !title.is_empty() {
+        return Ok(String::from_utf8_lossy(&title).into_owned());
+    }
+    let title = connection
+        .get_property(false, window, AtomEnum::WM_NAME, AtomEnum::STRING, 0, 1024)?
+        .reply()?
+        .value;
+    Ok(String::from_utf8_lossy(&title).into_owned())
+}
+
+// Looks through the windows managed by the window manager, falling back to the root children
+fn find_window(
+    connection: &RustConnection,
+    root: Window,
+    title: &str,
+) -> anyhow::Result<Option<Window>> {
+    let client_list = connection
+        .intern_atom(false, b"_NET_CLIENT_LIST")?
+        .reply()?
+        .atom;
+    let mut windows: Vec<Window> = connection
+        .get_property(false, root, client_list, AtomEnum::WINDOW, 0, u32::MAX)?
+        .reply()?
+        .value32()
+        .map(|windows| windows.collect())
+        .unwrap_or_default();
+    windows.extend(connection.query_tree(root)?.reply()?.children);
+
+    let title = title.to_lowercase();
+    for window in windows {
+        if window_title(connection, window)?
+            .to_lowercase()
+            .contains(&title)
+        {
+            return Ok(Some(window));
+        }
+    }
+    Ok(None)
+}
+
+struct ShmSegment {
+    id: shm::Seg,
+    address: *mut c_void,
+    len: usize,
+}
+
+impl ShmSegment {
+    fn create(connection: &RustConnection, len: usize) -> anyhow::Result<Self> {
+        let id = connection.generate_id()?;
+        let reply = connection
+            .shm_create_segment(id, len as u32, false)?
+            .reply()?;
+        let fd = reply.shm_fd.into_raw_fd();
+        let address = unsafe {
+            mmap(
+                None,
+                NonZeroUsize::new(len).context("Empty capture size")?,
+                ProtFlags::PROT_READ,
+                MapFlags::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        let _ = close(fd);
+        match address {
+            Ok(address) => Ok(Self { id, address, len }),
+            Err(err) => {
+                connection.shm_detach(id)?;
+                Err(err.into())
+            }
+        }
+    }
+
+    fn pixels(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.address as *const u8, self.len) }
+    }
+
+    fn destroy(self, connection: &RustConnection) {
+        let _ = connection.shm_detach(self.id);
+        let _ = unsafe { munmap(self.address, self.len) };
+    }
+}
+
+fn capture(
+    connection: &RustConnection,
+    window: Window,
+    frame: &Mutex<X11Frame>,
+    running: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut segment = None;
+    let result = capture_frames(connection, window, frame, running, &mut segment);
+    if let Some(segment) = segment {
+        segment.destroy(connection);
+    }
+    result
+}
+
+fn capture_frames(
+    connection: &RustConnection,
+    window: Window,
+    frame: &Mutex<X11Frame>,
+    running: &AtomicBool,
+    segment: &mut Option<ShmSegment>,
+) -> anyhow::Result<()> {
+    let mut size = (0, 0);
+    while running.load(Ordering::Relaxed) {
+        let geometry = connection.get_geometry(window)?.reply()?;
+        if geometry.depth != 24 && geometry.depth != 32 {
+            bail!("Unsupported window depth {}", geometry.depth);
+        }
+
+        // Resized windows get a new segment, the loader reloads through is_invalid
+        let new_size = (geometry.width as u32, geometry.height as u32);
+        if new_size != size || segment.is_none() {
+            if let Some(segment) = segment.take() {
+                segment.destroy(connection);
+            }
+            *segment = Some(ShmSegment::create(
+                connection,
+                (new_size.0 * new_size.1 * 4) as usize,
+            )?);
+            size = new_size;
+        }
+        let segment = segment.as_ref().context("Missing capture segment")?;
+
+        connection
+            .shm_get_image(
+                window,
+                0,
+                0,
+                geometry.width,
+                geometry.height,
+                !0,
+                ImageFormat::Z_PIXMAP.into(),
+                segment.id,
+                0,
+            )?
+            .reply()?;
+
+        if let Ok(mut frame) = frame.lock() {
+            frame.width = size.0;
+            frame.height = size.1;
+            frame.pixels.clear();
+            frame.pixels.extend_from_slice(segment.pixels());
+            // The padding byte of 24 bit visuals is undefined
+            for pixel in frame.pixels.chunks_exact_mut(4) {
+                pixel[3] = 255;
+            }
+            frame.sequence = frame.sequence.wrapping_add(1);
+        }
+        std::thread::sleep(CAPTURE_INTERVAL);
+    }
+    Ok(())
+}
+
+impl Loader for X11Loader {
+    fn load(
+        &mut self,
+        _instance: &Instance,
+        device: &Device,
+        queue: &Queue,
+    ) -> anyhow::Result<TextureSource> {
+        self.loaded_frame = None;
+        self.ensure_capturing()?;
+        if !self.wait_first_frame(FIRST_FRAME_TIMEOUT) {
+            bail!("No X11 frame captured yet");
+        }
+
+        let frame = self
+            .frame
+            .lock()
+            .map_err(|_| anyhow::anyhow!("X11 frame lock poisoned"))?;
+        check_texture_size(device, frame.width, frame.height)?;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("X11Capture"),
+            size: wgpu::Extent3d {
+                width: frame.width,
+                height: frame.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        Self::upload_frame(&frame, queue, &texture);
+
+        let (width, height) = (frame.width, frame.height);
+        self.loaded_frame = Some(X11Frame {
+            width,
+            height,
+            pixels: vec![],
+            sequence: frame.sequence,
+        });
+
+        Ok(TextureSource {
+            texture: Texture2D::<Unbound>::from_wgpu(device, texture),
+            width,
+            height,
+            stereo_mode: self.stereo_mode,
+            planes: None,
+        })
+    }
+
+    fn is_invalid(&self) -> bool {
+        if !self.running.load(Ordering::Relaxed) {
+            return true;
+        }
+        match (&self.loaded_frame, self.frame.lock()) {
+            (Some(loaded_frame), Ok(frame)) => {
+                frame.width != loaded_frame.width || frame.height != loaded_frame.height
+            }
+            _ => true,
+        }
+    }
+
+    fn update(&mut self, context: &mut FrameContext) -> anyhow::Result<FrameUpdate> {
+        if let Some(loaded_frame) = &mut self.loaded_frame {
+            let Ok(frame) = self.frame.lock() else {
+                return Ok(FrameUpdate::Unchanged);
+            };
+            if frame.sequence != loaded_frame.sequence
+                && frame.width == loaded_frame.width
+                && frame.height == loaded_frame.height
+            {
+                Self::upload_frame(&frame, context.queue, &context.texture.texture);
+                loaded_frame.sequence = frame.sequence;
+                return Ok(FrameUpdate::NewFrame);
+            }
+        }
+        Ok(FrameUpdate::Unchanged)
+    }
+}
+
+impl Drop for X11Loader {
+    fn drop(&mut self) {
+        self.stop_capturing();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x11rb::{
+        connection::Connection,
+        protocol::xproto::{AtomEnum, ConnectionExt as _, CreateWindowAux, PropMode, WindowClass},
+        wrapper::ConnectionExt as _,
+    };
+
+    use super::{X11Loader, X11Target, FIRST_FRAME_TIMEOUT};
+    use crate::loaders::StereoMode;
+
+    // Captures without a GPU, checking what load would upload
+    fn capture_first_frame(target: X11Target) -> (u32, u32, Vec<u8>) {
+        let mut loader = X11Loader::new(target, StereoMode::Mono);
+        loader.ensure_capturing().unwrap();
+        assert!(loader.wait_first_frame(FIRST_FRAME_TIMEOUT * 4));
+        let frame = loader.frame.lock().unwrap();
+        (frame.width, frame.height, frame.pixels.clone())
+    }
+
+    #[test]
+    #[ignore = "needs an X server, run with xvfb-run cargo test -- --ignored x11"]
+    fn x11_screen_is_captured() {
+        let (connection, screen_num) = x11rb::connect(None).unwrap();
+        let screen = &connection.setup().roots[screen_num];
+        let size = (
+            screen.width_in_pixels as u32,
+            screen.height_in_pixels as u32,
+        );
+
+        let (width, height, pixels) = capture_first_frame(X11Target::Screen);
+        assert_eq!((width, height), size);
+        assert_eq!(pixels.len(), (width * height * 4) as usize);
+        assert!(pixels.chunks_exact(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    #[ignore = "needs an X server, run with xvfb-run cargo test -- --ignored x11"]
+    fn x11_window_is_found_by_title() {
+        let (connection, screen_num) = x11rb::connect(None).unwrap();
+        let screen = &connection.setup().roots[screen_num];
+        let window = connection.generate_id().unwrap();
+        connection
+            .create_window(
+                screen.root_depth,
+                window,
+                screen.root,
+                0,
+                0,
+                320,
+                240,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                screen.root_visual,
+                &CreateWindowAux::new().background_pixel(screen.white_pixel),
+            )
+            .unwrap();
+        connection
+            .change_property8(
+                PropMode::REPLACE,
+                window,
+                AtomEnum::WM_NAME,
+                AtomEnum::STRING,
+                b"VRScreenCap Capture Test",
+            )
+            .unwrap();
+        connection.map_window(window).unwrap();
+        // Round trip so the window is mapped before the capture looks for it
+        connection.get_input_focus().unwrap().reply().unwrap();
+
+        let (width, height, pixels) = capture_first_frame(X11Target::from("capture test"));
+        assert_eq!((width, height), (320, 240));
+        assert!(pixels
+            .chunks_exact(4)
+            .all(|pixel| pixel == [255, 255, 255, 255]));
+    }
+}
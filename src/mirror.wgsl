@@ -0,0 +1,61 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+struct MirrorParams {
+    mode: u32,
+};
+
+const MIRROR_MODE_LEFT: u32 = 0u;
+const MIRROR_MODE_RIGHT: u32 = 1u;
+const MIRROR_MODE_BOTH: u32 = 2u;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32
+) -> VertexOutput {
+    var out: VertexOutput;
+    let x = i32(vertex_index) / 2;
+    let y = i32(vertex_index) & 1;
+    let tc = vec2<f32>(
+        f32(x) * 2.0,
+        f32(y) * 2.0
+    );
+    out.clip_position = vec4<f32>(
+        tc.x * 2.0 - 1.0,
+        1.0 - tc.y * 2.0,
+        0.0, 1.0
+    );
+    out.tex_coords = tc;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_layers: texture_2d_array<f32>;
+@group(0) @binding(1)
+var s_layers: sampler;
+@group(0) @binding(2)
+var<uniform> mirror_params: MirrorParams;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    if mirror_params.mode == MIRROR_MODE_LEFT {
+        return textureSampleLevel(t_layers, s_layers, uv, 0, 0.0);
+    }
+    if mirror_params.mode == MIRROR_MODE_RIGHT {
+        return textureSampleLevel(t_layers, s_layers, uv, 1, 0.0);
+    }
+    if mirror_params.mode == MIRROR_MODE_BOTH {
+        return mix(
+            textureSampleLevel(t_layers, s_layers, uv, 0, 0.0),
+            textureSampleLevel(t_layers, s_layers, uv, 1, 0.0),
+            0.5
+        );
+    }
+    // Side by side, left eye on the left half
+    let eye = select(0, 1, uv.x >= 0.5);
+    let eye_uv = vec2<f32>(fract(uv.x * 2.0), uv.y);
+    return textureSampleLevel(t_layers, s_layers, eye_uv, eye, 0.0);
+}
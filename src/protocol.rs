@@ -0,0 +1,5 @@
+#[cfg(target_os = "linux")]
+pub mod dmabuf;
+pub mod net;
+#[cfg(target_os = "linux")]
+pub mod shm;
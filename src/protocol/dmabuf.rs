@@ -0,0 +1,112 @@
+use std::{
+    io::{IoSlice, IoSliceMut},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+};
+
+use anyhow::{bail, Context};
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+
+pub const DMABUF_MAGIC: u32 = u32::from_le_bytes(*b"VRDB");
+pub const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+// Sent over the Unix socket together with the DMA-BUF file descriptor (SCM_RIGHTS)
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DmaBufFrameDescriptor {
+    pub magic: u32,
+    pub width: u32,
+    pub height: u32,
+    pub fourcc: u32,
+    pub stride: u32,
+    pub offset: u32,
+    pub modifier: u64,
+    pub stereo_mode: u32,
+    pub _reserved: u32,
+}
+
+impl DmaBufFrameDescriptor {
+    pub fn new(
+        width: u32,
+        height: u32,
+        fourcc: u32,
+        stride: u32,
+        offset: u32,
+        modifier: u64,
+        stereo_mode: u32,
+    ) -> Self {
+        Self {
+            magic: DMABUF_MAGIC,
+            width,
+            height,
+            fourcc,
+            stride,
+            offset,
+            modifier,
+            stereo_mode,
+            _reserved: 0,
+        }
+    }
+}
+
+pub fn send_dmabuf(
+    stream: &UnixStream,
+    descriptor: &DmaBufFrameDescriptor,
+    dmabuf_fd: RawFd,
+) -> anyhow::Result<()> {
+    let fds = [dmabuf_fd];
+    sendmsg::<()>(
+        stream.as_raw_fd(),
+        &[IoSlice::new(bytemuck::bytes_of(descriptor))],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )?;
+    Ok(())
+}
+
+// Blocks until a descriptor is received, returns None when the sender closed the connection
+pub fn receive_dmabuf(
+    stream: &UnixStream,
+) -> anyhow::Result<Option<(DmaBufFrameDescriptor, OwnedFd)>> {
+    let mut descriptor = DmaBufFrameDescriptor::new(0, 0, 0, 0, 0, DRM_FORMAT_MOD_INVALID, 0);
+    let mut cmsg_buffer = nix::cmsg_space!([RawFd; 1]);
+    let (bytes, fds) = {
+        let mut iov = [IoSliceMut::new(bytemuck::bytes_of_mut(&mut descriptor))];
+        let message = recvmsg::<()>(
+            stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg_buffer),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+        let fds = message
+            .cmsgs()
+            .filter_map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmRights(fds) => Some(fds),
+                _ => None,
+            })
+            .flatten()
+            .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+            .collect::<Vec<_>>();
+        (message.bytes, fds)
+    };
+
+    if bytes == 0 {
+        return Ok(None);
+    }
+    if bytes != std::mem::size_of::<DmaBufFrameDescriptor>() {
+        bail!("Received a truncated DMA-BUF descriptor ({} bytes)", bytes);
+    }
+    if descriptor.magic != DMABUF_MAGIC {
+        bail!("Invalid DMA-BUF descriptor magic {:#x}", descriptor.magic);
+    }
+
+    let fd = fds
+        .into_iter()
+        .next()
+        .context("DMA-BUF descriptor received without a file descriptor")?;
+    Ok(Some((descriptor, fd)))
+}
@@ -0,0 +1,226 @@
+use std::io::{Read, Write};
+
+use anyhow::bail;
+
+pub const NET_MAGIC: u32 = u32::from_le_bytes(*b"VRSN");
+pub const NET_VERSION: u32 = 1;
+pub const DEFAULT_NET_PORT: u16 = 9930;
+pub const NET_HEADER_SIZE: usize = 28;
+// Upper bound on payloads so a corrupted header can't make the receiver allocate gigabytes
+pub const NET_MAX_PAYLOAD_SIZE: u32 = 256 * 1024 * 1024;
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetCodec {
+    // Tightly packed RGBA8 rows
+    RawRgba = 1,
+    Jpeg = 2,
+}
+
+impl TryFrom<u32> for NetCodec {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u32) -> anyhow::Result<Self> {
+        Ok(match value {
+            1 => NetCodec::RawRgba,
+            2 => NetCodec::Jpeg,
+            _ => bail!("Unknown network frame codec {}", value),
+        })
+    }
+}
+
+// Sent in front of every frame, all fields are little endian u32 and the payload follows right after
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NetFrameHeader {
+    pub width: u32,
+    pub height: u32,
+    pub stereo_mode: u32,
+    pub codec: NetCodec,
+    pub payload_size: u32,
+}
+
+impl NetFrameHeader {
+    pub fn encode(&self) -> [u8; NET_HEADER_SIZE] {
+        let mut bytes = [0; NET_HEADER_SIZE];
+        let fields = [
+            NET_MAGIC,
+            NET_VERSION,
+            self.width,
+            self.height,
+            self.stereo_mode,
+            self.codec as u32,
+            self.payload_size,
+        ];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; NET_HEADER_SIZE]) -> anyhow::Result<Self> {
+        let field = |idx: usize| {
+            u32::from_le_bytes([
+                bytes[idx * 4],
+                bytes[idx * 4 + 1],
+                bytes[idx * 4 + 2],
+                bytes[idx * 4 + 3],
+            ])
+        };
+        if field(0) != NET_MAGIC {
+            bail!("Invalid network frame magic {:#x}", field(0));
+        }
+        if field(1) != NET_VERSION {
+            bail!("Unsupported network protocol version {}", field(1));
+        }
+
+        let header = Self {
+            width: field(2),
+            height: field(3),
+            stereo_mode: field(4),
+            codec: NetCodec::try_from(field(5))?,
+            payload_size: field(6),
+        };
+        if header.width == 0 || header.height == 0 {
+            bail!("Invalid frame size {}x{}", header.width, header.height);
+        }
+        if header.payload_size > NET_MAX_PAYLOAD_SIZE {
+            bail!("Frame payload of {} bytes is too big", header.payload_size);
+        }
+        if header.codec == NetCodec::RawRgba
+            && header.payload_size as u64 != header.width as u64 * header.height as u64 * 4
+        {
+            bail!(
+                "Raw frame payload of {} bytes doesn't match {}x{}",
+                header.payload_size,
+                header.width,
+                header.height
+            );
+        }
+        Ok(header)
+    }
+}
+
+pub fn write_frame(
+    writer: &mut impl Write,
+    header: &NetFrameHeader,
+    payload: &[u8],
+) -> anyhow::Result<()> {
+    if header.payload_size as usize != payload.len() {
+        bail!("Header payload size doesn't match the payload");
+    }
+    writer.write_all(&header.encode())?;
+    writer.write_all(payload)?;
+    Ok(())
+}
+
+// Returns None once the sender closes the connection between two frames
+pub fn read_frame(reader: &mut impl Read) -> anyhow::Result<Option<(NetFrameHeader, Vec<u8>)>> {
+    let mut header_bytes = [0; NET_HEADER_SIZE];
+    match reader.read_exact(&mut header_bytes) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let header = NetFrameHeader::decode(&header_bytes)?;
+    let mut payload = vec![0; header.payload_size as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((header, payload)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{
+        read_frame, write_frame, NetCodec, NetFrameHeader, NET_HEADER_SIZE, NET_MAX_PAYLOAD_SIZE,
+        NET_VERSION,
+    };
+
+    fn header() -> NetFrameHeader {
+        NetFrameHeader {
+            width: 4,
+            height: 2,
+            stereo_mode: 0,
+            codec: NetCodec::RawRgba,
+            payload_size: 4 * 2 * 4,
+        }
+    }
+
+    // Overwrites one of the little endian u32 fields of an encoded header
+    fn patched(field: usize, value: u32) -> [u8; NET_HEADER_SIZE] {
+        let mut bytes = header().encode();
+        bytes[field * 4..field * 4 + 4].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let header = header();
+        assert_eq!(NetFrameHeader::decode(&header.encode()).unwrap(), header);
+    }
+
+    #[test]
+    fn frame_round_trips() {
+        let payload: Vec<u8> = (0..32).collect();
+        let mut stream = vec![];
+        write_frame(&mut stream, &header(), &payload).unwrap();
+        assert_eq!(stream.len(), NET_HEADER_SIZE + payload.len());
+
+        let mut reader = Cursor::new(stream);
+        let (read_header, read_payload) = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(read_header, header());
+        assert_eq!(read_payload, payload);
+        // A connection closed between two frames is not an error
+        assert!(read_frame(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn mismatched_payload_is_not_written() {
+        assert!(write_frame(&mut vec![], &header(), &[0; 4]).is_err());
+    }
+
+    #[test]
+    fn truncated_payload_is_rejected() {
+        let mut stream = header().encode().to_vec();
+        stream.extend_from_slice(&[0; 8]);
+        assert!(read_frame(&mut Cursor::new(stream)).is_err());
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        assert!(NetFrameHeader::decode(&patched(0, 0)).is_err());
+    }
+
+    #[test]
+    fn bad_version_is_rejected() {
+        assert!(NetFrameHeader::decode(&patched(1, NET_VERSION + 1)).is_err());
+    }
+
+    #[test]
+    fn empty_frame_is_rejected() {
+        assert!(NetFrameHeader::decode(&patched(2, 0)).is_err());
+        assert!(NetFrameHeader::decode(&patched(3, 0)).is_err());
+    }
+
+    #[test]
+    fn unknown_codec_is_rejected() {
+        assert!(NetFrameHeader::decode(&patched(5, 3)).is_err());
+    }
+
+    #[test]
+    fn oversized_payload_is_rejected() {
+        let mut bytes = patched(5, NetCodec::Jpeg as u32);
+        bytes[24..28].copy_from_slice(&(NET_MAX_PAYLOAD_SIZE + 1).to_le_bytes());
+        assert!(NetFrameHeader::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn raw_payload_must_match_the_size() {
+        assert!(NetFrameHeader::decode(&patched(6, 4 * 2 * 4 - 1)).is_err());
+        // Compressed payloads can have any size
+        let mut bytes = patched(5, NetCodec::Jpeg as u32);
+        bytes[24..28].copy_from_slice(&100u32.to_le_bytes());
+        assert!(NetFrameHeader::decode(&bytes).is_ok());
+    }
+}
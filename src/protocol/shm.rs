@@ -0,0 +1,277 @@
+use std::{ffi::c_void, num::NonZeroUsize, os::fd::RawFd};
+
+use anyhow::{bail, Context};
+use nix::{
+    fcntl::OFlag,
+    sys::{
+        mman::{mmap, munmap, shm_open, shm_unlink, MapFlags, ProtFlags},
+        stat::{fstat, Mode},
+    },
+    unistd::{close, ftruncate},
+};
+
+pub const SHM_MAGIC: u32 = u32::from_le_bytes(*b"VRSC");
+pub const SHM_VERSION: u32 = 1;
+pub const DEFAULT_SHM_NAME: &str = "/vr-screen-cap";
+pub const SHM_HEADER_SIZE: usize = std::mem::size_of::<ShmFrameHeader>();
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShmPixelFormat {
+    Rgba8Unorm = 1,
+    Bgra8Unorm = 2,
+    Rgba8UnormSrgb = 3,
+    Bgra8UnormSrgb = 4,
+}
+
+impl TryFrom<u32> for ShmPixelFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u32) -> anyhow::Result<Self> {
+        Ok(match value {
+            1 => ShmPixelFormat::Rgba8Unorm,
+            2 => ShmPixelFormat::Bgra8Unorm,
+            3 => ShmPixelFormat::Rgba8UnormSrgb,
+            4 => ShmPixelFormat::Bgra8UnormSrgb,
+            _ => bail!("Unknown shared memory pixel format {}", value),
+        })
+    }
+}
+
+// Header at the start of the segment, the pixels follow right after it.
+// Producers must bump frame_counter only after the whole frame has been written.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShmFrameHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub width: u32,
+    pub height: u32,
+    pub format: u32,
+    pub stride: u32,
+    pub stereo_mode: u32,
+    pub _reserved0: u32,
+    pub frame_counter: u64,
+    pub _reserved1: [u32; 6],
+}
+
+impl ShmFrameHeader {
+    pub fn new(width: u32, height: u32, format: ShmPixelFormat, stereo_mode: u32) -> Self {
+        Self {
+            magic: SHM_MAGIC,
+            version: SHM_VERSION,
+            width,
+            height,
+            format: format as u32,
+            stride: width * 4,
+            stereo_mode,
+            _reserved0: 0,
+            frame_counter: 0,
+            _reserved1: [0; 6],
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.magic != SHM_MAGIC {
+            bail!("Invalid shared memory magic {:#x}", self.magic);
+        }
+        if self.version != SHM_VERSION {
+            bail!(
+                "Unsupported shared memory protocol version {}",
+                self.version
+            );
+        }
+        // Computed in 64 bits, the header comes from an untrusted producer
+        if self.width == 0 || self.height == 0 || (self.stride as u64) < self.width as u64 * 4 {
+            bail!(
+                "Invalid shared memory frame {}x{} with stride {}",
+                self.width,
+                self.height,
+                self.stride
+            );
+        }
+        ShmPixelFormat::try_from(self.format)?;
+        Ok(())
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.stride as usize * self.height as usize
+    }
+
+    // True when a frame with this header needs a different texture than the other one
+    pub fn layout_changed(&self, other: &ShmFrameHeader) -> bool {
+        self.width != other.width
+            || self.height != other.height
+            || self.format != other.format
+            || self.stride != other.stride
+            || self.stereo_mode != other.stereo_mode
+    }
+}
+
+pub struct SharedMemory {
+    name: String,
+    fd: RawFd,
+    address: *mut c_void,
+    len: usize,
+    owned: bool,
+}
+
+impl SharedMemory {
+    pub fn create(name: &str, len: usize) -> anyhow::Result<SharedMemory> {
+        let fd = shm_open(
+            name,
+            OFlag::O_CREAT | OFlag::O_RDWR,
+            Mode::S_IRUSR | Mode::S_IWUSR,
+        )?;
+        ftruncate(fd, len as i64)?;
+        Self::map(
+            name,
+            fd,
+            len,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            true,
+        )
+    }
+
+    pub fn open(name: &str) -> anyhow::Result<SharedMemory> {
+        let fd = shm_open(name, OFlag::O_RDONLY, Mode::empty())?;
+        let len = fstat(fd)?.st_size as usize;
+        Self::map(name, fd, len, ProtFlags::PROT_READ, false)
+    }
+
+    fn map(
+        name: &str,
+        fd: RawFd,
+        len: usize,
+        protection: ProtFlags,
+        owned: bool,
+    ) -> anyhow::Result<SharedMemory> {
+        let map_len = NonZeroUsize::new(len).context("Shared memory segment is empty");
+        let address = map_len.and_then(|map_len| {
+            Ok(unsafe { mmap(None, map_len, protection, MapFlags::MAP_SHARED, fd, 0)? })
+        });
+        match address {
+            Ok(address) => Ok(SharedMemory {
+                name: name.to_owned(),
+                fd,
+                address,
+                len,
+                owned,
+            }),
+            Err(err) => {
+                let _ = close(fd);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn read_header(&self) -> anyhow::Result<ShmFrameHeader> {
+        if self.len < SHM_HEADER_SIZE {
+            bail!("Shared memory segment is smaller than its header");
+        }
+        Ok(unsafe { std::ptr::read_volatile(self.address as *const ShmFrameHeader) })
+    }
+
+    pub fn write_header(&mut self, header: &ShmFrameHeader) {
+        std::sync::atomic::fence(std::sync::atomic::Ordering::Release);
+        unsafe { std::ptr::write_volatile(self.address as *mut ShmFrameHeader, *header) };
+    }
+
+    pub fn pixels(&self, header: &ShmFrameHeader) -> anyhow::Result<&[u8]> {
+        if SHM_HEADER_SIZE + header.frame_size() > self.len {
+            bail!("Shared memory segment is smaller than its frame");
+        }
+        Ok(unsafe {
+            std::slice::from_raw_parts(
+                (self.address as *const u8).add(SHM_HEADER_SIZE),
+                header.frame_size(),
+            )
+        })
+    }
+
+    pub fn pixels_mut(&mut self, header: &ShmFrameHeader) -> anyhow::Result<&mut [u8]> {
+        if SHM_HEADER_SIZE + header.frame_size() > self.len {
+            bail!("Shared memory segment is smaller than its frame");
+        }
+        Ok(unsafe {
+            std::slice::from_raw_parts_mut(
+                (self.address as *mut u8).add(SHM_HEADER_SIZE),
+                header.frame_size(),
+            )
+        })
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = munmap(self.address, self.len);
+        }
+        let _ = close(self.fd);
+        if self.owned {
+            let _ = shm_unlink(self.name.as_str());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ShmFrameHeader, ShmPixelFormat, SHM_VERSION};
+
+    fn header() -> ShmFrameHeader {
+        ShmFrameHeader::new(1920, 1080, ShmPixelFormat::Rgba8Unorm, 0)
+    }
+
+    #[test]
+    fn valid_header_is_accepted() {
+        assert!(header().validate().is_ok());
+        assert_eq!(header().frame_size(), 1920 * 4 * 1080);
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let header = ShmFrameHeader {
+            magic: 0,
+            ..header()
+        };
+        assert!(header.validate().is_err());
+    }
+
+    #[test]
+    fn bad_version_is_rejected() {
+        let header = ShmFrameHeader {
+            version: SHM_VERSION + 1,
+            ..header()
+        };
+        assert!(header.validate().is_err());
+    }
+
+    #[test]
+    fn short_stride_is_rejected() {
+        let header = ShmFrameHeader {
+            stride: 1920 * 4 - 1,
+            ..header()
+        };
+        assert!(header.validate().is_err());
+    }
+
+    #[test]
+    fn overflowing_width_is_rejected() {
+        // width * 4 wraps to a small value in 32 bits
+        let header = ShmFrameHeader {
+            width: 0x4000_0001,
+            stride: 4,
+            ..header()
+        };
+        assert!(header.validate().is_err());
+    }
+
+    #[test]
+    fn unknown_format_is_rejected() {
+        let header = ShmFrameHeader {
+            format: 0,
+            ..header()
+        };
+        assert!(header.validate().is_err());
+    }
+}
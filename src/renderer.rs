@@ -0,0 +1,674 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+
+#[cfg(target_os = "windows")]
+use crate::loaders::katanga_loader::KatangaLoaderContext;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+use crate::loaders::pipewire_loader::PipeWireLoader;
+#[cfg(all(target_os = "linux", feature = "v4l"))]
+use crate::loaders::v4l_loader::V4lLoader;
+#[cfg(feature = "video")]
+use crate::loaders::video_loader::VideoLoader;
+#[cfg(all(target_os = "linux", feature = "x11"))]
+use crate::loaders::x11_loader::{X11Loader, X11Target};
+#[cfg(target_os = "linux")]
+use crate::loaders::{dmabuf_loader::DmaBufLoader, shm_loader::ShmLoader};
+use crate::{
+    config::{AppConfig, TemporalBlurParams},
+    engine::{
+        camera::{Camera, CameraUniform},
+        pipelines::{SceneUniforms, ScreenPipelines, ScreenSources},
+        screen::Screen,
+        texture::{Bound, FrameSequentialBuffer, RoundRobinTextureBuffer, Texture2D, Unbound},
+        vr::SWAPCHAIN_COLOR_FORMAT,
+        yuv::YuvPlanes,
+        WgpuContext,
+    },
+    loaders::{
+        image_loader::ImageLoader,
+        net_loader::NetLoader,
+        registry::{self, LoaderRegistry},
+        slideshow_loader::SlideshowLoader,
+        test_pattern_loader::TestPatternLoader,
+        FrameContext, FrameUpdate, LoaderAction, StereoMode,
+    },
+};
+
+// Sources that produce no new frame for this long are reported as stalled
+const STALE_FRAME_THRESHOLD: Duration = Duration::from_secs(1);
+// Interval between attempts to find a source while none is available, or a higher priority one
+// than the active source
+const LOADER_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+// Tracks how fresh the frames shown from the current loader are
+#[derive(Default)]
+struct SourceFrameStats {
+    frame_counter: u64,
+    last_frame_time: Option<Instant>,
+    stalled: bool,
+}
+
+impl SourceFrameStats {
+    fn record(&mut self, update: FrameUpdate, time: Instant) {
+        if update.is_new_frame() {
+            self.frame_counter += 1;
+            if self.stalled {
+                log::info!("Source resumed after {:?}", self.staleness(time));
+                self.stalled = false;
+            }
+            self.last_frame_time = Some(time);
+        } else if !self.stalled && self.staleness(time) > STALE_FRAME_THRESHOLD {
+            log::warn!(
+                "Source stalled after {} frames, no new frame for {:?}",
+                self.frame_counter,
+                STALE_FRAME_THRESHOLD
+            );
+            self.stalled = true;
+        }
+    }
+
+    // Time since the displayed frame was produced
+    fn staleness(&self, time: Instant) -> Duration {
+        self.last_frame_time
+            .map(|last_frame_time| time.duration_since(last_frame_time))
+            .unwrap_or_default()
+    }
+}
+
+const AMBIENT_BLUR_BASE_RES: u32 = 16;
+pub const AMBIENT_BLUR_TEMPORAL_SAMPLES: u32 = 16;
+
+// Owns the pipelines, the screen source and the per-frame resources, frontends (OpenXR, headless
+// or a desktop window) only provide the views and a two layer target to render into
+pub struct Renderer {
+    pipelines: ScreenPipelines,
+    uniforms: SceneUniforms,
+    loaders: LoaderRegistry,
+    current_loader: Option<usize>,
+    screen_invalidated: bool,
+    last_loader_retry: Instant,
+    source_frame_stats: SourceFrameStats,
+    screen_params: AppConfig,
+    stereo_mode: StereoMode,
+    screen: Screen,
+    screen_texture: Texture2D<Bound>,
+    screen_planes: Option<YuvPlanes<Bound>>,
+    ambient_texture: RoundRobinTextureBuffer<Texture2D<Bound>, 3>,
+    frame_sequential_buffer: Option<FrameSequentialBuffer>,
+    cameras: Vec<Camera>,
+    camera_uniform: Vec<CameraUniform>,
+    temporal_blur_params: TemporalBlurParams,
+    jitter_frame: u32,
+}
+
+impl Renderer {
+    // Starts on a blank screen and tries to load the configured sources right away
+    pub fn new(wgpu_context: &WgpuContext, screen_params: AppConfig) -> anyhow::Result<Self> {
+        let pipelines = ScreenPipelines::new(&wgpu_context.device);
+
+        //Load blank texture
+        let screen_texture = Texture2D::<Unbound>::from_bytes(
+            &wgpu_context.device,
+            &wgpu_context.queue,
+            include_bytes!("../assets/blank_grey.png"),
+            "Blank",
+        )?
+        .bind_to_context(wgpu_context, &pipelines.texture_bind_group_layout);
+        let stereo_mode = StereoMode::Mono;
+        let aspect_ratio = 1.0;
+        let ambient_texture = get_ambient_texture(
+            &screen_texture,
+            aspect_ratio,
+            &stereo_mode,
+            wgpu_context,
+            &pipelines.texture_bind_group_layout,
+        )?;
+
+        let temporal_blur_params = TemporalBlurParams {
+            jitter: [0.0, 0.0],
+            scale: [1.1, 1.1],
+            resolution: [
+                ambient_texture.current().texture.width() as f32,
+                ambient_texture.current().texture.height() as f32,
+            ],
+            history_decay: 0.985,
+        };
+
+        let screen = Screen::new(
+            &wgpu_context.device,
+            -screen_params.distance,
+            screen_params.scale,
+            aspect_ratio,
+            screen_params.ambient,
+        );
+
+        let cameras = vec![Camera::default(), Camera::default()];
+        let camera_uniform = vec![CameraUniform::new(), CameraUniform::new()];
+
+        let uniforms = pipelines.create_uniforms(
+            &wgpu_context.device,
+            &camera_uniform,
+            screen_params.uniform(
+                stereo_mode,
+                aspect_ratio,
+                screen_texture.texture.width() * stereo_mode.width_multiplier(),
+                ambient_texture.current().texture.width() * stereo_mode.width_multiplier(),
+            ),
+            screen.entity.uniform(),
+            temporal_blur_params.uniform(),
+        );
+
+        let mut renderer = Self {
+            pipelines,
+            uniforms,
+            loaders: register_loaders(&screen_params),
+            current_loader: None,
+            screen_invalidated: true,
+            last_loader_retry: Instant::now(),
+            source_frame_stats: SourceFrameStats::default(),
+            screen_params,
+            stereo_mode,
+            screen,
+            screen_texture,
+            screen_planes: None,
+            ambient_texture,
+            frame_sequential_buffer: None,
+            cameras,
+            camera_uniform,
+            temporal_blur_params,
+            jitter_frame: 0,
+        };
+        renderer.update_source(wgpu_context)?;
+        Ok(renderer)
+    }
+
+    pub fn screen_params(&self) -> &AppConfig {
+        &self.screen_params
+    }
+
+    pub fn stereo_mode(&self) -> StereoMode {
+        self.stereo_mode
+    }
+
+    pub fn has_source(&self) -> bool {
+        self.current_loader.is_some()
+    }
+
+    // Takes new settings into account, the source is reloaded on the next update_source
+    pub fn apply_config(&mut self, screen_params: AppConfig) {
+        self.screen.change_scale(screen_params.scale);
+        self.screen.change_distance(-screen_params.distance);
+        self.screen.change_ambient_mode(screen_params.ambient);
+        self.loaders.set_priority(&screen_params.loader_priority);
+        self.screen_params = screen_params;
+        self.screen_invalidated = true;
+    }
+
+    // Reloads the source if it went away, or looks for one when none is shown
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        self.loaders.clear_errors();
+        if let Some(loader) = self.current_loader {
+            if self
+                .loaders
+                .get(loader)
+                .context("Error getting loader")?
+                .is_invalid()
+            {
+                log::info!("Reloading app...");
+                self.screen_invalidated = true;
+            }
+        } else {
+            self.screen_invalidated = true;
+        }
+
+        Ok(())
+    }
+
+    // None goes back to picking the first available loader
+    pub fn select_loader(&mut self, name: Option<&'static str>) {
+        self.loaders.force(name);
+        self.screen_invalidated = true;
+    }
+
+    pub fn handle_loader_action(&mut self, action: &LoaderAction) {
+        if let Some(loader) = self
+            .current_loader
+            .and_then(|idx| self.loaders.get_mut(idx))
+        {
+            loader.handle_action(action);
+        }
+    }
+
+    // Loads a new source when the current one was invalidated, retrying periodically while none
+    // is available or a higher priority one could take over, to be called before every frame
+    pub fn update_source(&mut self, wgpu_context: &WgpuContext) -> anyhow::Result<()> {
+        let time = Instant::now();
+        let retry = time.duration_since(self.last_loader_retry) > LOADER_RETRY_INTERVAL;
+
+        // Active loaders are checked for invalidation while rendering
+        let loaded = if self.screen_invalidated || (retry && self.current_loader.is_none()) {
+            self.screen_invalidated = false;
+            self.last_loader_retry = time;
+            match try_to_load_texture(&mut self.loaders, None, wgpu_context) {
+                Some(loaded) => loaded,
+                None => {
+                    // Nothing could be loaded, keep showing the last texture and retry periodically
+                    self.current_loader = None;
+                    return Ok(());
+                }
+            }
+        } else if retry {
+            self.last_loader_retry = time;
+            match try_to_load_texture(&mut self.loaders, self.current_loader, wgpu_context) {
+                Some(loaded) => loaded,
+                None => return Ok(()),
+            }
+        } else {
+            return Ok(());
+        };
+        let (texture, planes, aspect, mode, loader) = loaded;
+
+        let texture_bind_group_layout = &self.pipelines.texture_bind_group_layout;
+        self.screen_texture = texture.bind_to_context(wgpu_context, texture_bind_group_layout);
+        self.screen_planes = planes.map(|planes| {
+            planes.bind_to_context(
+                wgpu_context,
+                &self.screen_texture,
+                &self.pipelines.planar_texture_bind_group_layout,
+            )
+        });
+        self.ambient_texture = get_ambient_texture(
+            &self.screen_texture,
+            aspect,
+            &mode,
+            wgpu_context,
+            texture_bind_group_layout,
+        )?;
+        self.stereo_mode = mode;
+        self.current_loader = Some(loader);
+        self.frame_sequential_buffer = get_frame_sequential_buffer(
+            &self.screen_texture,
+            &self.stereo_mode,
+            wgpu_context,
+            texture_bind_group_layout,
+        )?;
+        self.screen.change_aspect_ratio(aspect);
+
+        wgpu_context.queue.write_buffer(
+            &self.uniforms.screen_model_matrix_buffer,
+            0,
+            bytemuck::cast_slice(&[self.screen.entity.uniform()]),
+        );
+
+        wgpu_context.queue.write_buffer(
+            &self.uniforms.screen_params_buffer,
+            0,
+            bytemuck::cast_slice(&[self.screen_params.uniform(
+                self.stereo_mode,
+                aspect,
+                self.screen_texture.texture.width() * self.stereo_mode.width_multiplier(),
+                self.ambient_texture.current().texture.width()
+                    * self.stereo_mode.width_multiplier(),
+            )]),
+        );
+        self.source_frame_stats = SourceFrameStats::default();
+        Ok(())
+    }
+
+    // Renders one frame for the given views into both layers of target
+    pub fn render(
+        &mut self,
+        wgpu_context: &WgpuContext,
+        views: &[openxr::View],
+        target: &wgpu::TextureView,
+        display_time: Duration,
+    ) -> anyhow::Result<()> {
+        let encoder = self.encode(wgpu_context, target, display_time);
+        update_eye_cameras(views, &mut self.cameras, &mut self.camera_uniform)?;
+        self.submit(wgpu_context, encoder);
+        Ok(())
+    }
+
+    // Like render, but from cameras placed by the caller, e.g. the mouse-look desktop preview
+    pub fn render_cameras(
+        &mut self,
+        wgpu_context: &WgpuContext,
+        cameras: &[Camera],
+        target: &wgpu::TextureView,
+        display_time: Duration,
+    ) -> anyhow::Result<()> {
+        let encoder = self.encode(wgpu_context, target, display_time);
+        for (camera_uniform, camera) in self.camera_uniform.iter_mut().zip(cameras) {
+            camera_uniform.update_view_proj(camera)?;
+        }
+        self.submit(wgpu_context, encoder);
+        Ok(())
+    }
+
+    // Records the frame, the camera uniforms are written right before submitting it
+    fn encode(
+        &mut self,
+        wgpu_context: &WgpuContext,
+        target: &wgpu::TextureView,
+        display_time: Duration,
+    ) -> wgpu::CommandEncoder {
+        #[cfg(feature = "profiling")]
+        profiling::scope!("Encode Render Pass");
+        log::trace!("Encode render pass");
+        let mut encoder =
+            wgpu_context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Render Encorder"),
+                });
+
+        let frame_update = match self
+            .current_loader
+            .and_then(|idx| self.loaders.get_mut(idx))
+        {
+            Some(loader) => {
+                let update = loader.update(&mut FrameContext {
+                    device: &wgpu_context.device,
+                    queue: &wgpu_context.queue,
+                    encoder: &mut encoder,
+                    texture: &self.screen_texture,
+                    planes: self.screen_planes.as_ref(),
+                    display_time,
+                });
+                if loader.is_invalid() {
+                    log::info!("Reloading app...");
+                    self.screen_invalidated = true;
+                }
+                update.unwrap_or_else(|err| {
+                    log::warn!("Failed to update screen texture: {}", err);
+                    FrameUpdate::Unchanged
+                })
+            }
+            None => FrameUpdate::Untracked,
+        };
+        self.source_frame_stats.record(frame_update, Instant::now());
+
+        // Frame sequential sources get the left and right eye bound separately
+        let (left_eye_texture, right_eye_texture) = match self.frame_sequential_buffer {
+            Some(ref mut frame_sequential_buffer) => {
+                // Each source frame holds a single eye, repeated frames must not flip the phase
+                if frame_update.is_new_frame() {
+                    frame_sequential_buffer.push(&mut encoder, &self.screen_texture);
+                }
+                (
+                    frame_sequential_buffer.eye(0, self.screen_params.invert_frame_phase),
+                    frame_sequential_buffer.eye(1, self.screen_params.invert_frame_phase),
+                )
+            }
+            None => (&self.screen_texture, &self.screen_texture),
+        };
+        if self.screen.ambient_enabled {
+            self.ambient_texture.next();
+            self.pipelines.encode_ambient_blur(
+                &mut encoder,
+                &self.screen_texture,
+                self.screen_planes.as_ref(),
+                &self.ambient_texture,
+                &self.uniforms,
+            );
+        }
+        self.pipelines.encode_scene(
+            &mut encoder,
+            target,
+            &self.screen,
+            &ScreenSources {
+                left_eye: left_eye_texture,
+                right_eye: right_eye_texture,
+                planes: self.screen_planes.as_ref(),
+            },
+            self.screen
+                .ambient_enabled
+                .then_some(self.ambient_texture.current()),
+            &self.uniforms,
+        );
+        encoder
+    }
+
+    fn submit(&mut self, wgpu_context: &WgpuContext, encoder: wgpu::CommandEncoder) {
+        log::trace!("Write views");
+        wgpu_context.queue.write_buffer(
+            &self.uniforms.camera_buffer,
+            0,
+            bytemuck::cast_slice(self.camera_uniform.as_slice()),
+        );
+
+        if self.screen.ambient_enabled {
+            log::trace!("Writing temporal blur uniforms");
+            let ambient_texture = &self.ambient_texture.current().texture;
+            let resolution = [
+                ambient_texture.width() as f32,
+                ambient_texture.height() as f32,
+            ];
+            self.jitter_frame = (self.jitter_frame + 1) % AMBIENT_BLUR_TEMPORAL_SAMPLES;
+            self.temporal_blur_params.jitter =
+                crate::engine::jitter::get_jitter(self.jitter_frame, &resolution);
+            self.temporal_blur_params.resolution = resolution;
+            wgpu_context.queue.write_buffer(
+                &self.uniforms.temporal_blur_params_buffer,
+                0,
+                bytemuck::cast_slice(&[self.temporal_blur_params.uniform()]),
+            );
+        }
+
+        #[cfg(feature = "profiling")]
+        profiling::scope!("Encode Submit");
+        log::trace!("Submit command buffer");
+        wgpu_context.queue.submit(std::iter::once(encoder.finish()));
+    }
+}
+
+fn register_loaders(config: &AppConfig) -> LoaderRegistry {
+    let mut loaders = LoaderRegistry::default();
+    #[cfg(target_os = "windows")]
+    loaders.register(
+        registry::KATANGA_LOADER,
+        Box::<KatangaLoaderContext>::default(),
+    );
+    #[cfg(target_os = "linux")]
+    loaders.register(
+        registry::SHM_LOADER,
+        Box::new(ShmLoader::new(&config.shm_name)),
+    );
+
+    #[cfg(target_os = "linux")]
+    if let Some(dmabuf_socket) = &config.dmabuf_socket {
+        loaders.register(
+            registry::DMABUF_LOADER,
+            Box::new(DmaBufLoader::new(dmabuf_socket)),
+        );
+    }
+
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    if let Some(pipewire_node) = config.pipewire_node {
+        loaders.register(
+            registry::PIPEWIRE_LOADER,
+            Box::new(PipeWireLoader::new(
+                pipewire_node,
+                config.pipewire_stereo_mode,
+            )),
+        );
+    }
+
+    #[cfg(all(target_os = "linux", feature = "x11"))]
+    if let Some(x11_window) = &config.x11_window {
+        loaders.register(
+            registry::X11_LOADER,
+            Box::new(X11Loader::new(
+                X11Target::from(x11_window.as_str()),
+                config.x11_stereo_mode,
+            )),
+        );
+    }
+
+    #[cfg(all(target_os = "linux", feature = "v4l"))]
+    if let Some(v4l_device) = &config.v4l_device {
+        loaders.register(
+            registry::V4L_LOADER,
+            Box::new(V4lLoader::new(v4l_device, config.v4l_stereo_mode)),
+        );
+    }
+
+    if let Some(net_listen) = &config.net_listen {
+        loaders.register(registry::NET_LOADER, Box::new(NetLoader::new(net_listen)));
+    }
+
+    #[cfg(feature = "video")]
+    if let Some(video_path) = &config.video_path {
+        loaders.register(
+            registry::VIDEO_LOADER,
+            Box::new(VideoLoader::new(video_path, config.video_stereo_mode)),
+        );
+    }
+
+    if let Some(image_path) = &config.image_path {
+        if std::path::Path::new(image_path).is_dir() {
+            let interval = match config.slideshow_interval {
+                0 => None,
+                seconds => Some(std::time::Duration::from_secs(seconds)),
+            };
+            loaders.register(
+                registry::IMAGE_LOADER,
+                Box::new(SlideshowLoader::new(
+                    image_path,
+                    config.image_stereo_mode,
+                    interval,
+                )),
+            );
+        } else {
+            loaders.register(
+                registry::IMAGE_LOADER,
+                Box::new(ImageLoader::new(image_path, config.image_stereo_mode)),
+            );
+        }
+    }
+
+    if let Some(test_pattern) = config.test_pattern {
+        loaders.register(
+            registry::TEST_PATTERN_LOADER,
+            Box::new(TestPatternLoader::new(
+                test_pattern,
+                config.test_pattern_stereo_mode,
+            )),
+        );
+    }
+    loaders.set_priority(&config.loader_priority);
+    loaders
+}
+
+fn update_eye_cameras(
+    views: &[openxr::View],
+    cameras: &mut [Camera],
+    camera_uniform: &mut [CameraUniform],
+) -> anyhow::Result<()> {
+    for (view_idx, view) in views.iter().enumerate() {
+        let eye = cameras
+            .get_mut(view_idx)
+            .context("Cannot borrow camera as mutable")?;
+        eye.entity.position.x = view.pose.position.x;
+        eye.entity.position.y = view.pose.position.y;
+        eye.entity.position.z = view.pose.position.z;
+        eye.entity.rotation.v.x = view.pose.orientation.x;
+        eye.entity.rotation.v.y = view.pose.orientation.y;
+        eye.entity.rotation.v.z = view.pose.orientation.z;
+        eye.entity.rotation.s = view.pose.orientation.w;
+        eye.entity.update_matrices(&[]);
+        eye.update_projection_from_tangents(view.fov);
+        let camera_uniform = camera_uniform
+            .get_mut(view_idx)
+            .context("Cannot borrow camera uniform buffer as mutable")?;
+        camera_uniform.update_view_proj(eye)?;
+    }
+    Ok(())
+}
+
+fn get_ambient_texture(
+    screen_texture: &Texture2D<Bound>,
+    aspect: f32,
+    stereo_mode: &StereoMode,
+    wgpu_context: &WgpuContext,
+    bind_group_layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<RoundRobinTextureBuffer<Texture2D<Bound>, 3>> {
+    let height_multiplier = stereo_mode.height_multiplier();
+    let width_multiplier = stereo_mode.width_multiplier();
+
+    let buffer = RoundRobinTextureBuffer::new(
+        (0..3)
+            .map(|idx| {
+                screen_texture
+                    .as_render_target_with_extent(
+                        format!("Ambient Texture {idx}").as_str(),
+                        wgpu::Extent3d {
+                            width: AMBIENT_BLUR_BASE_RES * width_multiplier,
+                            height: (AMBIENT_BLUR_BASE_RES as f32 / aspect) as u32
+                                * height_multiplier,
+                            depth_or_array_layers: screen_texture.texture.depth_or_array_layers(),
+                        },
+                        SWAPCHAIN_COLOR_FORMAT,
+                        &wgpu_context.device,
+                    )
+                    .bind_to_context(wgpu_context, bind_group_layout)
+            })
+            .collect::<Vec<_>>()
+            .try_into()
+            .ok()
+            .context("Cannot create ambient texture buffer")?,
+    );
+
+    Ok(buffer)
+}
+
+fn get_frame_sequential_buffer(
+    screen_texture: &Texture2D<Bound>,
+    stereo_mode: &StereoMode,
+    wgpu_context: &WgpuContext,
+    bind_group_layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<Option<FrameSequentialBuffer>> {
+    match stereo_mode {
+        StereoMode::FrameSequential => Ok(Some(FrameSequentialBuffer::new(
+            screen_texture,
+            wgpu_context,
+            bind_group_layout,
+        )?)),
+        _ => Ok(None),
+    }
+}
+
+type LoadedTexture = (
+    Texture2D<Unbound>,
+    Option<YuvPlanes<Unbound>>,
+    f32,
+    StereoMode,
+    usize,
+);
+
+// Only loaders ranked above the active one are tried when there is one
+fn try_to_load_texture(
+    loaders: &mut LoaderRegistry,
+    active: Option<usize>,
+    wgpu_context: &WgpuContext,
+) -> Option<LoadedTexture> {
+    let (instance, device, queue) = (
+        &wgpu_context.instance,
+        &wgpu_context.device,
+        &wgpu_context.queue,
+    );
+    let (loader_idx, tex_source) = match active {
+        Some(active) => loaders.load_preferred(active, instance, device, queue),
+        None => loaders.load(instance, device, queue),
+    }?;
+    Some((
+        tex_source.texture,
+        tex_source.planes,
+        tex_source
+            .stereo_mode
+            .aspect_ratio(tex_source.width, tex_source.height),
+        tex_source.stereo_mode,
+        loader_idx,
+    ))
+}
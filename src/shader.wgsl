@@ -16,8 +16,22 @@ struct ScreenParams {
     aspect_ratio: f32,
     screen_width: u32,
     ambient_width: u32,
+    // xy: offset, zw: scale of each eye's region in the texture
+    eye_uv_rects: array<vec4<f32>, 2>,
+    stereo_mode: u32,
+    anaglyph_mode: u32,
+    _padding0: u32,
+    _padding1: u32,
 };
 
+// Must match the StereoMode and AnaglyphMode enums
+const STEREO_MODE_ROW_INTERLACED: u32 = 5u;
+const STEREO_MODE_COLUMN_INTERLACED: u32 = 6u;
+const STEREO_MODE_CHECKERBOARD: u32 = 7u;
+const STEREO_MODE_FRAME_SEQUENTIAL: u32 = 8u;
+const ANAGLYPH_MODE_OFF: u32 = 0u;
+const ANAGLYPH_MODE_RED_CYAN: u32 = 1u;
+
 @group(1) @binding(0)
 var<uniform> camera: array<CameraUniform, 2>;
 @group(1) @binding(1)
@@ -55,15 +69,106 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
+// Right eye frame for frame sequential sources, same as t_diffuse otherwise
+@group(2) @binding(0)
+var t_secondary: texture_2d<f32>;
+@group(2) @binding(1)
+var s_secondary: sampler;
+// Planar sources bind their luma plane in place of t_diffuse, sampled through s_diffuse
+@group(0) @binding(2)
+var t_luma: texture_2d<f32>;
+@group(0) @binding(3)
+var t_chroma: texture_2d<f32>;
+@group(0) @binding(4)
+var<uniform> yuv_params: YuvParams;
+
+fn view_to_eye(view_index: i32) -> u32 {
+    return u32(abs(f32(view_index) - screen_params.eye_offset));
+}
+
+fn eye_to_stereo_uv(eye: u32, uv: vec2<f32>) -> vec2<f32> {
+    let eye_rect = screen_params.eye_uv_rects[eye];
+    let flipped_uv = abs(uv - vec2<f32>(screen_params.x_offset, screen_params.y_offset));
+    return flipped_uv * eye_rect.zw + eye_rect.xy;
+}
 
 fn uv_to_stereo_uv(view_index: i32, uv: vec2<f32>) -> vec2<f32> {
-    let x_offset = abs(f32(view_index) - screen_params.eye_offset) / 2.0;
-    return vec2<f32>(abs(uv.x - screen_params.x_offset) / 2.0 + x_offset, abs(uv.y - screen_params.y_offset));
+    return eye_to_stereo_uv(view_to_eye(view_index), uv);
+}
+
+// Snaps the UV to the center of the nearest texel belonging to the eye, even rows/columns are the left eye
+fn interlaced_uv(eye: u32, uv: vec2<f32>, size: vec2<f32>) -> vec2<f32> {
+    let eye_parity = f32(eye);
+    if screen_params.stereo_mode == STEREO_MODE_ROW_INTERLACED {
+        let row = floor(uv.y * size.y * 0.5) * 2.0 + eye_parity;
+        return vec2<f32>(uv.x, (row + 0.5) / size.y);
+    }
+    if screen_params.stereo_mode == STEREO_MODE_COLUMN_INTERLACED {
+        let column = floor(uv.x * size.x * 0.5) * 2.0 + eye_parity;
+        return vec2<f32>((column + 0.5) / size.x, uv.y);
+    }
+    if screen_params.stereo_mode == STEREO_MODE_CHECKERBOARD {
+        let row = floor(uv.y * size.y);
+        let column = floor(uv.x * size.x * 0.5) * 2.0 + abs(eye_parity - (row % 2.0));
+        return (vec2<f32>(column, row) + vec2<f32>(0.5)) / size;
+    }
+    return uv;
+}
+
+fn sample_eye(eye: u32, uv: vec2<f32>) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_diffuse));
+    let stereo_uv = interlaced_uv(eye, eye_to_stereo_uv(eye, uv), size);
+    let color = textureSample(t_diffuse, s_diffuse, stereo_uv);
+    if screen_params.stereo_mode == STEREO_MODE_FRAME_SEQUENTIAL {
+        return select(color, textureSample(t_secondary, s_secondary, stereo_uv), eye == 1u);
+    }
+    return color;
+}
+
+// Chroma planes are sampled at the luma UV, the sampler takes care of the subsampling.
+// Frame sequential sources are never planar (see TextureSource::validate), t_secondary isn't needed
+fn sample_eye_yuv(eye: u32, uv: vec2<f32>) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_luma));
+    let stereo_uv = interlaced_uv(eye, eye_to_stereo_uv(eye, uv), size);
+    let luma = textureSample(t_luma, s_diffuse, stereo_uv).r;
+    let chroma = textureSample(t_chroma, s_diffuse, stereo_uv).rg;
+    // RGBA sources are sampled from sRGB textures, match their linear output
+    return vec4<f32>(srgb_to_linear(yuv_to_rgb(vec3<f32>(luma, chroma), yuv_params)), 1.0);
+}
+
+fn anaglyph(left: vec3<f32>, right: vec3<f32>) -> vec4<f32> {
+    if screen_params.anaglyph_mode == ANAGLYPH_MODE_RED_CYAN {
+        return vec4<f32>(left.r, right.g, right.b, 1.0);
+    }
+
+    // Dubois least squares red/cyan projection
+    let red = dot(vec3<f32>(0.456100, 0.500484, 0.176381), left)
+        + dot(vec3<f32>(-0.0434706, -0.0879388, -0.00155529), right);
+    let green = dot(vec3<f32>(-0.0400822, -0.0378246, -0.0157589), left)
+        + dot(vec3<f32>(0.378476, 0.73364, -0.0184503), right);
+    let blue = dot(vec3<f32>(-0.0152161, -0.0205971, -0.00546856), left)
+        + dot(vec3<f32>(-0.0721527, -0.112961, 1.2264), right);
+    return vec4<f32>(clamp(vec3<f32>(red, green, blue), vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
 }
 
 @fragment
 fn fs_main(in: VertexOutput, @builtin(view_index) view_index: i32) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, uv_to_stereo_uv(view_index, in.tex_coords));
+    if screen_params.anaglyph_mode != ANAGLYPH_MODE_OFF {
+        let left = sample_eye(view_to_eye(0), in.tex_coords).rgb;
+        let right = sample_eye(view_to_eye(1), in.tex_coords).rgb;
+        return anaglyph(left, right);
+    }
+    return sample_eye(view_to_eye(view_index), in.tex_coords);
+}
+
+@fragment
+fn yuv_fs_main(in: VertexOutput, @builtin(view_index) view_index: i32) -> @location(0) vec4<f32> {
+    if screen_params.anaglyph_mode != ANAGLYPH_MODE_OFF {
+        let left = sample_eye_yuv(view_to_eye(0), in.tex_coords).rgb;
+        let right = sample_eye_yuv(view_to_eye(1), in.tex_coords).rgb;
+        return anaglyph(left, right);
+    }
+    return sample_eye_yuv(view_to_eye(view_index), in.tex_coords);
 }
 
 @vertex
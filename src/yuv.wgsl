@@ -0,0 +1,43 @@
+const YUV_FORMAT_YUYV: u32 = 1u;
+const YUV_FORMAT_NV12: u32 = 2u;
+const YUV_COLOR_SPACE_BT601: u32 = 0u;
+const YUV_COLOR_SPACE_BT709: u32 = 1u;
+
+struct YuvParams {
+    format: u32,
+    color_space: u32,
+    full_range: u32,
+    _padding: u32,
+};
+
+// Converts Y'CbCr to non linear R'G'B'
+fn yuv_to_rgb(yuv: vec3<f32>, params: YuvParams) -> vec3<f32> {
+    var y = yuv.x;
+    var uv = yuv.yz - vec2<f32>(0.5, 0.5);
+    if params.full_range == 0u {
+        y = (y - 16.0 / 255.0) * (255.0 / 219.0);
+        uv = uv * (255.0 / 224.0);
+    }
+
+    var rgb: vec3<f32>;
+    if params.color_space == YUV_COLOR_SPACE_BT709 {
+        rgb = vec3<f32>(
+            y + 1.5748 * uv.y,
+            y - 0.1873 * uv.x - 0.4681 * uv.y,
+            y + 1.8556 * uv.x
+        );
+    } else {
+        rgb = vec3<f32>(
+            y + 1.402 * uv.y,
+            y - 0.344136 * uv.x - 0.714136 * uv.y,
+            y + 1.772 * uv.x
+        );
+    }
+    return clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
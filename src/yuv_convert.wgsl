@@ -0,0 +1,43 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32
+) -> VertexOutput {
+    var out: VertexOutput;
+    let x = i32(vertex_index) / 2;
+    let y = i32(vertex_index) & 1;
+    out.clip_position = vec4<f32>(
+        f32(x) * 4.0 - 1.0,
+        1.0 - f32(y) * 4.0,
+        0.0, 1.0
+    );
+    return out;
+}
+
+@group(0) @binding(0)
+var t_plane0: texture_2d<f32>;
+@group(0) @binding(1)
+var t_plane1: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> yuv_params: YuvParams;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    var yuv: vec3<f32>;
+    if yuv_params.format == YUV_FORMAT_YUYV {
+        // Every texel packs two pixels as Y0 U Y1 V
+        let packed = textureLoad(t_plane0, vec2<i32>(pixel.x / 2, pixel.y), 0);
+        let y = select(packed.r, packed.b, (pixel.x & 1) == 1);
+        yuv = vec3<f32>(y, packed.g, packed.a);
+    } else {
+        let y = textureLoad(t_plane0, pixel, 0).r;
+        let uv = textureLoad(t_plane1, pixel / 2, 0).rg;
+        yuv = vec3<f32>(y, uv);
+    }
+    // The target is an sRGB texture, which expects linear values
+    return vec4<f32>(srgb_to_linear(yuv_to_rgb(yuv, yuv_params)), 1.0);
+}